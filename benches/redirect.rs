@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use gohome::{
+    RequestContext,
+    db::Db,
+    model::{Link, Passthrough, RedirectStatus},
+    render::Renderer,
+};
+use handlebars::Handlebars;
+
+const TEMPLATE: &str = "https://www.google.com/{{#if path}}search?q={{query_escape path}}{{/if}}";
+
+fn redirect(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let db = Db::in_memory().unwrap();
+    let renderer = Renderer::new("go", db.clone(), Handlebars::new());
+    let request = RequestContext::default();
+    runtime.block_on(async {
+        for (short, long) in [("nyt", "http://www.nytimes.com"), ("search", TEMPLATE)] {
+            let link = Link {
+                short: short.to_string(),
+                long: long.to_string(),
+                created: chrono::Utc::now(),
+                updated: chrono::Utc::now(),
+                status: RedirectStatus::default(),
+                max_age: None,
+                passthrough: Passthrough::default(),
+                dont_track: false,
+            };
+            db.link.save(&link).await.unwrap();
+            db.stats.save(short).await.unwrap();
+        }
+    });
+
+    let mut group = c.benchmark_group("redirect");
+    group.bench_function("plain", |b| {
+        b.to_async(&runtime)
+            .iter(|| renderer.get("nyt", "/nyt/section/arts", HashMap::new(), &request))
+    });
+    group.bench_function("cached template", |b| {
+        b.to_async(&runtime)
+            .iter(|| renderer.get("search", "/search/pangolins", HashMap::new(), &request))
+    });
+    // previewing an unsaved link compiles its template on every request, as redirects used to
+    group.bench_function("uncached template", |b| {
+        b.to_async(&runtime)
+            .iter(|| renderer.preview("search/pangolins", Some(TEMPLATE.to_string()), &request))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, redirect);
+criterion_main!(benches);
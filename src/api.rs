@@ -0,0 +1,867 @@
+use std::{collections::HashMap, convert::Infallible};
+
+use rand::Rng;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    model,
+    render::{self, Renderer},
+};
+
+// the number of links on a page when no limit is given, and the most that can be asked for
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+// API tokens start with this, so that they are easy to recognize in scripts and secret scanners
+const TOKEN_PREFIX: &str = "gohome_";
+const MAX_TOKEN_NAME_LENGTH: usize = 100;
+const MAX_TOKEN_DAYS: u32 = 3650;
+
+type Reply = Result<Box<dyn warp::Reply>, Infallible>;
+
+fn json<T: serde::Serialize>(body: &T, status: warp::http::StatusCode) -> Reply {
+    Ok(Box::new(warp::reply::with_status(warp::reply::json(body), status)))
+}
+
+/// Answers an API request with an error object.
+pub(crate) fn error(status: warp::http::StatusCode, error: &str, details: Vec<String>) -> Reply {
+    json(
+        &model::ErrorResponse {
+            error: error.to_string(),
+            details,
+        },
+        status,
+    )
+}
+
+// answers a request that may not use an endpoint, before it is handled
+fn denied(status: warp::http::StatusCode, error: String) -> Box<dyn warp::Reply> {
+    let reply = warp::reply::with_status(
+        warp::reply::json(&model::ErrorResponse {
+            error,
+            details: Vec::new(),
+        }),
+        status,
+    );
+    if status == warp::http::StatusCode::UNAUTHORIZED {
+        Box::new(warp::reply::with_header(
+            reply,
+            warp::http::header::WWW_AUTHENTICATE,
+            "Bearer",
+        ))
+    } else {
+        Box::new(reply)
+    }
+}
+
+fn internal_error(e: impl std::fmt::Display) -> Reply {
+    tracing::error!("{e}");
+    error(
+        warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        "internal error",
+        Vec::new(),
+    )
+}
+
+fn not_found(short: &str) -> Reply {
+    error(
+        warp::http::StatusCode::NOT_FOUND,
+        &format!("link go/{short} not found"),
+        Vec::new(),
+    )
+}
+
+// reads a JSON request body, or describes why it cannot be read
+fn parse<T: DeserializeOwned>(body: &[u8]) -> Result<T, Vec<String>> {
+    serde_json::from_slice(body).map_err(|e| vec![e.to_string()])
+}
+
+// returns what is wrong with a short name, which must be usable as the first segment of a path
+fn short_problems(short: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    if short.trim().is_empty() {
+        problems.push("short name must not be empty".to_string());
+    }
+    if short.contains('/') {
+        problems.push("short name must not contain \"/\"".to_string());
+    }
+    if short.ends_with('+') {
+        problems.push("short name must not end with \"+\"".to_string());
+    }
+    problems
+}
+
+// returns the hex SHA-256 of an API token, which is kept instead of the token itself
+fn hash_token(token: &str) -> String {
+    data_encoding::HEXLOWER.encode(ring::digest::digest(&ring::digest::SHA256, token.as_bytes()).as_ref())
+}
+
+// returns what is wrong with a request to create an API token
+pub(crate) fn token_problems(input: &model::TokenInput) -> Vec<String> {
+    let mut problems = Vec::new();
+    if input.name.trim().is_empty() {
+        problems.push("name must not be empty".to_string());
+    }
+    if input.name.chars().count() > MAX_TOKEN_NAME_LENGTH {
+        problems.push(format!("name must be at most {MAX_TOKEN_NAME_LENGTH} characters"));
+    }
+    if input
+        .expires_in_days
+        .is_some_and(|days| !(1..=MAX_TOKEN_DAYS).contains(&days))
+    {
+        problems.push(format!("expires_in_days must be between 1 and {MAX_TOKEN_DAYS}"));
+    }
+    problems
+}
+
+// reads a query parameter, if it is given, describing it if it cannot be read
+fn query_param<T: std::str::FromStr>(
+    query: &HashMap<String, String>,
+    name: &str,
+    problems: &mut Vec<String>,
+) -> Option<T> {
+    let value = query.get(name)?;
+    let parsed = value.parse().ok();
+    if parsed.is_none() {
+        problems.push(format!("invalid {name} {value:?}"));
+    }
+    parsed
+}
+
+impl Renderer {
+    /// Decides whether a request may do what the scope allows. A request with an
+    /// "Authorization: Bearer" API token needs a token with the scope that has not
+    /// expired or been revoked, and its use is recorded. Without one, unless API
+    /// tokens are required, links may be read, and changed with the Sec-Golink header.
+    pub async fn authorize(
+        &self,
+        authorization: Option<&str>,
+        sec_golink: bool,
+        scope: model::TokenScope,
+    ) -> Result<(), Box<dyn warp::Reply>> {
+        let required = || {
+            denied(
+                warp::http::StatusCode::UNAUTHORIZED,
+                format!("an API token with the {} scope is required", scope.as_str()),
+            )
+        };
+        let Some(authorization) = authorization else {
+            return match scope {
+                _ if self.require_api_tokens => Err(required()),
+                model::TokenScope::Read => Ok(()),
+                model::TokenScope::Write if sec_golink => Ok(()),
+                model::TokenScope::Write => Err(denied(
+                    warp::http::StatusCode::FORBIDDEN,
+                    "changing links requires an API token or the Sec-Golink header".to_string(),
+                )),
+                model::TokenScope::Admin => Err(required()),
+            };
+        };
+        let token = match authorization.trim().split_once(' ') {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim(),
+            _ => {
+                return Err(denied(
+                    warp::http::StatusCode::UNAUTHORIZED,
+                    "the Authorization header must be a bearer API token".to_string(),
+                ));
+            }
+        };
+
+        let now = chrono::Utc::now();
+        match self.db.tokens.find_by_hash(&hash_token(token)).await {
+            Ok(Some(api_token)) if api_token.is_expired(now) => Err(denied(
+                warp::http::StatusCode::UNAUTHORIZED,
+                format!("API token {} has expired", api_token.id),
+            )),
+            Ok(Some(api_token)) if api_token.scope < scope => Err(denied(
+                warp::http::StatusCode::FORBIDDEN,
+                format!("API token {} does not have the {} scope", api_token.id, scope.as_str()),
+            )),
+            Ok(Some(api_token)) => {
+                if let Err(e) = self.db.tokens.touch(&api_token.id, now).await {
+                    tracing::error!("failed to record use of API token {}: {e}", api_token.id);
+                }
+                Ok(())
+            }
+            Ok(None) => Err(denied(
+                warp::http::StatusCode::UNAUTHORIZED,
+                "invalid API token".to_string(),
+            )),
+            Err(e) => {
+                tracing::error!("{e}");
+                Err(denied(
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                    "internal error".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Decides whether a form posted from a browser may do what the scope allows.
+    /// Its XSRF token is checked too, but any page hands one out, so that only
+    /// shows the form was posted from a browser. Managing API tokens, purging
+    /// usage data, and changing links when API tokens are required, also need an API token
+    /// with the scope or a user signed in through a trusted proxy.
+    pub async fn authorize_form(
+        &self,
+        authorization: Option<&str>,
+        user: Option<&str>,
+        scope: model::TokenScope,
+    ) -> Result<(), Box<dyn warp::Reply>> {
+        if authorization.is_some() {
+            return self.authorize(authorization, false, scope).await;
+        }
+        match (user, scope) {
+            (Some(_), _) | (None, model::TokenScope::Read) => Ok(()),
+            (None, model::TokenScope::Write) if !self.require_api_tokens => Ok(()),
+            (None, _) => Err(denied(
+                warp::http::StatusCode::UNAUTHORIZED,
+                format!(
+                    "an API token with the {} scope, or signing in through a trusted proxy, is required",
+                    scope.as_str()
+                ),
+            )),
+        }
+    }
+
+    /// Creates an API token, returning it along with the token itself, which is
+    /// not kept and cannot be read again. The input must have no
+    /// [`token_problems`].
+    pub async fn issue_token(&self, input: model::TokenInput) -> Result<model::NewToken, Box<crate::db::DbError>> {
+        let mut id = [0u8; 6];
+        let mut secret = [0u8; 32];
+        rand::rng().fill_bytes(&mut id);
+        rand::rng().fill_bytes(&mut secret);
+        let id = data_encoding::HEXLOWER.encode(&id);
+        let token = format!("{TOKEN_PREFIX}{id}_{}", data_encoding::HEXLOWER.encode(&secret));
+
+        let now = chrono::Utc::now();
+        let api_token = model::ApiToken {
+            id,
+            name: input.name.trim().to_string(),
+            scope: input.scope,
+            created: now,
+            expires: input
+                .expires_in_days
+                .map(|days| now + chrono::Duration::days(i64::from(days))),
+            last_used: None,
+        };
+        self.db.tokens.save(&api_token, &hash_token(&token)).await?;
+        tracing::info!(
+            "created API token {} ({}) with the {} scope",
+            api_token.id,
+            api_token.name,
+            api_token.scope.as_str()
+        );
+        Ok(model::NewToken { api_token, token })
+    }
+
+    /// Lists the API tokens, without the tokens themselves.
+    pub async fn api_tokens(&self) -> Reply {
+        match self.db.tokens.load_all().await {
+            Ok(tokens) => json(&tokens, warp::http::StatusCode::OK),
+            Err(e) => internal_error(e),
+        }
+    }
+
+    /// Creates an API token from a JSON [`model::TokenInput`].
+    pub async fn api_create_token(&self, body: &[u8]) -> Reply {
+        let input: model::TokenInput = match parse(body) {
+            Ok(input) => input,
+            Err(details) => return error(warp::http::StatusCode::BAD_REQUEST, "invalid API token", details),
+        };
+        let problems = token_problems(&input);
+        if !problems.is_empty() {
+            return error(warp::http::StatusCode::BAD_REQUEST, "invalid API token", problems);
+        }
+        match self.issue_token(input).await {
+            Ok(token) => json(&token, warp::http::StatusCode::CREATED),
+            Err(e) => internal_error(e),
+        }
+    }
+
+    /// Revokes an API token, which stops working at once.
+    pub async fn api_revoke_token(&self, id: &str) -> Reply {
+        match self.db.tokens.delete(id).await {
+            Ok(true) => {
+                tracing::info!("revoked API token {id}");
+                Ok(Box::new(warp::http::StatusCode::NO_CONTENT))
+            }
+            Ok(false) => error(
+                warp::http::StatusCode::NOT_FOUND,
+                &format!("API token {id} not found"),
+                Vec::new(),
+            ),
+            Err(e) => internal_error(e),
+        }
+    }
+
+    /// Lists a page of links. The "offset" and "limit" query parameters choose the page,
+    /// "q" only lists links whose short name or destination link contains it, and
+    /// "template" only lists templates, or only links that are not templates.
+    pub async fn api_list(&self, query: &HashMap<String, String>) -> Reply {
+        let mut problems = Vec::new();
+        let offset = query_param(query, "offset", &mut problems).unwrap_or(0);
+        let limit = query_param(query, "limit", &mut problems).unwrap_or(DEFAULT_PAGE_SIZE);
+        let template = query_param(query, "template", &mut problems);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            problems.push(format!("limit must be between 1 and {MAX_PAGE_SIZE}"));
+        }
+        if i64::try_from(offset).is_err() {
+            problems.push(format!("offset must be at most {}", i64::MAX));
+        }
+        if !problems.is_empty() {
+            return error(warp::http::StatusCode::BAD_REQUEST, "invalid query", problems);
+        }
+        let q = query.get("q").map(|q| q.trim()).filter(|q| !q.is_empty());
+
+        match self.db.link.page(q, template, offset, limit).await {
+            Ok((links, total)) => json(
+                &model::LinkPage {
+                    next: offset.checked_add(links.len()).filter(|next| *next < total),
+                    links,
+                    total,
+                    offset,
+                    limit,
+                },
+                warp::http::StatusCode::OK,
+            ),
+            Err(e) => internal_error(e),
+        }
+    }
+
+    /// Returns a link.
+    pub async fn api_get(&self, short: &str) -> Reply {
+        match self.db.link.find(short).await {
+            Ok(Some(link)) => json(&link, warp::http::StatusCode::OK),
+            Ok(None) => not_found(short),
+            Err(e) => internal_error(e),
+        }
+    }
+
+    /// Creates a link from a JSON [`model::LinkInput`].
+    pub async fn api_create(&self, body: &[u8]) -> Reply {
+        let input: model::LinkInput = match parse(body) {
+            Ok(input) => input,
+            Err(details) => return error(warp::http::StatusCode::BAD_REQUEST, "invalid link", details),
+        };
+        let problems = short_problems(&input.short);
+        if !problems.is_empty() {
+            return error(warp::http::StatusCode::BAD_REQUEST, "invalid short name", problems);
+        }
+        let long = match self.prepare_target(&input.long, &input.passthrough) {
+            Ok(long) => long,
+            Err(details) => {
+                return error(warp::http::StatusCode::BAD_REQUEST, "invalid destination link", details);
+            }
+        };
+        let now = chrono::Utc::now();
+        let link = model::Link {
+            short: input.short,
+            long,
+            created: now,
+            updated: now,
+            status: input.status,
+            max_age: input.max_age,
+            passthrough: input.passthrough,
+            dont_track: input.dont_track,
+        };
+        // the link is only inserted if there is none, so that of two creates at once only one succeeds
+        match self.db.link.insert(&link).await {
+            Ok(true) => {
+                self.invalidate(&link.short);
+                let _ = self.db.stats.save(&link.short).await;
+                json(&link, warp::http::StatusCode::CREATED)
+            }
+            Ok(false) => error(
+                warp::http::StatusCode::CONFLICT,
+                &format!("link go/{} already exists", link.short),
+                Vec::new(),
+            ),
+            Err(e) => internal_error(e),
+        }
+    }
+
+    /// Replaces every field of a link with those of a JSON [`model::LinkInput`].
+    pub async fn api_replace(&self, short: &str, body: &[u8]) -> Reply {
+        let input: model::LinkInput = match parse(body) {
+            Ok(input) => input,
+            Err(details) => return error(warp::http::StatusCode::BAD_REQUEST, "invalid link", details),
+        };
+        // the short name may only change case or hyphens, since that does not change which link it is
+        if !input.short.is_empty() && model::normalized_id(&input.short) != model::normalized_id(short) {
+            return error(
+                warp::http::StatusCode::BAD_REQUEST,
+                "invalid short name",
+                vec![format!("short name {:?} does not match go/{short}", input.short)],
+            );
+        }
+        let Some(link) = (match self.db.link.find(short).await {
+            Ok(link) => link,
+            Err(e) => return internal_error(e),
+        }) else {
+            return not_found(short);
+        };
+        let replaced = model::Link {
+            short: if input.short.is_empty() {
+                link.short.clone()
+            } else {
+                input.short
+            },
+            long: input.long,
+            created: link.created,
+            updated: chrono::Utc::now(),
+            status: input.status,
+            max_age: input.max_age,
+            passthrough: input.passthrough,
+            dont_track: input.dont_track,
+        };
+        self.api_save(link, replaced).await
+    }
+
+    /// Changes the fields of a link given in a JSON [`model::LinkPatch`].
+    pub async fn api_patch(&self, short: &str, body: &[u8]) -> Reply {
+        let patch: model::LinkPatch = match parse(body) {
+            Ok(patch) => patch,
+            Err(details) => return error(warp::http::StatusCode::BAD_REQUEST, "invalid patch", details),
+        };
+        let Some(link) = (match self.db.link.find(short).await {
+            Ok(link) => link,
+            Err(e) => return internal_error(e),
+        }) else {
+            return not_found(short);
+        };
+        let patched = model::Link {
+            long: patch.long.unwrap_or_else(|| link.long.clone()),
+            updated: chrono::Utc::now(),
+            status: patch.status.unwrap_or(link.status),
+            max_age: patch.max_age.unwrap_or(link.max_age),
+            passthrough: model::Passthrough {
+                path_mode: patch.path_mode.unwrap_or(link.passthrough.path_mode),
+                query_mode: patch.query_mode.unwrap_or(link.passthrough.query_mode),
+                query_allow: patch
+                    .query_allow
+                    .unwrap_or_else(|| link.passthrough.query_allow.clone()),
+            },
+            dont_track: patch.dont_track.unwrap_or(link.dont_track),
+            ..link.clone()
+        };
+        self.api_save(link, patched).await
+    }
+
+    // saves a changed link, once its destination link is prepared and valid
+    async fn api_save(&self, link: model::Link, changed: model::Link) -> Reply {
+        let long = match self.prepare_target(&changed.long, &changed.passthrough) {
+            Ok(long) => long,
+            Err(details) => {
+                return error(warp::http::StatusCode::BAD_REQUEST, "invalid destination link", details);
+            }
+        };
+        let changed = model::Link { long, ..changed };
+        match self.db.link.save(&changed).await {
+            Ok(()) => {
+                self.invalidate(&changed.short);
+                // the last check was of the old destination link
+                if changed.long != link.long {
+                    let _ = self.db.checks.delete(&changed.short).await;
+                }
+                json(&changed, warp::http::StatusCode::OK)
+            }
+            Err(e) => internal_error(e),
+        }
+    }
+
+    /// Deletes a link and its click stats.
+    pub async fn api_delete(&self, short: &str) -> Reply {
+        match self.db.link.find(short).await {
+            Ok(Some(_)) => match self.delete_link(short).await {
+                Ok(()) => Ok(Box::new(warp::http::StatusCode::NO_CONTENT)),
+                Err(e) => internal_error(e),
+            },
+            Ok(None) => not_found(short),
+            Err(e) => internal_error(e),
+        }
+    }
+
+    /// Returns the clicks on a link: all time, per day over the "days" query
+    /// parameter, and where the clicks of those days came from.
+    pub async fn api_stats(&self, short: &str, query: &HashMap<String, String>) -> Reply {
+        let mut problems = Vec::new();
+        let days = query_param(query, "days", &mut problems).unwrap_or(render::BREAKDOWN_DAYS);
+        if !problems.is_empty() {
+            return error(warp::http::StatusCode::BAD_REQUEST, "invalid query", problems);
+        }
+        if !self.track_clicks {
+            return error(
+                warp::http::StatusCode::NOT_FOUND,
+                "click tracking is disabled",
+                Vec::new(),
+            );
+        }
+        let days = days.clamp(1, render::MAX_ROLLUP_DAYS);
+        let stats = match self.db.stats.load(short).await {
+            Ok(Some(stats)) => stats,
+            Ok(None) => return not_found(short),
+            Err(e) => return internal_error(e),
+        };
+        match self.rollups(short, days).await {
+            Ok(Some(rollups)) => json(
+                &model::LinkStats {
+                    rollups,
+                    skipped: stats.skipped,
+                    breakdown: self.breakdown(short, Some(days)).await,
+                },
+                warp::http::StatusCode::OK,
+            ),
+            Ok(None) => not_found(short),
+            Err(e) => internal_error(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RequestContext;
+
+    async fn call<T: DeserializeOwned>(reply: Reply) -> (warp::http::StatusCode, T) {
+        let response = warp::Reply::into_response(reply.unwrap());
+        let status = response.status();
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_links() {
+        let renderer = Renderer::empty();
+
+        let (status, link): (_, model::Link) = call(
+            renderer
+                .api_create(br#"{"short": "nyt", "long": "http://www.nytimes.com", "max_age": 60}"#)
+                .await,
+        )
+        .await;
+        assert_eq!(status, warp::http::StatusCode::CREATED);
+        assert_eq!((link.short.as_str(), link.max_age), ("nyt", Some(60)));
+
+        let (status, error): (_, model::ErrorResponse) = call(
+            renderer
+                .api_create(br#"{"short": "NYT", "long": "http://www.nytimes.com"}"#)
+                .await,
+        )
+        .await;
+        assert_eq!(status, warp::http::StatusCode::CONFLICT);
+        assert_eq!(error.error, "link go/NYT already exists");
+        // of two creates at once, the second finds the first rather than replacing it
+        let (first, second) = tokio::join!(
+            renderer.api_create(br#"{"short": "race", "long": "http://first.example"}"#),
+            renderer.api_create(br#"{"short": "race", "long": "http://second.example"}"#),
+        );
+        let statuses = [first, second].map(|reply| warp::Reply::into_response(reply.unwrap()).status());
+        assert!(statuses.contains(&warp::http::StatusCode::CREATED));
+        assert!(statuses.contains(&warp::http::StatusCode::CONFLICT));
+        for body in [
+            r#"{"short": "nyt"}"#.as_bytes(),
+            br#"{"short": "a/b", "long": "http://example.com"}"#,
+            br#"{"short": "broken", "long": "{{#if path}}http://example.com"}"#,
+        ] {
+            let (status, error): (_, model::ErrorResponse) = call(renderer.api_create(body).await).await;
+            assert_eq!(status, warp::http::StatusCode::BAD_REQUEST);
+            assert!(!error.details.is_empty());
+        }
+
+        // a patch keeps the fields it leaves out, and null clears the max age
+        let (status, link): (_, model::Link) =
+            call(renderer.api_patch("nyt", br#"{"status": 301, "max_age": null}"#).await).await;
+        assert_eq!(status, warp::http::StatusCode::OK);
+        assert_eq!(link.status, model::RedirectStatus::MovedPermanently);
+        assert_eq!((link.long.as_str(), link.max_age), ("http://www.nytimes.com", None));
+        let (status, _): (_, model::ErrorResponse) = call(renderer.api_patch("nyt", br#"{"short": "x"}"#).await).await;
+        assert_eq!(status, warp::http::StatusCode::BAD_REQUEST);
+
+        // a replacement resets the fields it leaves out
+        let (status, link): (_, model::Link) = call(
+            renderer
+                .api_replace("nyt", br#"{"long": "https://www.nytimes.com"}"#)
+                .await,
+        )
+        .await;
+        assert_eq!(status, warp::http::StatusCode::OK);
+        assert_eq!(link.status, model::RedirectStatus::default());
+        assert_eq!(link.long, "https://www.nytimes.com");
+        let (status, _): (_, model::ErrorResponse) = call(
+            renderer
+                .api_replace("nyt", br#"{"short": "cnn", "long": "https://cnn.com"}"#)
+                .await,
+        )
+        .await;
+        assert_eq!(status, warp::http::StatusCode::BAD_REQUEST);
+        let (status, _): (_, model::ErrorResponse) =
+            call(renderer.api_replace("nope", br#"{"long": "https://cnn.com"}"#).await).await;
+        assert_eq!(status, warp::http::StatusCode::NOT_FOUND);
+
+        let (status, fetched): (_, model::Link) = call(renderer.api_get("NYT").await).await;
+        assert_eq!((status, fetched), (warp::http::StatusCode::OK, link));
+
+        let response = warp::Reply::into_response(renderer.api_delete("nyt").await.unwrap());
+        assert_eq!(response.status(), warp::http::StatusCode::NO_CONTENT);
+        let (status, _): (_, model::ErrorResponse) = call(renderer.api_get("nyt").await).await;
+        assert_eq!(status, warp::http::StatusCode::NOT_FOUND);
+        let (status, _): (_, model::ErrorResponse) = call(renderer.api_delete("nyt").await).await;
+        assert_eq!(status, warp::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_list_and_stats() {
+        let renderer = Renderer::empty();
+        for short in ["a", "b", "c"] {
+            let body = format!(r#"{{"short": "{short}", "long": "http://{short}.example"}}"#);
+            renderer.api_create(body.as_bytes()).await.unwrap();
+        }
+        let query = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let (status, page): (_, model::LinkPage) = call(renderer.api_list(&query(&[("limit", "2")])).await).await;
+        assert_eq!(status, warp::http::StatusCode::OK);
+        assert_eq!((page.links.len(), page.total, page.next), (2, 3, Some(2)));
+        let (_, page): (_, model::LinkPage) =
+            call(renderer.api_list(&query(&[("limit", "2"), ("offset", "2")])).await).await;
+        assert_eq!((page.links[0].short.as_str(), page.next), ("c", None));
+        let (_, page): (_, model::LinkPage) = call(renderer.api_list(&query(&[("q", "B.EXAMPLE")])).await).await;
+        assert_eq!(page.total, 1);
+        let (status, error): (_, model::ErrorResponse) =
+            call(renderer.api_list(&query(&[("limit", "0"), ("offset", "x")])).await).await;
+        assert_eq!(status, warp::http::StatusCode::BAD_REQUEST);
+        assert_eq!(error.details.len(), 2);
+        let (status, _): (_, model::ErrorResponse) =
+            call(renderer.api_list(&query(&[("offset", &u64::MAX.to_string())])).await).await;
+        assert_eq!(status, warp::http::StatusCode::BAD_REQUEST);
+        let (status, page): (_, model::LinkPage) =
+            call(renderer.api_list(&query(&[("offset", &i64::MAX.to_string())])).await).await;
+        assert_eq!(status, warp::http::StatusCode::OK);
+        assert_eq!((page.links.len(), page.next), (0, None));
+
+        renderer
+            .get("a", "/a", HashMap::new(), &RequestContext::default())
+            .await
+            .unwrap();
+        renderer.flush_clicks().await;
+        let (status, stats): (_, model::LinkStats) =
+            call(renderer.api_stats("a", &query(&[("days", "7")])).await).await;
+        assert_eq!(status, warp::http::StatusCode::OK);
+        assert_eq!((stats.rollups.clicks, stats.rollups.days), (1, 7));
+        assert_eq!(stats.breakdown.unwrap().days, Some(7));
+        let (status, _): (_, model::ErrorResponse) = call(renderer.api_stats("nope", &HashMap::new()).await).await;
+        assert_eq!(status, warp::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_authorize() {
+        use model::TokenScope::{Admin, Read, Write};
+
+        let renderer = Renderer::empty();
+        let status = |access: Result<(), Box<dyn warp::Reply>>| {
+            access.map_err(|reply| warp::Reply::into_response(reply).status())
+        };
+        let issue = |scope, expires_in_days| {
+            renderer.issue_token(model::TokenInput {
+                name: "script".to_string(),
+                scope,
+                expires_in_days,
+            })
+        };
+        let write = issue(Write, Some(30)).await.unwrap();
+        let bearer = format!("Bearer {}", write.token);
+        assert!(
+            write
+                .token
+                .starts_with(&format!("{TOKEN_PREFIX}{}_", write.api_token.id))
+        );
+
+        // without a token, links can be read, and changed with the Sec-Golink header
+        assert_eq!(status(renderer.authorize(None, false, Read).await), Ok(()));
+        assert_eq!(status(renderer.authorize(None, true, Write).await), Ok(()));
+        assert_eq!(
+            status(renderer.authorize(None, false, Write).await),
+            Err(warp::http::StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            status(renderer.authorize(None, true, Admin).await),
+            Err(warp::http::StatusCode::UNAUTHORIZED)
+        );
+
+        // a token allows its scope and those before it, and its use is recorded
+        assert_eq!(status(renderer.authorize(Some(&bearer), false, Read).await), Ok(()));
+        assert_eq!(status(renderer.authorize(Some(&bearer), false, Write).await), Ok(()));
+        assert_eq!(
+            status(renderer.authorize(Some(&bearer), true, Admin).await),
+            Err(warp::http::StatusCode::FORBIDDEN)
+        );
+        assert!(renderer.db.tokens.load_all().await.unwrap()[0].last_used.is_some());
+        for authorization in [
+            format!("Basic {}", write.token),
+            "Bearer gohome_nope".to_string(),
+            write.token.clone(),
+        ] {
+            assert_eq!(
+                status(renderer.authorize(Some(&authorization), true, Read).await),
+                Err(warp::http::StatusCode::UNAUTHORIZED),
+                "{authorization}"
+            );
+        }
+
+        // an expired token does not work, and nor does a revoked one
+        let mut expired = issue(Admin, None).await.unwrap();
+        expired.api_token.expires = Some(chrono::Utc::now() - chrono::Duration::seconds(1));
+        renderer.db.tokens.delete(&expired.api_token.id).await.unwrap();
+        renderer
+            .db
+            .tokens
+            .save(&expired.api_token, &hash_token(&expired.token))
+            .await
+            .unwrap();
+        assert_eq!(
+            status(
+                renderer
+                    .authorize(Some(&format!("bearer {}", expired.token)), false, Read)
+                    .await
+            ),
+            Err(warp::http::StatusCode::UNAUTHORIZED)
+        );
+        let response = warp::Reply::into_response(renderer.api_revoke_token(&write.api_token.id).await.unwrap());
+        assert_eq!(response.status(), warp::http::StatusCode::NO_CONTENT);
+        assert_eq!(
+            status(renderer.authorize(Some(&bearer), false, Read).await),
+            Err(warp::http::StatusCode::UNAUTHORIZED)
+        );
+
+        // once API tokens are required, the Sec-Golink header is not enough
+        let required = Renderer::with_options(
+            "go",
+            renderer.db.clone(),
+            handlebars::Handlebars::new(),
+            render::Options {
+                require_api_tokens: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            status(required.authorize(None, true, Write).await),
+            Err(warp::http::StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            status(required.authorize(None, false, Read).await),
+            Err(warp::http::StatusCode::UNAUTHORIZED)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_authorize_form() {
+        use model::TokenScope::{Admin, Write};
+
+        let renderer = Renderer::empty();
+        let status = |access: Result<(), Box<dyn warp::Reply>>| {
+            access.map_err(|reply| warp::Reply::into_response(reply).status())
+        };
+        let admin = renderer
+            .issue_token(model::TokenInput {
+                name: "admin".to_string(),
+                scope: Admin,
+                expires_in_days: None,
+            })
+            .await
+            .unwrap();
+        let bearer = format!("Bearer {}", admin.token);
+
+        // anyone with an XSRF token may change links, but only a signed in user or an API token may manage tokens
+        assert_eq!(status(renderer.authorize_form(None, None, Write).await), Ok(()));
+        assert_eq!(
+            status(renderer.authorize_form(None, None, Admin).await),
+            Err(warp::http::StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            status(renderer.authorize_form(None, Some("amelie"), Admin).await),
+            Ok(())
+        );
+        assert_eq!(
+            status(renderer.authorize_form(Some(&bearer), None, Admin).await),
+            Ok(())
+        );
+        assert_eq!(
+            status(
+                renderer
+                    .authorize_form(Some("Bearer gohome_nope"), Some("amelie"), Admin)
+                    .await
+            ),
+            Err(warp::http::StatusCode::UNAUTHORIZED)
+        );
+
+        // once API tokens are required, so are they to change links
+        let required = Renderer::with_options(
+            "go",
+            renderer.db.clone(),
+            handlebars::Handlebars::new(),
+            render::Options {
+                require_api_tokens: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            status(required.authorize_form(None, None, Write).await),
+            Err(warp::http::StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            status(required.authorize_form(None, Some("amelie"), Write).await),
+            Ok(())
+        );
+        assert_eq!(
+            status(required.authorize_form(Some(&bearer), None, Write).await),
+            Ok(())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tokens() {
+        let renderer = Renderer::empty();
+        let (status, created): (_, model::NewToken) = call(
+            renderer
+                .api_create_token(br#"{"name": "deploy", "scope": "write", "expires_in_days": 7}"#)
+                .await,
+        )
+        .await;
+        assert_eq!(status, warp::http::StatusCode::CREATED);
+        assert_eq!(created.api_token.scope, model::TokenScope::Write);
+        assert!(created.api_token.expires.is_some());
+        for body in [
+            r#"{"name": " "}"#.as_bytes(),
+            br#"{"name": "deploy", "scope": "root"}"#,
+            br#"{"name": "deploy", "expires_in_days": 0}"#,
+            br#"{"name": "deploy", "expires_in_days": 4000000000}"#,
+        ] {
+            let (status, _): (_, model::ErrorResponse) = call(renderer.api_create_token(body).await).await;
+            assert_eq!(status, warp::http::StatusCode::BAD_REQUEST);
+        }
+
+        // the token itself is never listed
+        let response = warp::Reply::into_response(renderer.api_tokens().await.unwrap());
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        let listed: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0]["id"], created.api_token.id.as_str());
+        assert!(listed[0].get("token").is_none());
+        assert!(!String::from_utf8_lossy(&body).contains(&created.token));
+
+        let (status, _): (_, model::ErrorResponse) = call(renderer.api_revoke_token("nope").await).await;
+        assert_eq!(status, warp::http::StatusCode::NOT_FOUND);
+    }
+}
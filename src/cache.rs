@@ -0,0 +1,188 @@
+use std::sync::{
+    Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+
+use hashlink::LruCache;
+
+use crate::model;
+
+// the most short names without a link that are cached, so that requests for made up names
+// cannot push out the links in use
+const MAX_MISSING: usize = 256;
+
+/// LinkCache is a bounded in-memory cache from a link's normalized id to the
+/// link, so that hot redirects do not need the database. Links that do not
+/// exist are cached too, as `None`, in a smaller cache of their own, so repeated
+/// misses are also answered here. When either is full its least recently used
+/// entry is evicted.
+pub(crate) struct LinkCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Entries {
+    links: LruCache<String, model::Link>,
+    missing: LruCache<String, ()>,
+    invalidations: u64,
+}
+
+impl LinkCache {
+    /// Creates a cache holding up to `capacity` entries. A capacity of 0 disables caching.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(Entries {
+                links: LruCache::new(capacity),
+                missing: LruCache::new(capacity.min(MAX_MISSING)),
+                invalidations: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the cached entry for the short name: `Some(None)` if the link is
+    /// known not to exist, and `None` if the cache does not know.
+    pub(crate) fn get(&self, short: &str) -> Option<Option<model::Link>> {
+        let id = model::normalized_id(short);
+        let mut entries = self.entries.lock().unwrap();
+        let found = match entries.links.get(&id) {
+            Some(link) => Some(Some(link.clone())),
+            None => entries.missing.get(&id).map(|()| None),
+        };
+        let counter = if found.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    /// Returns a token to pass to [`LinkCache::insert`] for a link about to be loaded.
+    pub(crate) fn generation(&self) -> u64 {
+        self.entries.lock().unwrap().invalidations
+    }
+
+    /// Caches the link found for the short name, or that there is none. The link
+    /// is not cached if any link was invalidated since `generation` was taken,
+    /// since it may have been loaded before a change was saved.
+    pub(crate) fn insert(&self, short: &str, link: Option<model::Link>, generation: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        if self.capacity == 0 || entries.invalidations != generation {
+            return;
+        }
+        let id = model::normalized_id(short);
+        match link {
+            Some(link) => {
+                entries.missing.remove(&id);
+                entries.links.insert(id, link);
+            }
+            None => {
+                entries.links.remove(&id);
+                entries.missing.insert(id, ());
+            }
+        }
+    }
+
+    /// Forgets the short name, after its link was created, changed or deleted.
+    pub(crate) fn invalidate(&self, short: &str) {
+        let id = model::normalized_id(short);
+        let mut entries = self.entries.lock().unwrap();
+        entries.invalidations += 1;
+        entries.links.remove(&id);
+        entries.missing.remove(&id);
+    }
+
+    pub(crate) fn stats(&self) -> model::CacheStats {
+        let entries = self.entries.lock().unwrap();
+        model::CacheStats {
+            capacity: self.capacity,
+            entries: entries.links.len() + entries.missing.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(short: &str) -> model::Link {
+        model::Link {
+            short: short.to_string(),
+            long: format!("http://{short}.example"),
+            created: chrono::Utc::now(),
+            updated: chrono::Utc::now(),
+            status: model::RedirectStatus::default(),
+            max_age: None,
+            passthrough: model::Passthrough::default(),
+            dont_track: false,
+        }
+    }
+
+    #[test]
+    fn test_hits_and_misses() {
+        let cache = LinkCache::new(10);
+        assert_eq!(cache.get("nyt"), None);
+        let nyt = link("nyt");
+        cache.insert("nyt", Some(nyt.clone()), 0);
+        cache.insert("missing", None, 0);
+        assert_eq!(cache.get("NYT"), Some(Some(nyt)));
+        assert_eq!(cache.get("missing"), Some(None));
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.hits, 2);
+
+        cache.invalidate("missing");
+        assert_eq!(cache.get("missing"), None);
+    }
+
+    #[test]
+    fn test_stale_insert() {
+        let cache = LinkCache::new(10);
+        let generation = cache.generation();
+        cache.invalidate("nyt"); // saved while the old link was being loaded
+        cache.insert("nyt", Some(link("nyt")), generation);
+        assert_eq!(cache.get("nyt"), None);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = LinkCache::new(2);
+        cache.insert("a", Some(link("a")), 0);
+        cache.insert("b", Some(link("b")), 0);
+        cache.get("a");
+        cache.insert("c", Some(link("c")), 0);
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[test]
+    fn test_missing_links_are_bounded_apart() {
+        let cache = LinkCache::new(MAX_MISSING * 2);
+        cache.insert("a", Some(link("a")), 0);
+        for n in 0..MAX_MISSING * 2 {
+            cache.insert(&format!("missing{n}"), None, 0);
+        }
+        assert!(cache.get("a").is_some_and(|link| link.is_some()));
+        assert_eq!(cache.get("missing0"), None);
+        assert_eq!(cache.get(&format!("missing{}", MAX_MISSING * 2 - 1)), Some(None));
+        assert_eq!(cache.stats().entries, 1 + MAX_MISSING);
+
+        // a link created since it was missing replaces the miss
+        cache.insert("missing300", Some(link("missing300")), 0);
+        assert!(cache.get("missing300").is_some_and(|link| link.is_some()));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let cache = LinkCache::new(0);
+        cache.insert("a", Some(link("a")), 0);
+        assert!(cache.get("a").is_none());
+    }
+}
@@ -0,0 +1,254 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::Semaphore, task::JoinSet};
+use url::Url;
+
+use crate::{db, model};
+
+/// CheckerOptions configures how often and how hard the [`Checker`] requests destination links.
+#[derive(Clone, Debug)]
+pub struct CheckerOptions {
+    pub interval: Duration,       // the time between checking every link
+    pub concurrency: usize,       // the most requests in flight at once
+    pub requests_per_second: f64, // the most requests started per second
+    pub timeout: Duration,        // how long a single request may take
+}
+
+impl Default for CheckerOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(24 * 60 * 60),
+            concurrency: 4,
+            requests_per_second: 2.0,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Returns the time between starting requests at the given rate, or `None` if the
+/// rate is not a positive number that a time between requests can be made of.
+pub fn request_period(requests_per_second: f64) -> Option<Duration> {
+    if !(requests_per_second.is_finite() && requests_per_second > 0.0) {
+        return None;
+    }
+    Duration::try_from_secs_f64(1.0 / requests_per_second)
+        .ok()
+        .filter(|period| !period.is_zero())
+}
+
+/// Checker periodically requests the destination of every link that is not
+/// a template, and records whether it still works. Links are requested with
+/// HEAD, falling back to GET for servers that do not answer HEAD requests,
+/// and redirects are recorded rather than followed.
+#[derive(Clone)]
+pub struct Checker {
+    client: reqwest::Client,
+    db: db::Db,
+    options: CheckerOptions,
+}
+
+impl Checker {
+    pub fn new(db: db::Db, options: CheckerOptions) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(options.timeout)
+            .user_agent(concat!("gohome-link-checker/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        Ok(Self { client, db, options })
+    }
+
+    /// Checks every link on the interval, forever.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.options.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match self.check_all().await {
+                Ok(checked) => tracing::info!("checked {checked} links"),
+                Err(e) => tracing::error!("failed to check links: {e}"),
+            }
+        }
+    }
+
+    /// Checks every link that is not a template once, within the concurrency
+    /// and rate limits, returning how many were checked.
+    pub async fn check_all(&self) -> Result<usize, Box<db::DbError>> {
+        let links = self.db.link.load_all().await?;
+        let permits = Arc::new(Semaphore::new(self.options.concurrency.max(1)));
+        // options that were not checked with request_period fall back to the default rate
+        let period = request_period(self.options.requests_per_second)
+            .or_else(|| request_period(CheckerOptions::default().requests_per_second))
+            .expect("the default rate is valid");
+        let mut rate = tokio::time::interval(period);
+        rate.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        let mut checks = JoinSet::new();
+        // templates expand differently for every click, so there is no one destination to check
+        for link in links.into_iter().filter(|link| !link.long.contains("{{")) {
+            rate.tick().await;
+            let permit = Arc::clone(&permits)
+                .acquire_owned()
+                .await
+                .expect("semaphore is never closed");
+            let checker = self.clone();
+            checks.spawn(async move {
+                let check = checker.check(&link.long).await;
+                drop(permit);
+                if check.broken {
+                    tracing::warn!("go/{} is broken: {}", link.short, link.long);
+                }
+                checker.db.checks.save(&link.short, &check).await
+            });
+        }
+
+        let mut checked = 0;
+        while let Some(result) = checks.join_next().await {
+            match result {
+                Ok(Ok(())) => checked += 1,
+                Ok(Err(e)) => tracing::error!("failed to save link check: {e}"),
+                Err(e) => tracing::error!("link check failed: {e}"),
+            }
+        }
+        Ok(checked)
+    }
+
+    /// Requests a destination link, with HEAD and then with GET if the HEAD
+    /// request fails or is answered with an error.
+    pub async fn check(&self, long: &str) -> model::LinkCheck {
+        let response = match self.client.head(long).send().await {
+            Ok(response) if !response.status().is_client_error() && !response.status().is_server_error() => {
+                Ok(response)
+            }
+            _ => self.client.get(long).send().await,
+        };
+        let checked = chrono::Utc::now();
+        match response {
+            Ok(response) => {
+                let location = response
+                    .headers()
+                    .get(reqwest::header::LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .map(|location| {
+                        // relative redirects are resolved against the destination link
+                        Url::parse(long)
+                            .and_then(|base| base.join(location))
+                            .map_or(location.to_string(), String::from)
+                    });
+                model::LinkCheck::new(Some(response.status().as_u16()), location, None, checked)
+            }
+            Err(e) => model::LinkCheck::new(None, None, Some(e.to_string()), checked),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use warp::Filter;
+
+    use super::*;
+
+    // serves destinations that work, are gone, redirect, do not answer HEAD requests and are slow
+    async fn test_server() -> SocketAddr {
+        let ok = warp::path!("ok").map(|| "ok");
+        let gone = warp::path!("gone").map(|| warp::http::StatusCode::NOT_FOUND);
+        let moved = warp::path!("moved").map(|| {
+            warp::http::Response::builder()
+                .status(warp::http::StatusCode::MOVED_PERMANENTLY)
+                .header("Location", "/ok")
+                .body(String::new())
+                .unwrap()
+        });
+        let no_head = warp::path!("nohead")
+            .and(warp::head())
+            .map(|| warp::http::StatusCode::METHOD_NOT_ALLOWED)
+            .or(warp::path!("nohead")
+                .and(warp::get())
+                .map(|| warp::http::StatusCode::OK))
+            .unify();
+        let slow = warp::path!("slow").then(|| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            "slow"
+        });
+        let routes = ok.or(gone).or(moved).or(no_head).or(slow);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(crate::server::serve(
+            warp::service(routes),
+            listener,
+            std::future::pending(),
+        ));
+        addr
+    }
+
+    fn checker(db: db::Db) -> Checker {
+        let options = CheckerOptions {
+            requests_per_second: 100.0,
+            timeout: Duration::from_millis(500),
+            ..Default::default()
+        };
+        Checker::new(db, options).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let addr = test_server().await;
+        let checker = checker(db::Db::in_memory().unwrap());
+
+        let check = checker.check(&format!("http://{addr}/ok")).await;
+        assert_eq!((check.status, check.broken), (Some(200), false));
+        let check = checker.check(&format!("http://{addr}/gone")).await;
+        assert_eq!((check.status, check.broken), (Some(404), true));
+        let check = checker.check(&format!("http://{addr}/moved")).await;
+        assert_eq!((check.status, check.broken), (Some(301), false));
+        assert_eq!(check.location, Some(format!("http://{addr}/ok")));
+        let check = checker.check(&format!("http://{addr}/nohead")).await;
+        assert_eq!((check.status, check.broken), (Some(200), false));
+        let check = checker.check(&format!("http://{addr}/slow")).await;
+        assert_eq!((check.status, check.broken), (None, true));
+        assert!(check.error.is_some());
+    }
+
+    #[test]
+    fn test_request_period() {
+        assert_eq!(request_period(2.0), Some(Duration::from_millis(500)));
+        assert_eq!(request_period(0.5), Some(Duration::from_secs(2)));
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY, f64::MIN_POSITIVE, f64::MAX] {
+            assert_eq!(request_period(rate), None, "{rate}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_all() -> Result<(), Box<db::DbError>> {
+        let addr = test_server().await;
+        let db = db::Db::in_memory().unwrap();
+        for (short, long) in [
+            ("ok", format!("http://{addr}/ok")),
+            ("gone", format!("http://{addr}/gone")),
+            ("search", format!("http://{addr}/{{{{path}}}}")),
+        ] {
+            let link = model::Link {
+                short: short.to_string(),
+                long,
+                created: chrono::Utc::now(),
+                updated: chrono::Utc::now(),
+                status: model::RedirectStatus::default(),
+                max_age: None,
+                passthrough: model::Passthrough::default(),
+                dont_track: false,
+            };
+            db.link.save(&link).await?;
+        }
+
+        assert_eq!(checker(db.clone()).check_all().await?, 2);
+        assert!(!db.checks.load("ok").await?.unwrap().broken);
+        assert!(db.checks.load("gone").await?.unwrap().broken);
+        assert!(db.checks.load("search").await?.is_none()); // templates are not checked
+        assert_eq!(db.checks.load_all().await?.len(), 2);
+        db.checks.delete("gone").await?;
+        assert!(db.checks.load("gone").await?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use tokio::sync::{Mutex, mpsc};
+
+use crate::{db, model};
+
+// the most events queued between flushes, and the most sources and misses kept while
+// writes fail, beyond which new ones are dropped rather than held in memory
+const MAX_QUEUED: usize = 10_000;
+
+// how often clicks older than the retention window are pruned
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// ClickRecorder counts clicks, hits that are not clicks, and lookups of links
+/// that do not exist, off the request path, and keeps where each click came
+/// from. Redirects queue their short name on a channel without waiting, and
+/// [`ClickRecorder::flush`] adds up the queued events per link and writes them
+/// to the database in one transaction per table, so redirects never wait on
+/// the database write lock. Events queued faster than they are flushed are
+/// dropped. Flushes also prune where clicks came from once they are older than
+/// the retention window, leaving only their clicks per day.
+pub(crate) struct ClickRecorder {
+    sender: mpsc::Sender<Event>,
+    pending: Mutex<Pending>,
+    dropped: AtomicU64,  // events dropped since the last flush
+    retention_days: u32, // the days clicks are kept before they are pruned, or 0 to keep them
+}
+
+enum Event {
+    // the normalized id of a link that was followed, and by whom
+    Click(String, model::ClickSource),
+    // the normalized id of a link that was requested by a prefetch, bot or HEAD request
+    Skip(String),
+    // the short name of a link that does not exist, and when
+    Miss(String, chrono::DateTime<Utc>),
+}
+
+struct Pending {
+    receiver: mpsc::Receiver<Event>,
+    clicks: HashMap<String, u64>, // clicks per normalized id not yet written to the database
+    sources: Vec<(String, model::ClickSource)>, // where those clicks came from, by normalized id
+    skipped: HashMap<String, u64>, // skipped hits per normalized id not yet written to the database
+    misses: HashMap<String, model::WantedLink>, // misses per normalized id not yet written to the database
+    pruned: Option<Instant>,      // when old clicks were last pruned
+}
+
+impl ClickRecorder {
+    pub(crate) fn new(retention_days: u32) -> Self {
+        let (sender, receiver) = mpsc::channel(MAX_QUEUED);
+        Self {
+            sender,
+            dropped: AtomicU64::new(0),
+            retention_days,
+            pending: Mutex::new(Pending {
+                receiver,
+                clicks: HashMap::new(),
+                sources: Vec::new(),
+                skipped: HashMap::new(),
+                misses: HashMap::new(),
+                pruned: None,
+            }),
+        }
+    }
+
+    /// Queues a click on the short name.
+    pub(crate) fn record(&self, short: &str, source: model::ClickSource) {
+        self.send(Event::Click(model::normalized_id(short), source));
+    }
+
+    /// Queues a hit on the short name that is not a click, such as a prefetch.
+    pub(crate) fn record_skipped(&self, short: &str) {
+        self.send(Event::Skip(model::normalized_id(short)));
+    }
+
+    /// Queues a lookup of a short name that has no link.
+    pub(crate) fn record_miss(&self, short: &str) {
+        self.send(Event::Miss(short.to_string(), Utc::now()));
+    }
+
+    // queues an event without waiting, dropping it if the queue is full; the receiver lives as long
+    // as the sender, so the queue is never closed
+    fn send(&self, event: Event) {
+        if self.sender.try_send(event).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Forgets the clicks, skipped hits and misses queued so far without writing them.
+    pub(crate) async fn discard(&self) {
+        let mut pending = self.pending.lock().await;
+        while pending.receiver.try_recv().is_ok() {}
+        self.dropped.store(0, Ordering::Relaxed);
+        pending.clicks.clear();
+        pending.sources.clear();
+        pending.skipped.clear();
+        pending.misses.clear();
+    }
+
+    /// Writes the clicks, skipped hits and misses queued so far to the database,
+    /// returning how many were written. If a write fails its events are kept for
+    /// the next flush.
+    pub(crate) async fn flush(&self, db: &db::Db) -> Result<u64, Box<db::DbError>> {
+        let mut pending = self.pending.lock().await;
+        let Pending {
+            receiver,
+            clicks,
+            sources,
+            skipped,
+            misses,
+            pruned,
+        } = &mut *pending;
+        let mut dropped = self.dropped.swap(0, Ordering::Relaxed);
+        while let Ok(event) = receiver.try_recv() {
+            match event {
+                Event::Click(id, source) => {
+                    *clicks.entry(id.clone()).or_default() += 1;
+                    if sources.len() < MAX_QUEUED {
+                        sources.push((id, source));
+                    }
+                }
+                Event::Skip(id) => *skipped.entry(id).or_default() += 1,
+                Event::Miss(short, seen) => {
+                    let id = model::normalized_id(&short);
+                    if misses.len() >= MAX_QUEUED && !misses.contains_key(&id) {
+                        dropped += 1;
+                        continue;
+                    }
+                    let wanted = misses.entry(id).or_insert_with(|| model::WantedLink {
+                        short: short.clone(),
+                        misses: 0,
+                        last_seen: seen,
+                    });
+                    wanted.short = short;
+                    wanted.misses += 1;
+                    wanted.last_seen = seen;
+                }
+            }
+        }
+
+        if dropped > 0 {
+            tracing::warn!("dropped {dropped} clicks, skipped hits and misses queued faster than they were written");
+        }
+
+        let mut written = 0;
+        if !clicks.is_empty() {
+            db.stats.incr_many(clicks).await?;
+            written += clicks.values().sum::<u64>();
+            clicks.clear();
+        }
+        if !sources.is_empty() {
+            db.clicks.record_many(sources).await?;
+            sources.clear();
+        }
+        if !skipped.is_empty() {
+            db.stats.skip_many(skipped).await?;
+            written += skipped.values().sum::<u64>();
+            skipped.clear();
+        }
+        if !misses.is_empty() {
+            db.misses.record_many(misses).await?;
+            written += misses.values().map(|wanted| wanted.misses).sum::<u64>();
+            misses.clear();
+        }
+        if self.retention_days > 0 && pruned.is_none_or(|pruned| pruned.elapsed() >= PRUNE_INTERVAL) {
+            let before = Utc::now() - chrono::Duration::days(self.retention_days.into());
+            let forgotten = db.clicks.prune(before).await?;
+            if forgotten > 0 {
+                tracing::debug!("pruned {forgotten} clicks from before {before}");
+            }
+            *pruned = Some(Instant::now());
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_flush() -> Result<(), Box<db::DbError>> {
+        let db = db::Db::in_memory().unwrap();
+        let link = model::Link {
+            short: "nyt".to_string(),
+            long: "http://www.nytimes.com".to_string(),
+            created: chrono::Utc::now(),
+            updated: chrono::Utc::now(),
+            status: model::RedirectStatus::default(),
+            max_age: None,
+            passthrough: model::Passthrough::default(),
+            dont_track: false,
+        };
+        db.link.save(&link).await?;
+        db.stats.save(&link.short).await?;
+
+        let recorder = ClickRecorder::new(0);
+        assert_eq!(recorder.flush(&db).await?, 0);
+        let source = model::ClickSource {
+            subnet: Some("192.0.2.0/24".to_string()),
+            referrer: None,
+            agent: model::AgentFamily::Cli,
+            clicked: Utc::now(),
+        };
+        recorder.record("nyt", source.clone());
+        recorder.record("NYT", source.clone());
+        recorder.record_miss("team-notes");
+        recorder.record_miss("TeamNotes");
+        recorder.record_skipped("nyt");
+        assert!(db.stats.load("nyt").await?.unwrap().clicks.is_none());
+
+        assert_eq!(recorder.flush(&db).await?, 5);
+        let stats = db.stats.load("nyt").await?.unwrap();
+        assert_eq!((stats.clicks, stats.skipped), (Some(2), 1));
+        let breakdown = db.clicks.breakdown("nyt", None, 10).await?;
+        assert_eq!(breakdown.subnets[0].clicks, 2);
+        let wanted = db.misses.wanted(10).await?;
+        assert_eq!(wanted.len(), 1);
+        assert_eq!(wanted[0].short, "TeamNotes"); // the most recent spelling
+        assert_eq!(wanted[0].misses, 2);
+        assert_eq!(recorder.flush(&db).await?, 0);
+
+        recorder.record("nyt", source.clone());
+        recorder.record_miss("wiki");
+        recorder.discard().await;
+        assert_eq!(recorder.flush(&db).await?, 0);
+
+        // with a retention window, clicks older than it are pruned but still counted per day
+        let recorder = ClickRecorder::new(30);
+        let old = model::ClickSource {
+            clicked: Utc::now() - chrono::Duration::days(31),
+            ..source
+        };
+        recorder.record("nyt", old);
+        assert_eq!(recorder.flush(&db).await?, 1);
+        assert_eq!(db.clicks.breakdown("nyt", None, 10).await?.subnets[0].clicks, 2);
+        assert_eq!(db.stats.load("nyt").await?.unwrap().clicks, Some(3));
+        let month = db
+            .clicks
+            .daily("nyt", Utc::now().date_naive() - chrono::Duration::days(40))
+            .await?;
+        assert_eq!(month.iter().map(|day| day.clicks).sum::<u64>(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_drops_when_full() -> Result<(), Box<db::DbError>> {
+        let db = db::Db::in_memory().unwrap();
+        let recorder = ClickRecorder::new(0);
+        for _ in 0..MAX_QUEUED + 5 {
+            recorder.record_skipped("nyt");
+        }
+        assert_eq!(recorder.dropped.load(Ordering::Relaxed), 5);
+        assert_eq!(recorder.flush(&db).await?, MAX_QUEUED as u64);
+        assert_eq!(recorder.dropped.load(Ordering::Relaxed), 0);
+
+        // once flushed, there is room again
+        recorder.record_skipped("nyt");
+        assert_eq!(recorder.flush(&db).await?, 1);
+        Ok(())
+    }
+}
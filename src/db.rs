@@ -1,10 +1,17 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use rusqlite::fallible_iterator::FallibleIterator;
-use rusqlite::params;
+use rusqlite::{OptionalExtension, params};
 use tokio::sync::Mutex;
 
-use crate::model;
+use crate::{metrics, model};
+
+// the most short names without a link whose lookups are kept, beyond which those seen least recently are forgotten
+const MAX_MISSES: usize = 10_000;
+
+// the columns of Links read by read_link, in order
+const LINK_COLUMNS: &str =
+    "short, long, created, updated, status, max_age, path_mode, query_mode, query_allow, dont_track";
 
 #[derive(Clone, Debug)]
 pub struct DbError {
@@ -39,10 +46,34 @@ pub struct StatsDAO {
     connection: Arc<Mutex<rusqlite::Connection>>,
 }
 
+#[derive(Clone, Debug)]
+pub struct MissesDAO {
+    connection: Arc<Mutex<rusqlite::Connection>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ClicksDAO {
+    connection: Arc<Mutex<rusqlite::Connection>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ChecksDAO {
+    connection: Arc<Mutex<rusqlite::Connection>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TokensDAO {
+    connection: Arc<Mutex<rusqlite::Connection>>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Db {
     pub link: LinkDAO,
     pub stats: StatsDAO,
+    pub misses: MissesDAO,
+    pub clicks: ClicksDAO,
+    pub checks: ChecksDAO,
+    pub tokens: TokensDAO,
 }
 
 impl LinkDAO {
@@ -51,20 +82,10 @@ impl LinkDAO {
     }
 
     pub async fn save(&self, link: &model::Link) -> Result<(), Box<DbError>> {
+        let _timer = metrics::time_db("links.save");
         let conn = self.connection.lock().await;
 
-        let rows_affected = conn
-            .execute(
-                r#"INSERT OR REPLACE INTO Links (ID, short, long, created, updated) values (?1, ?2, ?3, ?4, ?5)"#,
-                params![
-                    model::normalized_id(&link.short),
-                    link.short,
-                    link.long,
-                    link.created,
-                    link.updated
-                ],
-            )
-            .map_err(DbError::from)?;
+        let rows_affected = write_link(&conn, "INSERT OR REPLACE", link).map_err(DbError::from)?;
         if rows_affected != 1 {
             return Err(Box::new(DbError::new(format!(
                 "expected to affect 1 row, affected {}",
@@ -75,7 +96,23 @@ impl LinkDAO {
         Ok(())
     }
 
+    /// Saves a new link, returning false without saving it if a link with the
+    /// same normalized id already exists.
+    pub async fn insert(&self, link: &model::Link) -> Result<bool, Box<DbError>> {
+        let _timer = metrics::time_db("links.insert");
+        let conn = self.connection.lock().await;
+
+        match write_link(&conn, "INSERT", link) {
+            Ok(_) => Ok(true),
+            Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::ConstraintViolation => {
+                Ok(false)
+            }
+            Err(e) => Err(Box::new(DbError::from(e))),
+        }
+    }
+
     pub async fn delete(&self, short: &str) -> Result<(), Box<DbError>> {
+        let _timer = metrics::time_db("links.delete");
         let conn = self.connection.lock().await;
 
         let rows_affected = conn
@@ -92,78 +129,262 @@ impl LinkDAO {
     }
 
     pub async fn load(&self, short: &str) -> Result<model::Link, Box<DbError>> {
+        let _timer = metrics::time_db("links.load");
         let conn = self.connection.lock().await;
 
         let mut stmt = conn
-            .prepare("SELECT short, long, created, updated FROM Links WHERE ID = ?1")
+            .prepare(&format!("SELECT {LINK_COLUMNS} FROM Links WHERE ID = ?1"))
             .map_err(DbError::from)?;
-        stmt.query_one([model::normalized_id(short)], |row| {
-            Ok(model::Link {
-                short: row.get(0)?,
-                long: row.get(1)?,
-                created: row.get(2)?,
-                updated: row.get(3)?,
-            })
-        })
-        .map_err(|e| Box::new(DbError::from(e)))
+        stmt.query_one([model::normalized_id(short)], read_link)
+            .map_err(|e| Box::new(DbError::from(e)))
+    }
+
+    /// Like [`LinkDAO::load`], but a link that does not exist is `Ok(None)` rather than an error.
+    pub async fn find(&self, short: &str) -> Result<Option<model::Link>, Box<DbError>> {
+        let _timer = metrics::time_db("links.find");
+        let conn = self.connection.lock().await;
+
+        let mut stmt = conn
+            .prepare(&format!("SELECT {LINK_COLUMNS} FROM Links WHERE ID = ?1"))
+            .map_err(DbError::from)?;
+        stmt.query_one([model::normalized_id(short)], read_link)
+            .optional()
+            .map_err(|e| Box::new(DbError::from(e)))
     }
 
     pub async fn load_all(&self) -> Result<Vec<model::Link>, Box<DbError>> {
+        let _timer = metrics::time_db("links.load_all");
         let conn = self.connection.lock().await;
 
         let mut stmt: rusqlite::Statement<'_> = conn
-            .prepare(r#"SELECT short, long, created, updated FROM Links"#)
+            .prepare(&format!("SELECT {LINK_COLUMNS} FROM Links"))
             .map_err(DbError::from)?;
         let rows = stmt.query([]).map_err(DbError::from)?;
-        let results: Vec<model::Link> = rows
-            .map(|row| {
-                Ok(model::Link {
-                    short: row.get(0)?,
-                    long: row.get(1)?,
-                    created: row.get(2)?,
-                    updated: row.get(3)?,
-                })
-            })
-            .collect()
-            .map_err(|e| Box::new(DbError::from(e)))?;
+        let results: Vec<model::Link> = rows.map(read_link).collect().map_err(|e| Box::new(DbError::from(e)))?;
 
         Ok(results)
     }
 
-    pub async fn most_popular(&self) -> Result<Vec<(model::Link, model::ClickStats)>, Box<DbError>> {
+    /// Returns the number of links saved.
+    pub async fn count(&self) -> Result<usize, Box<DbError>> {
+        let _timer = metrics::time_db("links.count");
         let conn = self.connection.lock().await;
 
-        let mut stmt: rusqlite::Statement<'_> = conn
-            .prepare(
-                r#"SELECT l.short, l.long, l.created, l.updated, s.created, s.clicks
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM Links", [], |row| row.get(0))
+            .map_err(DbError::from)?;
+        Ok(count as usize)
+    }
+
+    /// Returns a page of links in short name order, with the number of links on all pages. Only
+    /// links whose short name or destination link contains `query`, ignoring case, are listed if
+    /// it is given, and only templates or only links that are not templates if `template` is.
+    pub async fn page(
+        &self,
+        query: Option<&str>,
+        template: Option<bool>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<model::Link>, usize), Box<DbError>> {
+        let _timer = metrics::time_db("links.page");
+        let conn = self.connection.lock().await;
+
+        let filter = "(?1 IS NULL OR instr(lower(short), ?1) > 0 OR instr(lower(long), ?1) > 0)
+            AND (?2 IS NULL OR (instr(long, '{{') > 0) = ?2)";
+        let query = query.map(str::to_lowercase);
+        let total: i64 = conn
+            .query_row(
+                &format!("SELECT COUNT(*) FROM Links WHERE {filter}"),
+                params![query, template],
+                |row| row.get(0),
+            )
+            .map_err(DbError::from)?;
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {LINK_COLUMNS} FROM Links WHERE {filter} ORDER BY ID LIMIT ?3 OFFSET ?4"
+            ))
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query(params![
+                query,
+                template,
+                limit as i64,
+                i64::try_from(offset).unwrap_or(i64::MAX)
+            ])
+            .map_err(DbError::from)?;
+        let results: Result<Vec<model::Link>, rusqlite::Error> = rows.map(read_link).collect();
+        Ok((results.map_err(DbError::from)?, total as usize))
+    }
+
+    /// Returns the most clicked links with their click stats, where the clicks are those of
+    /// the last `days` days, or of all time.
+    pub async fn most_popular(
+        &self,
+        days: Option<u32>,
+        limit: usize,
+    ) -> Result<Vec<(model::Link, model::ClickStats)>, Box<DbError>> {
+        let _timer = metrics::time_db("links.most_popular");
+        let conn = self.connection.lock().await;
+
+        let read = |row: &rusqlite::Row<'_>| {
+            Ok((
+                read_link(row)?,
+                model::ClickStats {
+                    created: row.get(10)?,
+                    clicks: row.get(11)?,
+                    skipped: row.get(12)?,
+                },
+            ))
+        };
+        let results: Result<Vec<(model::Link, model::ClickStats)>, rusqlite::Error> = match days {
+            Some(days) => {
+                // clicks in a window are summed from the daily rollups
+                let since = chrono::Utc::now().date_naive() - chrono::Duration::days(i64::from(days.max(1)) - 1);
+                let mut stmt = conn
+                    .prepare(
+                        r#"SELECT l.short, l.long, l.created, l.updated, l.status, l.max_age, l.path_mode, l.query_mode,
+        l.query_allow, l.dont_track, s.created, SUM(d.clicks) AS recent, s.skipped
+        FROM Links l
+        INNER JOIN Stats s ON s.ID = l.ID
+        INNER JOIN DailyClicks d ON d.ID = l.ID
+        WHERE d.day >= ?1
+        GROUP BY l.ID
+        ORDER BY recent DESC, l.short
+        LIMIT ?2"#,
+                    )
+                    .map_err(DbError::from)?;
+                stmt.query(params![since, limit as i64])
+                    .map_err(DbError::from)?
+                    .map(read)
+                    .collect()
+            }
+            None => {
+                let mut stmt = conn
+                    .prepare(
+                        r#"SELECT l.short, l.long, l.created, l.updated, l.status, l.max_age, l.path_mode, l.query_mode,
+        l.query_allow, l.dont_track, s.created, s.clicks, s.skipped
         FROM Links l
         INNER JOIN Stats s ON s.ID = l.ID
         WHERE s.clicks NOT NULL
-        ORDER BY s.clicks DESC
-        LIMIT 10"#,
+        ORDER BY s.clicks DESC, l.short
+        LIMIT ?1"#,
+                    )
+                    .map_err(DbError::from)?;
+                stmt.query(params![limit as i64])
+                    .map_err(DbError::from)?
+                    .map(read)
+                    .collect()
+            }
+        };
+        results.map_err(|e| Box::new(DbError::from(e)))
+    }
+
+    /// Returns every link with its clicks and the last day it was clicked, ordered by short name.
+    pub async fn load_all_with_usage(&self) -> Result<Vec<(model::Link, model::LinkUsage)>, Box<DbError>> {
+        let _timer = metrics::time_db("links.load_all_with_usage");
+        let conn = self.connection.lock().await;
+
+        let mut stmt = conn
+            .prepare(
+                r#"SELECT l.short, l.long, l.created, l.updated, l.status, l.max_age, l.path_mode, l.query_mode,
+        l.query_allow, l.dont_track, COALESCE(s.clicks, 0), (SELECT MAX(d.day) FROM DailyClicks d WHERE d.ID = l.ID)
+        FROM Links l
+        LEFT JOIN Stats s ON s.ID = l.ID
+        ORDER BY l.short"#,
             )
             .map_err(DbError::from)?;
-
         let rows = stmt.query([]).map_err(DbError::from)?;
-        let results: Vec<(model::Link, model::ClickStats)> = rows
+        let results: Result<Vec<(model::Link, model::LinkUsage)>, rusqlite::Error> = rows
             .map(|row| {
                 Ok((
-                    model::Link {
-                        short: row.get(0)?,
-                        long: row.get(1)?,
-                        created: row.get(2)?,
-                        updated: row.get(3)?,
-                    },
-                    model::ClickStats {
-                        created: row.get(4)?,
-                        clicks: row.get(5)?,
+                    read_link(row)?,
+                    model::LinkUsage {
+                        clicks: row.get(10)?,
+                        last_clicked: row.get(11)?,
                     },
                 ))
             })
-            .collect()
-            .map_err(|e| Box::new(DbError::from(e)))?;
+            .collect();
+        results.map_err(|e| Box::new(DbError::from(e)))
+    }
 
-        Ok(results)
+    /// Deletes the link `from`, adding its clicks to the link `into`, in a single transaction.
+    pub async fn merge(&self, from: &str, into: &str) -> Result<(), Box<DbError>> {
+        let _timer = metrics::time_db("links.merge");
+        let mut conn = self.connection.lock().await;
+
+        let (from, into) = (model::normalized_id(from), model::normalized_id(into));
+        if from == into {
+            return Err(Box::new(DbError::new(format!("cannot merge {from} into itself"))));
+        }
+        let tx = conn.transaction().map_err(DbError::from)?;
+        let exists = |id: &str| -> Result<bool, rusqlite::Error> {
+            tx.query_row("SELECT COUNT(*) FROM Links WHERE ID = ?1", [id], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map(|count| count == 1)
+        };
+        for id in [&from, &into] {
+            if !exists(id).map_err(DbError::from)? {
+                return Err(Box::new(DbError::new(format!("no link {id}"))));
+            }
+        }
+        let (clicks, skipped): (Option<i64>, i64) = tx
+            .query_row("SELECT clicks, skipped FROM Stats WHERE ID = ?1", [&from], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .optional()
+            .map_err(DbError::from)?
+            .unwrap_or((None, 0));
+        tx.execute(
+            r#"UPDATE Stats SET clicks = CASE WHEN ?2 IS NULL THEN clicks ELSE COALESCE(clicks, 0) + ?2 END,
+            skipped = skipped + ?3 WHERE ID = ?1"#,
+            params![into, clicks, skipped],
+        )
+        .map_err(DbError::from)?;
+        tx.execute(
+            r#"INSERT INTO DailyClicks (ID, day, clicks) SELECT ?2, day, clicks FROM DailyClicks WHERE ID = ?1
+            ON CONFLICT(ID, day) DO UPDATE SET clicks = clicks + excluded.clicks"#,
+            params![from, into],
+        )
+        .map_err(DbError::from)?;
+        tx.execute("DELETE FROM DailyClicks WHERE ID = ?1", [&from])
+            .map_err(DbError::from)?;
+        tx.execute("UPDATE Clicks SET ID = ?2 WHERE ID = ?1", params![from, into])
+            .map_err(DbError::from)?;
+        tx.execute("DELETE FROM Stats WHERE ID = ?1", [&from])
+            .map_err(DbError::from)?;
+        tx.execute("DELETE FROM LinkChecks WHERE ID = ?1", [&from])
+            .map_err(DbError::from)?;
+        tx.execute("DELETE FROM Links WHERE ID = ?1", [&from])
+            .map_err(DbError::from)?;
+        tx.commit().map_err(DbError::from)?;
+        Ok(())
+    }
+
+    /// Returns the most recently created links, newest first.
+    pub async fn recently_created(&self, limit: usize) -> Result<Vec<model::Link>, Box<DbError>> {
+        self.recent("created", limit).await
+    }
+
+    /// Returns the most recently edited links, newest first.
+    pub async fn recently_updated(&self, limit: usize) -> Result<Vec<model::Link>, Box<DbError>> {
+        self.recent("updated", limit).await
+    }
+
+    // returns the links with the latest values of the created or updated column
+    async fn recent(&self, column: &str, limit: usize) -> Result<Vec<model::Link>, Box<DbError>> {
+        let _timer = metrics::time_db("links.recent");
+        let conn = self.connection.lock().await;
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {LINK_COLUMNS} FROM Links ORDER BY {column} DESC, short LIMIT ?1"
+            ))
+            .map_err(DbError::from)?;
+        let rows = stmt.query([limit as i64]).map_err(DbError::from)?;
+        let results: Result<Vec<model::Link>, rusqlite::Error> = rows.map(read_link).collect();
+        results.map_err(|e| Box::new(DbError::from(e)))
     }
 }
 
@@ -173,6 +394,7 @@ impl StatsDAO {
     }
 
     pub async fn save(&self, short: &str) -> Result<(), Box<DbError>> {
+        let _timer = metrics::time_db("stats.save");
         let conn = self.connection.lock().await;
 
         let rows_affected = conn
@@ -192,6 +414,7 @@ impl StatsDAO {
     }
 
     pub async fn incr(&self, short: &str) -> Result<(), Box<DbError>> {
+        let _timer = metrics::time_db("stats.incr");
         let conn = self.connection.lock().await;
 
         let rows_affected = conn
@@ -209,16 +432,67 @@ impl StatsDAO {
         Ok(())
     }
 
+    /// Adds the given number of clicks to each link, keyed by normalized id, in a single transaction.
+    pub async fn incr_many(&self, clicks: &HashMap<String, u64>) -> Result<(), Box<DbError>> {
+        let _timer = metrics::time_db("stats.incr_many");
+        let mut conn = self.connection.lock().await;
+
+        let tx = conn.transaction().map_err(DbError::from)?;
+        {
+            let mut stmt = tx
+                .prepare(r#"UPDATE Stats SET clicks = IFNULL(clicks, 0) + ?2 WHERE stats.ID = ?1"#)
+                .map_err(DbError::from)?;
+            for (id, count) in clicks {
+                stmt.execute(params![id, *count as i64]).map_err(DbError::from)?;
+            }
+        }
+        tx.commit().map_err(DbError::from)?;
+        Ok(())
+    }
+
+    /// Adds the given number of hits that were not counted as clicks, such as
+    /// prefetches and bots, to each link, keyed by normalized id, in a single transaction.
+    pub async fn skip_many(&self, skipped: &HashMap<String, u64>) -> Result<(), Box<DbError>> {
+        let _timer = metrics::time_db("stats.skip_many");
+        let mut conn = self.connection.lock().await;
+
+        let tx = conn.transaction().map_err(DbError::from)?;
+        {
+            let mut stmt = tx
+                .prepare(r#"UPDATE Stats SET skipped = skipped + ?2 WHERE stats.ID = ?1"#)
+                .map_err(DbError::from)?;
+            for (id, count) in skipped {
+                stmt.execute(params![id, *count as i64]).map_err(DbError::from)?;
+            }
+        }
+        tx.commit().map_err(DbError::from)?;
+        Ok(())
+    }
+
+    /// Forgets the clicks and skipped hits of every link, returning the number of links that had any.
+    pub async fn purge(&self) -> Result<usize, Box<DbError>> {
+        let _timer = metrics::time_db("stats.purge");
+        let conn = self.connection.lock().await;
+
+        conn.execute(
+            r#"UPDATE Stats SET clicks = NULL, skipped = 0 WHERE clicks NOT NULL OR skipped != 0"#,
+            (),
+        )
+        .map_err(|e| Box::new(DbError::from(e)))
+    }
+
     pub async fn load(&self, short: &str) -> Result<Option<model::ClickStats>, Box<DbError>> {
+        let _timer = metrics::time_db("stats.load");
         let conn = self.connection.lock().await;
 
         let mut stmt: rusqlite::Statement<'_> = conn
-            .prepare(r#"SELECT created, clicks FROM Stats WHERE ID = ?1"#)
+            .prepare(r#"SELECT created, clicks, skipped FROM Stats WHERE ID = ?1"#)
             .map_err(DbError::from)?;
         match stmt.query_one([model::normalized_id(short)], |row| {
             Ok(model::ClickStats {
                 created: row.get(0)?,
                 clicks: row.get(1)?,
+                skipped: row.get(2)?,
             })
         }) {
             Ok(stats) => Ok(Some(stats)),
@@ -230,10 +504,11 @@ impl StatsDAO {
     }
 
     pub async fn load_all(&self) -> Result<Vec<model::ClickStats>, Box<DbError>> {
+        let _timer = metrics::time_db("stats.load_all");
         let conn = self.connection.lock().await;
 
         let mut stmt: rusqlite::Statement<'_> = conn
-            .prepare(r#"SELECT created, clicks FROM Stats"#)
+            .prepare(r#"SELECT created, clicks, skipped FROM Stats"#)
             .map_err(DbError::from)?;
         let rows = stmt.query([]).map_err(DbError::from)?;
         let results: Result<Vec<model::ClickStats>, rusqlite::Error> = rows
@@ -241,6 +516,7 @@ impl StatsDAO {
                 Ok(model::ClickStats {
                     created: row.get(0)?,
                     clicks: row.get(1)?,
+                    skipped: row.get(2)?,
                 })
             })
             .collect();
@@ -248,6 +524,7 @@ impl StatsDAO {
     }
 
     pub async fn delete(&self, short: &str) -> Result<(), Box<DbError>> {
+        let _timer = metrics::time_db("stats.delete");
         let conn = self.connection.lock().await;
 
         let rows_affected = conn
@@ -264,6 +541,408 @@ impl StatsDAO {
     }
 }
 
+impl MissesDAO {
+    fn new(connection: Arc<Mutex<rusqlite::Connection>>) -> Self {
+        Self { connection }
+    }
+
+    /// Adds lookups of short names that have no link, keyed by normalized id, in a single transaction,
+    /// forgetting the names seen least recently once there are more than can be kept.
+    pub async fn record_many(&self, misses: &HashMap<String, model::WantedLink>) -> Result<(), Box<DbError>> {
+        let _timer = metrics::time_db("misses.record_many");
+        let mut conn = self.connection.lock().await;
+
+        let tx = conn.transaction().map_err(DbError::from)?;
+        {
+            let mut stmt = tx
+                .prepare(
+                    r#"INSERT INTO Misses (ID, short, count, last_seen) values (?1, ?2, ?3, ?4)
+                ON CONFLICT(ID) DO UPDATE SET short = excluded.short, count = count + excluded.count, last_seen = excluded.last_seen"#,
+                )
+                .map_err(DbError::from)?;
+            for (id, wanted) in misses {
+                stmt.execute(params![id, wanted.short, wanted.misses as i64, wanted.last_seen])
+                    .map_err(DbError::from)?;
+            }
+        }
+        tx.execute(
+            "DELETE FROM Misses WHERE ID IN (SELECT ID FROM Misses ORDER BY last_seen DESC LIMIT -1 OFFSET ?1)",
+            [MAX_MISSES as i64],
+        )
+        .map_err(DbError::from)?;
+        tx.commit().map_err(DbError::from)?;
+        Ok(())
+    }
+
+    /// Forgets all missed lookups, returning how many there were.
+    pub async fn purge(&self) -> Result<usize, Box<DbError>> {
+        let _timer = metrics::time_db("misses.purge");
+        let conn = self.connection.lock().await;
+
+        conn.execute("DELETE FROM Misses", ())
+            .map_err(|e| Box::new(DbError::from(e)))
+    }
+
+    /// Returns the most looked up short names that still have no link.
+    pub async fn wanted(&self, limit: usize) -> Result<Vec<model::WantedLink>, Box<DbError>> {
+        let _timer = metrics::time_db("misses.wanted");
+        let conn = self.connection.lock().await;
+
+        let mut stmt: rusqlite::Statement<'_> = conn
+            .prepare(
+                r#"SELECT m.short, m.count, m.last_seen
+        FROM Misses m
+        WHERE NOT EXISTS (SELECT 1 FROM Links l WHERE l.ID = m.ID)
+        ORDER BY m.count DESC, m.last_seen DESC
+        LIMIT ?1"#,
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt.query([limit as i64]).map_err(DbError::from)?;
+        let results: Result<Vec<model::WantedLink>, rusqlite::Error> = rows
+            .map(|row| {
+                Ok(model::WantedLink {
+                    short: row.get(0)?,
+                    misses: row.get::<_, i64>(1)? as u64,
+                    last_seen: row.get(2)?,
+                })
+            })
+            .collect();
+        results.map_err(|e| Box::new(DbError::from(e)))
+    }
+}
+
+impl ClicksDAO {
+    fn new(connection: Arc<Mutex<rusqlite::Connection>>) -> Self {
+        Self { connection }
+    }
+
+    /// Adds where individual clicks came from, keyed by normalized id, and rolls them up into
+    /// their link's clicks per day, in a single transaction.
+    pub async fn record_many(&self, clicks: &[(String, model::ClickSource)]) -> Result<(), Box<DbError>> {
+        let _timer = metrics::time_db("clicks.record_many");
+        let mut conn = self.connection.lock().await;
+
+        let tx = conn.transaction().map_err(DbError::from)?;
+        {
+            let mut stmt = tx
+                .prepare(r#"INSERT INTO Clicks (ID, clicked, subnet, referrer, agent) values (?1, ?2, ?3, ?4, ?5)"#)
+                .map_err(DbError::from)?;
+            let mut daily = tx
+                .prepare(
+                    r#"INSERT INTO DailyClicks (ID, day, clicks) values (?1, ?2, 1)
+                ON CONFLICT(ID, day) DO UPDATE SET clicks = clicks + 1"#,
+                )
+                .map_err(DbError::from)?;
+            for (id, source) in clicks {
+                daily
+                    .execute(params![id, source.clicked.date_naive()])
+                    .map_err(DbError::from)?;
+                stmt.execute(params![
+                    id,
+                    source.clicked,
+                    source.subnet,
+                    source.referrer,
+                    source.agent.as_str()
+                ])
+                .map_err(DbError::from)?;
+            }
+        }
+        tx.commit().map_err(DbError::from)?;
+        Ok(())
+    }
+
+    /// Returns the most common referrers and subnets, and the clicks per user agent family, of the
+    /// clicks on the short name in the last `days` days, or of all its clicks.
+    pub async fn breakdown(
+        &self,
+        short: &str,
+        days: Option<u32>,
+        limit: usize,
+    ) -> Result<model::ClickBreakdown, Box<DbError>> {
+        let _timer = metrics::time_db("clicks.breakdown");
+        let conn = self.connection.lock().await;
+
+        // a window reaching back before the earliest time there can be is all time
+        let since = days.and_then(|days| chrono::Utc::now().checked_sub_signed(chrono::Duration::days(days.into())));
+        let id = model::normalized_id(short);
+        // a negative limit is no limit
+        let tally = |column: &str, limit: i64| -> Result<Vec<model::Tally>, rusqlite::Error> {
+            let mut stmt = conn.prepare(&format!(
+                r#"SELECT {column}, COUNT(*) AS n FROM Clicks
+        WHERE ID = ?1 AND (?2 IS NULL OR clicked >= ?2) AND {column} IS NOT NULL
+        GROUP BY {column}
+        ORDER BY n DESC, {column}
+        LIMIT ?3"#
+            ))?;
+            let rows = stmt.query(params![id, since, limit])?;
+            rows.map(|row| {
+                Ok(model::Tally {
+                    name: row.get(0)?,
+                    clicks: row.get::<_, i64>(1)? as u64,
+                })
+            })
+            .collect()
+        };
+        Ok(model::ClickBreakdown {
+            days,
+            referrers: tally("referrer", limit as i64).map_err(DbError::from)?,
+            subnets: tally("subnet", limit as i64).map_err(DbError::from)?,
+            agents: tally("agent", -1).map_err(DbError::from)?,
+        })
+    }
+
+    /// Returns the clicks per day on the short name from the day `since` on, oldest first,
+    /// leaving out days without clicks.
+    pub async fn daily(&self, short: &str, since: chrono::NaiveDate) -> Result<Vec<model::DailyClicks>, Box<DbError>> {
+        let _timer = metrics::time_db("clicks.daily");
+        let conn = self.connection.lock().await;
+
+        let mut stmt = conn
+            .prepare("SELECT day, clicks FROM DailyClicks WHERE ID = ?1 AND day >= ?2 ORDER BY day")
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query(params![model::normalized_id(short), since])
+            .map_err(DbError::from)?;
+        let results: Result<Vec<model::DailyClicks>, rusqlite::Error> = rows
+            .map(|row| {
+                Ok(model::DailyClicks {
+                    day: row.get(0)?,
+                    clicks: row.get::<_, i64>(1)? as u64,
+                })
+            })
+            .collect();
+        results.map_err(|e| Box::new(DbError::from(e)))
+    }
+
+    /// Returns when the short name was last clicked, if its clicks are recorded, or the start of
+    /// the last day it was clicked on once the clicks themselves were pruned.
+    pub async fn last_clicked(&self, short: &str) -> Result<Option<chrono::DateTime<chrono::Utc>>, Box<DbError>> {
+        let _timer = metrics::time_db("clicks.last_clicked");
+        let conn = self.connection.lock().await;
+
+        let id = model::normalized_id(short);
+        let clicked: Option<chrono::DateTime<chrono::Utc>> = conn
+            .query_row("SELECT MAX(clicked) FROM Clicks WHERE ID = ?1", params![id], |row| {
+                row.get(0)
+            })
+            .map_err(DbError::from)?;
+        if clicked.is_some() {
+            return Ok(clicked);
+        }
+        let day: Option<chrono::NaiveDate> = conn
+            .query_row("SELECT MAX(day) FROM DailyClicks WHERE ID = ?1", params![id], |row| {
+                row.get(0)
+            })
+            .map_err(DbError::from)?;
+        Ok(day.map(|day| day.and_time(chrono::NaiveTime::MIN).and_utc()))
+    }
+
+    /// Forgets where the clicks before `before` came from, keeping their clicks per day,
+    /// returning how many clicks were forgotten.
+    pub async fn prune(&self, before: chrono::DateTime<chrono::Utc>) -> Result<usize, Box<DbError>> {
+        let _timer = metrics::time_db("clicks.prune");
+        let conn = self.connection.lock().await;
+
+        conn.execute("DELETE FROM Clicks WHERE clicked < ?1", params![before])
+            .map_err(|e| Box::new(DbError::from(e)))
+    }
+
+    /// Forgets the clicks on the short name and their rollups.
+    pub async fn delete(&self, short: &str) -> Result<usize, Box<DbError>> {
+        let _timer = metrics::time_db("clicks.delete");
+        let conn = self.connection.lock().await;
+
+        let id = model::normalized_id(short);
+        conn.execute("DELETE FROM DailyClicks WHERE ID = ?1", params![id])
+            .map_err(DbError::from)?;
+        conn.execute("DELETE FROM Clicks WHERE ID = ?1", params![id])
+            .map_err(|e| Box::new(DbError::from(e)))
+    }
+
+    /// Forgets all clicks and their rollups, returning how many clicks there were.
+    pub async fn purge(&self) -> Result<usize, Box<DbError>> {
+        let _timer = metrics::time_db("clicks.purge");
+        let conn = self.connection.lock().await;
+
+        conn.execute("DELETE FROM DailyClicks", ()).map_err(DbError::from)?;
+        conn.execute("DELETE FROM Clicks", ())
+            .map_err(|e| Box::new(DbError::from(e)))
+    }
+}
+
+impl ChecksDAO {
+    fn new(connection: Arc<Mutex<rusqlite::Connection>>) -> Self {
+        Self { connection }
+    }
+
+    /// Saves the result of checking the destination of the short name, replacing the last one.
+    pub async fn save(&self, short: &str, check: &model::LinkCheck) -> Result<(), Box<DbError>> {
+        let _timer = metrics::time_db("checks.save");
+        let conn = self.connection.lock().await;
+
+        conn.execute(
+            r#"INSERT OR REPLACE INTO LinkChecks (ID, status, location, error, checked) values (?1, ?2, ?3, ?4, ?5)"#,
+            params![
+                model::normalized_id(short),
+                check.status,
+                check.location,
+                check.error,
+                check.checked
+            ],
+        )
+        .map_err(DbError::from)?;
+        Ok(())
+    }
+
+    /// Returns the last check of the destination of the short name, if it has been checked.
+    pub async fn load(&self, short: &str) -> Result<Option<model::LinkCheck>, Box<DbError>> {
+        let _timer = metrics::time_db("checks.load");
+        let conn = self.connection.lock().await;
+
+        conn.query_row(
+            "SELECT status, location, error, checked FROM LinkChecks WHERE ID = ?1",
+            params![model::normalized_id(short)],
+            read_check,
+        )
+        .optional()
+        .map_err(|e| Box::new(DbError::from(e)))
+    }
+
+    /// Returns the last check of every checked link, keyed by normalized id.
+    pub async fn load_all(&self) -> Result<HashMap<String, model::LinkCheck>, Box<DbError>> {
+        let _timer = metrics::time_db("checks.load_all");
+        let conn = self.connection.lock().await;
+
+        let mut stmt = conn
+            .prepare("SELECT ID, status, location, error, checked FROM LinkChecks")
+            .map_err(DbError::from)?;
+        let rows = stmt.query([]).map_err(DbError::from)?;
+        let results: Result<HashMap<String, model::LinkCheck>, rusqlite::Error> = rows
+            .map(|row| {
+                Ok((
+                    row.get(0)?,
+                    model::LinkCheck::new(row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?),
+                ))
+            })
+            .collect();
+        results.map_err(|e| Box::new(DbError::from(e)))
+    }
+
+    /// Forgets the last check of the short name, as when its destination changes.
+    pub async fn delete(&self, short: &str) -> Result<(), Box<DbError>> {
+        let _timer = metrics::time_db("checks.delete");
+        let conn = self.connection.lock().await;
+
+        conn.execute(
+            "DELETE FROM LinkChecks WHERE ID = ?1",
+            params![model::normalized_id(short)],
+        )
+        .map_err(DbError::from)?;
+        Ok(())
+    }
+}
+
+// the columns of ApiTokens read by read_token, in order
+const TOKEN_COLUMNS: &str = "ID, name, scope, created, expires, last_used";
+
+impl TokensDAO {
+    fn new(connection: Arc<Mutex<rusqlite::Connection>>) -> Self {
+        Self { connection }
+    }
+
+    /// Saves a new API token along with the hash of the token itself.
+    pub async fn save(&self, token: &model::ApiToken, hash: &str) -> Result<(), Box<DbError>> {
+        let _timer = metrics::time_db("tokens.save");
+        let conn = self.connection.lock().await;
+
+        conn.execute(
+            r#"INSERT INTO ApiTokens (ID, name, hash, scope, created, expires, last_used) values (?1, ?2, ?3, ?4, ?5, ?6, ?7)"#,
+            params![
+                token.id,
+                token.name,
+                hash,
+                token.scope.as_str(),
+                token.created,
+                token.expires,
+                token.last_used
+            ],
+        )
+        .map_err(DbError::from)?;
+        Ok(())
+    }
+
+    /// Returns the API token with the hash, if there is one.
+    pub async fn find_by_hash(&self, hash: &str) -> Result<Option<model::ApiToken>, Box<DbError>> {
+        let _timer = metrics::time_db("tokens.find_by_hash");
+        let conn = self.connection.lock().await;
+
+        conn.query_row(
+            &format!("SELECT {TOKEN_COLUMNS} FROM ApiTokens WHERE hash = ?1"),
+            params![hash],
+            read_token,
+        )
+        .optional()
+        .map_err(|e| Box::new(DbError::from(e)))
+    }
+
+    /// Returns every API token, oldest first.
+    pub async fn load_all(&self) -> Result<Vec<model::ApiToken>, Box<DbError>> {
+        let _timer = metrics::time_db("tokens.load_all");
+        let conn = self.connection.lock().await;
+
+        let mut stmt = conn
+            .prepare(&format!("SELECT {TOKEN_COLUMNS} FROM ApiTokens ORDER BY created, ID"))
+            .map_err(DbError::from)?;
+        let rows = stmt.query([]).map_err(DbError::from)?;
+        let results: Result<Vec<model::ApiToken>, rusqlite::Error> = rows.map(read_token).collect();
+        results.map_err(|e| Box::new(DbError::from(e)))
+    }
+
+    /// Records that the API token was used.
+    pub async fn touch(&self, id: &str, used: chrono::DateTime<chrono::Utc>) -> Result<(), Box<DbError>> {
+        let _timer = metrics::time_db("tokens.touch");
+        let conn = self.connection.lock().await;
+
+        conn.execute("UPDATE ApiTokens SET last_used = ?2 WHERE ID = ?1", params![id, used])
+            .map_err(DbError::from)?;
+        Ok(())
+    }
+
+    /// Revokes the API token, returning whether there was one with the id.
+    pub async fn delete(&self, id: &str) -> Result<bool, Box<DbError>> {
+        let _timer = metrics::time_db("tokens.delete");
+        let conn = self.connection.lock().await;
+
+        let rows_affected = conn
+            .execute("DELETE FROM ApiTokens WHERE ID = ?1", params![id])
+            .map_err(DbError::from)?;
+        Ok(rows_affected == 1)
+    }
+}
+
+fn read_token(row: &rusqlite::Row<'_>) -> Result<model::ApiToken, rusqlite::Error> {
+    let scope: String = row.get(2)?;
+    Ok(model::ApiToken {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        scope: scope
+            .parse()
+            .map_err(|e: String| rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, e.into()))?,
+        created: row.get(3)?,
+        expires: row.get(4)?,
+        last_used: row.get(5)?,
+    })
+}
+
+fn read_check(row: &rusqlite::Row<'_>) -> Result<model::LinkCheck, rusqlite::Error> {
+    Ok(model::LinkCheck::new(
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+    ))
+}
+
 fn create_link_table(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
     conn.execute(
         r#"create table if not exists Links(
@@ -280,6 +959,85 @@ fn create_link_table(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error>
     Ok(())
 }
 
+// adds a column to an existing table, for databases created before the column was
+fn add_column(conn: &rusqlite::Connection, table: &str, column: &str, definition: &str) -> Result<(), rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(column));
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"), ())?;
+    }
+
+    Ok(())
+}
+
+fn migrate_link_table(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+    add_column(conn, "Links", "status", "INTEGER NOT NULL DEFAULT 302")?; // HTTP redirect status
+    add_column(conn, "Links", "max_age", "INTEGER")?; // seconds the redirect may be cached
+    add_column(conn, "Links", "path_mode", "TEXT NOT NULL DEFAULT 'append'")?;
+    add_column(conn, "Links", "query_mode", "TEXT NOT NULL DEFAULT 'pass'")?;
+    add_column(conn, "Links", "query_allow", "TEXT NOT NULL DEFAULT ''")?; // comma separated query parameter names
+    add_column(conn, "Links", "dont_track", "INTEGER NOT NULL DEFAULT 0")?; // boolean
+
+    Ok(())
+}
+
+fn migrate_stats_table(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+    add_column(conn, "Stats", "skipped", "INTEGER NOT NULL DEFAULT 0")?; // hits from prefetches, bots and HEAD requests
+
+    Ok(())
+}
+
+// writes every column of a link with the INSERT statement given, which may replace an existing link
+fn write_link(conn: &rusqlite::Connection, insert: &str, link: &model::Link) -> Result<usize, rusqlite::Error> {
+    conn.execute(
+        &format!(
+            r#"{insert} INTO Links (ID, short, long, created, updated, status, max_age, path_mode, query_mode, query_allow, dont_track)
+                values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)"#
+        ),
+        params![
+            model::normalized_id(&link.short),
+            link.short,
+            link.long,
+            link.created,
+            link.updated,
+            link.status.code(),
+            link.max_age,
+            link.passthrough.path_mode.as_str(),
+            link.passthrough.query_mode.as_str(),
+            link.passthrough.query_allow.join(","),
+            link.dont_track
+        ],
+    )
+}
+
+// reads a link from the first columns of a row, which are LINK_COLUMNS
+fn read_link(row: &rusqlite::Row<'_>) -> Result<model::Link, rusqlite::Error> {
+    let invalid =
+        |column, e: String| rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, e.into());
+    let status: u16 = row.get(4)?;
+    let path_mode: String = row.get(6)?;
+    let query_mode: String = row.get(7)?;
+    let query_allow: String = row.get(8)?;
+    Ok(model::Link {
+        short: row.get(0)?,
+        long: row.get(1)?,
+        created: row.get(2)?,
+        updated: row.get(3)?,
+        status: model::RedirectStatus::try_from(status).map_err(|e| invalid(4, e))?,
+        max_age: row.get(5)?,
+        passthrough: model::Passthrough {
+            path_mode: path_mode.parse().map_err(|e| invalid(6, e))?,
+            query_mode: query_mode.parse().map_err(|e| invalid(7, e))?,
+            query_allow: model::parse_query_allow(&query_allow),
+        },
+        dont_track: row.get(9)?,
+    })
+}
+
 fn create_stats_table(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
     conn.execute(
         r#"create table if not exists Stats(
@@ -293,6 +1051,79 @@ fn create_stats_table(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error
     Ok(())
 }
 
+fn create_misses_table(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        r#"create table if not exists Misses(
+    ID        TEXT    PRIMARY KEY,         -- normalized version of short
+	short     TEXT    NOT NULL DEFAULT "", -- the short name as last requested
+	count     INTEGER NOT NULL DEFAULT 0,  -- number of lookups
+	last_seen INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+)"#,
+        (),
+    )?;
+    conn.execute("create index if not exists MissesByLastSeen on Misses(last_seen)", ())?;
+
+    Ok(())
+}
+
+fn create_clicks_table(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        r#"create table if not exists Clicks(
+    ID       TEXT    NOT NULL,            -- normalized version of the short name clicked
+	clicked  INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+	subnet   TEXT,                        -- client subnet, or its keyed hash
+	referrer TEXT,                        -- host of the Referer header
+	agent    TEXT    NOT NULL DEFAULT 'other' -- user agent family
+)"#,
+        (),
+    )?;
+    conn.execute("create index if not exists ClicksByID on Clicks(ID, clicked)", ())?;
+    conn.execute("create index if not exists ClicksByTime on Clicks(clicked)", ())?;
+    conn.execute(
+        r#"create table if not exists DailyClicks(
+    ID       TEXT    NOT NULL,           -- normalized version of the short name clicked
+	day      TEXT    NOT NULL,           -- UTC date, YYYY-MM-DD
+	clicks   INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY(ID, day)
+)"#,
+        (),
+    )?;
+
+    Ok(())
+}
+
+fn create_checks_table(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        r#"create table if not exists LinkChecks(
+    ID       TEXT    PRIMARY KEY, -- normalized version of the short name checked
+	status   INTEGER,             -- HTTP status, or NULL if the request failed
+	location TEXT,                -- Location header of a redirect
+	error    TEXT,                -- why the request failed
+	checked  INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+)"#,
+        (),
+    )?;
+
+    Ok(())
+}
+
+fn create_tokens_table(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        r#"create table if not exists ApiTokens(
+    ID        TEXT    PRIMARY KEY,         -- public identifier, which the token starts with
+	name      TEXT    NOT NULL DEFAULT "", -- what the token is for
+	hash      TEXT    NOT NULL UNIQUE,     -- hex SHA-256 of the token
+	scope     TEXT    NOT NULL DEFAULT 'read',
+	created   INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+	expires   INTEGER,                     -- when the token stops working, or NULL for never
+	last_used INTEGER
+)"#,
+        (),
+    )?;
+
+    Ok(())
+}
+
 impl Db {
     pub fn in_memory() -> Result<Self, rusqlite::Error> {
         let connection = rusqlite::Connection::open_in_memory()?;
@@ -301,12 +1132,22 @@ impl Db {
 
     pub fn new(connection: rusqlite::Connection) -> Result<Self, rusqlite::Error> {
         create_link_table(&connection)?;
+        migrate_link_table(&connection)?;
         create_stats_table(&connection)?;
+        migrate_stats_table(&connection)?;
+        create_misses_table(&connection)?;
+        create_clicks_table(&connection)?;
+        create_checks_table(&connection)?;
+        create_tokens_table(&connection)?;
 
         let boxed_connection = Arc::new(Mutex::new(connection));
         Ok(Self {
             link: LinkDAO::new(Arc::clone(&boxed_connection)),
             stats: StatsDAO::new(Arc::clone(&boxed_connection)),
+            misses: MissesDAO::new(Arc::clone(&boxed_connection)),
+            clicks: ClicksDAO::new(Arc::clone(&boxed_connection)),
+            checks: ChecksDAO::new(Arc::clone(&boxed_connection)),
+            tokens: TokensDAO::new(Arc::clone(&boxed_connection)),
         })
     }
 }
@@ -328,6 +1169,14 @@ mod tests {
             long: "https://www.nytimes.com".to_string(),
             created: link_created,
             updated: chrono::Utc::now(),
+            status: model::RedirectStatus::MovedPermanently,
+            max_age: Some(3600),
+            passthrough: model::Passthrough {
+                path_mode: model::PathMode::Reject,
+                query_mode: model::QueryMode::Allow,
+                query_allow: vec!["q".to_string(), "lang".to_string()],
+            },
+            dont_track: true,
         };
 
         // Save
@@ -340,6 +1189,7 @@ mod tests {
         // Load
         let from_db_link = db.link.load(&test_link.short).await?;
         assert_eq!(test_link, from_db_link);
+        assert_eq!(db.link.find(&test_link.short).await?, Some(test_link.clone()));
         assert_eq!(from_db_link.short, test_link.short);
 
         // Load All
@@ -353,10 +1203,16 @@ mod tests {
             long: "https://nytimes.com".to_string(),
             created: link_created,
             updated: chrono::Utc::now(),
+            status: model::RedirectStatus::Found,
+            max_age: None,
+            passthrough: model::Passthrough::default(),
+            dont_track: false,
         };
         db.link.save(&updated_link).await?;
         let read_updated = db.link.load(&updated_link.short).await?;
         assert_eq!(read_updated.short, updated_link.short);
+        assert_eq!(read_updated.status, model::RedirectStatus::Found);
+        assert_eq!(read_updated.max_age, None);
 
         ////// Stats INCR
         let mut stats = db.stats.load(&updated_link.short).await?;
@@ -378,7 +1234,20 @@ mod tests {
         assert!(stats.is_some());
         assert!(stats.unwrap().clicks.is_some_and(|clicks| clicks == 3));
 
-        let res = db.link.most_popular().await?;
+        db.stats
+            .incr_many(&HashMap::from([(model::normalized_id(&updated_link.short), 4)]))
+            .await?;
+        stats = db.stats.load(&updated_link.short).await?;
+        assert!(stats.unwrap().clicks.is_some_and(|clicks| clicks == 7));
+
+        db.stats
+            .skip_many(&HashMap::from([(model::normalized_id(&updated_link.short), 2)]))
+            .await?;
+        stats = db.stats.load(&updated_link.short).await?;
+        let stats = stats.unwrap();
+        assert_eq!((stats.clicks, stats.skipped), (Some(7), 2));
+
+        let res = db.link.most_popular(None, 10).await?;
         assert!(res.len() == 1);
         let most_popular_links: Vec<model::PopularLink> = res
             .iter()
@@ -394,7 +1263,437 @@ mod tests {
         db.stats.delete(&test_link.short).await?;
         let clicks = db.stats.load(&test_link.short).await?;
         assert!(clicks.is_none());
+        // clicks for deleted links are dropped
+        db.stats
+            .incr_many(&HashMap::from([(model::normalized_id(&test_link.short), 2)]))
+            .await?;
+        assert!(db.link.find(&test_link.short).await?.is_none());
+        assert!(db.link.load(&test_link.short).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_migrate_links() -> Result<(), Box<dyn std::error::Error + 'static>> {
+        let connection = Connection::open_in_memory()?;
+        connection.execute(
+            r#"create table Links(ID TEXT PRIMARY KEY, short TEXT, long TEXT, created INTEGER, updated INTEGER)"#,
+            (),
+        )?;
+        connection.execute(
+            r#"INSERT INTO Links values ('nyt', 'nyt', 'https://www.nytimes.com', ?1, ?1)"#,
+            params![chrono::Utc::now()],
+        )?;
+        let db = Db::new(connection)?;
+
+        let link = db.link.load("nyt").await?;
+        assert_eq!(link.long, "https://www.nytimes.com");
+        assert_eq!(link.status, model::RedirectStatus::Found);
+        assert_eq!(link.max_age, None);
+        assert_eq!(link.passthrough, model::Passthrough::default());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_misses() -> Result<(), Box<dyn std::error::Error + 'static>> {
+        let db = Db::in_memory()?;
+        let miss = |short: &str, misses| {
+            (
+                model::normalized_id(short),
+                model::WantedLink {
+                    short: short.to_string(),
+                    misses,
+                    last_seen: chrono::Utc::now(),
+                },
+            )
+        };
+        db.misses
+            .record_many(&HashMap::from([miss("oncall", 2), miss("wiki", 1)]))
+            .await?;
+        db.misses.record_many(&HashMap::from([miss("wiki", 3)])).await?;
+        let wanted = db.misses.wanted(10).await?;
+        assert_eq!(
+            wanted.iter().map(|w| (w.short.as_str(), w.misses)).collect::<Vec<_>>(),
+            vec![("wiki", 4), ("oncall", 2)]
+        );
+        assert_eq!(db.misses.wanted(1).await?.len(), 1);
+
+        // once a link is created it is no longer wanted
+        let link = model::Link {
+            short: "wiki".to_string(),
+            long: "http://wiki".to_string(),
+            created: chrono::Utc::now(),
+            updated: chrono::Utc::now(),
+            status: model::RedirectStatus::default(),
+            max_age: None,
+            passthrough: model::Passthrough::default(),
+            dont_track: false,
+        };
+        db.link.save(&link).await?;
+        let wanted = db.misses.wanted(10).await?;
+        assert_eq!(wanted.len(), 1);
+        assert_eq!(wanted[0].short, "oncall");
+
+        // the names seen least recently are forgotten once there are too many
+        let earlier = chrono::Utc::now() - chrono::Duration::days(1);
+        let many: HashMap<String, model::WantedLink> = (0..MAX_MISSES)
+            .map(|n| {
+                let (id, wanted) = miss(&format!("name{n}"), 1);
+                (
+                    id,
+                    model::WantedLink {
+                        last_seen: earlier,
+                        ..wanted
+                    },
+                )
+            })
+            .collect();
+        db.misses.record_many(&many).await?;
+        let wanted = db.misses.wanted(MAX_MISSES + 10).await?;
+        assert_eq!(wanted.len(), MAX_MISSES - 1); // go/wiki is kept, though it has a link now
+        assert!(wanted.iter().any(|w| w.short == "oncall"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_clicks() -> Result<(), Box<dyn std::error::Error + 'static>> {
+        let db = Db::in_memory()?;
+        let source = |days_ago, subnet: &str, referrer: Option<&str>, agent| model::ClickSource {
+            subnet: Some(subnet.to_string()),
+            referrer: referrer.map(str::to_string),
+            agent,
+            clicked: chrono::Utc::now() - chrono::Duration::days(days_ago),
+        };
+        db.clicks
+            .record_many(&[
+                (
+                    "nyt".to_string(),
+                    source(0, "192.0.2.0/24", Some("chat.example"), model::AgentFamily::Browser),
+                ),
+                (
+                    "nyt".to_string(),
+                    source(1, "192.0.2.0/24", Some("chat.example"), model::AgentFamily::Browser),
+                ),
+                (
+                    "nyt".to_string(),
+                    source(2, "198.51.100.0/24", None, model::AgentFamily::Cli),
+                ),
+                (
+                    "nyt".to_string(),
+                    source(40, "2001:db8::/48", Some("wiki.example"), model::AgentFamily::Browser),
+                ),
+                (
+                    "wiki".to_string(),
+                    source(0, "192.0.2.0/24", None, model::AgentFamily::Other),
+                ),
+            ])
+            .await?;
+
+        let tally = |name: &str, clicks| model::Tally {
+            name: name.to_string(),
+            clicks,
+        };
+        let recent = db.clicks.breakdown("NYT", Some(30), 10).await?;
+        assert_eq!(recent.days, Some(30));
+        assert_eq!(recent.referrers, vec![tally("chat.example", 2)]);
+        assert_eq!(
+            recent.subnets,
+            vec![tally("192.0.2.0/24", 2), tally("198.51.100.0/24", 1)]
+        );
+        assert_eq!(recent.agents, vec![tally("browser", 2), tally("cli", 1)]);
+
+        let all = db.clicks.breakdown("nyt", None, 1).await?;
+        assert_eq!(all.referrers, vec![tally("chat.example", 2)]);
+        assert_eq!(all.agents, vec![tally("browser", 3), tally("cli", 1)]);
+        let forever = db.clicks.breakdown("nyt", Some(u32::MAX), 1).await?;
+        assert_eq!(forever.agents, all.agents);
+
+        let today = chrono::Utc::now().date_naive();
+        let daily = db.clicks.daily("nyt", today - chrono::Duration::days(30)).await?;
+        assert_eq!(
+            daily.iter().map(|d| (d.day, d.clicks)).collect::<Vec<_>>(),
+            vec![
+                (today - chrono::Duration::days(2), 1),
+                (today - chrono::Duration::days(1), 1),
+                (today, 1)
+            ]
+        );
+        assert!(db.clicks.last_clicked("nyt").await?.unwrap().date_naive() == today);
+        assert_eq!(db.clicks.last_clicked("team").await?, None);
+
+        // pruned clicks are still counted per day, but no longer broken down
+        assert_eq!(
+            db.clicks.prune(chrono::Utc::now() - chrono::Duration::days(30)).await?,
+            1
+        );
+        assert_eq!(db.clicks.breakdown("nyt", None, 10).await?.agents.len(), 2);
+        assert_eq!(
+            db.clicks.daily("nyt", today - chrono::Duration::days(90)).await?.len(),
+            4
+        );
+
+        assert_eq!(db.clicks.delete("wiki").await?, 1);
+        assert!(db.clicks.daily("wiki", today).await?.is_empty());
+        assert_eq!(
+            db.clicks.prune(chrono::Utc::now() + chrono::Duration::days(1)).await?,
+            3
+        );
+        assert_eq!(
+            db.clicks.last_clicked("nyt").await?,
+            Some(today.and_time(chrono::NaiveTime::MIN).and_utc())
+        );
+        assert_eq!(db.clicks.purge().await?, 0);
+        assert!(
+            db.clicks
+                .daily("nyt", today - chrono::Duration::days(90))
+                .await?
+                .is_empty()
+        );
+        assert!(db.clicks.breakdown("nyt", None, 10).await?.agents.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_trending() -> Result<(), Box<dyn std::error::Error + 'static>> {
+        let db = Db::in_memory()?;
+        let now = chrono::Utc::now();
+        for (short, age) in [("old", 400), ("new", 1), ("newest", 0)] {
+            let link = model::Link {
+                short: short.to_string(),
+                long: format!("http://{short}.example"),
+                created: now - chrono::Duration::days(age),
+                updated: now - chrono::Duration::days(age),
+                status: model::RedirectStatus::default(),
+                max_age: None,
+                passthrough: model::Passthrough::default(),
+                dont_track: false,
+            };
+            db.link.save(&link).await?;
+            db.stats.save(short).await?;
+        }
+        let click = |short: &str, days_ago| {
+            (
+                short.to_string(),
+                model::ClickSource {
+                    subnet: None,
+                    referrer: None,
+                    agent: model::AgentFamily::Other,
+                    clicked: now - chrono::Duration::days(days_ago),
+                },
+            )
+        };
+        // "old" was popular long ago, "new" is popular now
+        let clicks = [
+            vec![click("old", 200); 5],
+            vec![click("old", 2)],
+            vec![click("new", 1), click("new", 0)],
+        ]
+        .concat();
+        db.clicks.record_many(&clicks).await?;
+        db.stats
+            .incr_many(&HashMap::from([("old".to_string(), 6), ("new".to_string(), 2)]))
+            .await?;
+
+        let ranked = |links: Vec<(model::Link, model::ClickStats)>| {
+            links
+                .into_iter()
+                .map(|(link, stats)| (link.short, stats.clicks))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ranked(db.link.most_popular(None, 10).await?),
+            vec![("old".to_string(), Some(6)), ("new".to_string(), Some(2))]
+        );
+        assert_eq!(
+            ranked(db.link.most_popular(Some(7), 10).await?),
+            vec![("new".to_string(), Some(2)), ("old".to_string(), Some(1))]
+        );
+        assert_eq!(
+            ranked(db.link.most_popular(Some(1), 10).await?),
+            vec![("new".to_string(), Some(1))]
+        );
+        assert_eq!(db.link.most_popular(Some(30), 1).await?.len(), 1);
+
+        let shorts = |links: Vec<model::Link>| links.into_iter().map(|link| link.short).collect::<Vec<_>>();
+        assert_eq!(shorts(db.link.recently_created(2).await?), vec!["newest", "new"]);
+        let mut old = db.link.load("old").await?;
+        old.updated = chrono::Utc::now();
+        db.link.save(&old).await?;
+        assert_eq!(shorts(db.link.recently_updated(2).await?), vec!["old", "newest"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_merge() -> Result<(), Box<dyn std::error::Error + 'static>> {
+        let db = Db::in_memory()?;
+        for short in ["team-notes", "teamnote", "wiki"] {
+            let link = model::Link {
+                short: short.to_string(),
+                long: "http://notes.example".to_string(),
+                created: chrono::Utc::now(),
+                updated: chrono::Utc::now(),
+                status: model::RedirectStatus::default(),
+                max_age: None,
+                passthrough: model::Passthrough::default(),
+                dont_track: false,
+            };
+            db.link.save(&link).await?;
+            db.stats.save(short).await?;
+        }
+        let click = |short: &str| {
+            (
+                model::normalized_id(short),
+                model::ClickSource {
+                    subnet: None,
+                    referrer: None,
+                    agent: model::AgentFamily::Other,
+                    clicked: chrono::Utc::now(),
+                },
+            )
+        };
+        db.clicks
+            .record_many(&[click("team-notes"), click("teamnote"), click("teamnote")])
+            .await?;
+        db.stats
+            .incr_many(&HashMap::from([
+                ("teamnotes".to_string(), 1),
+                ("teamnote".to_string(), 2),
+            ]))
+            .await?;
+        db.stats
+            .skip_many(&HashMap::from([("teamnote".to_string(), 3)]))
+            .await?;
+
+        let usage = db.link.load_all_with_usage().await?;
+        let today = chrono::Utc::now().date_naive();
+        assert_eq!(
+            usage
+                .iter()
+                .map(|(link, usage)| (link.short.as_str(), usage.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "team-notes",
+                    model::LinkUsage {
+                        clicks: 1,
+                        last_clicked: Some(today)
+                    }
+                ),
+                (
+                    "teamnote",
+                    model::LinkUsage {
+                        clicks: 2,
+                        last_clicked: Some(today)
+                    }
+                ),
+                (
+                    "wiki",
+                    model::LinkUsage {
+                        clicks: 0,
+                        last_clicked: None
+                    }
+                ),
+            ]
+        );
+
+        db.link.merge("TeamNote", "team-notes").await?;
+        assert!(db.link.find("teamnote").await?.is_none());
+        assert!(db.stats.load("teamnote").await?.is_none());
+        let stats = db.stats.load("team-notes").await?.unwrap();
+        assert_eq!((stats.clicks, stats.skipped), (Some(3), 3));
+        assert_eq!(db.clicks.daily("team-notes", today).await?[0].clicks, 3);
+        assert_eq!(db.clicks.breakdown("team-notes", None, 10).await?.agents[0].clicks, 3);
+
+        db.link.merge("wiki", "team-notes").await?; // never clicked
+        assert_eq!(db.stats.load("team-notes").await?.unwrap().clicks, Some(3));
+        assert!(db.link.merge("nope", "team-notes").await.is_err());
+        assert!(db.link.merge("team-notes", "TeamNotes").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_page() -> Result<(), Box<dyn std::error::Error + 'static>> {
+        let db = Db::in_memory()?;
+        for (short, long) in [
+            ("Docs", "https://docs.example"),
+            ("search", "https://search.example/?q={{path}}"),
+            ("wiki", "https://wiki.example/Docs"),
+        ] {
+            let link = model::Link {
+                short: short.to_string(),
+                long: long.to_string(),
+                created: chrono::Utc::now(),
+                updated: chrono::Utc::now(),
+                status: model::RedirectStatus::default(),
+                max_age: None,
+                passthrough: model::Passthrough::default(),
+                dont_track: false,
+            };
+            db.link.save(&link).await?;
+        }
+        let shorts = |links: Vec<model::Link>| links.into_iter().map(|l| l.short).collect::<Vec<_>>();
+
+        let (links, total) = db.link.page(None, None, 0, 2).await?;
+        assert_eq!(
+            (shorts(links), total),
+            (vec!["Docs".to_string(), "search".to_string()], 3)
+        );
+        let (links, total) = db.link.page(None, None, 2, 2).await?;
+        assert_eq!((shorts(links), total), (vec!["wiki".to_string()], 3));
+        let (links, total) = db.link.page(Some("DOCS"), None, 0, 10).await?;
+        assert_eq!(
+            (shorts(links), total),
+            (vec!["Docs".to_string(), "wiki".to_string()], 2)
+        );
+        let (links, _) = db.link.page(None, Some(true), 0, 10).await?;
+        assert_eq!(shorts(links), vec!["search"]);
+        let (links, total) = db.link.page(Some("docs"), Some(false), 1, 10).await?;
+        assert_eq!((shorts(links), total), (vec!["wiki".to_string()], 2));
+        let (links, _) = db.link.page(None, None, usize::MAX, 10).await?;
+        assert!(links.is_empty()); // rather than the first page
+
+        // inserting never replaces a link, even one whose short name differs in case
+        let mut docs = db.link.load("docs").await?;
+        docs.short = "DOCS".to_string();
+        docs.long = "https://other.example".to_string();
+        assert!(!db.link.insert(&docs).await?);
+        assert_eq!(db.link.load("docs").await?.long, "https://docs.example");
+        docs.short = "new".to_string();
+        assert!(db.link.insert(&docs).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tokens() -> Result<(), Box<DbError>> {
+        let db = Db::in_memory().unwrap();
+        let created = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let token = model::ApiToken {
+            id: "a1b2".to_string(),
+            name: "deploy".to_string(),
+            scope: model::TokenScope::Write,
+            created,
+            expires: Some(created + chrono::Duration::days(30)),
+            last_used: None,
+        };
+        db.tokens.save(&token, "hash").await?;
+        assert!(db.tokens.save(&token, "other").await.is_err()); // ids are unique
+
+        let found = db.tokens.find_by_hash("hash").await?.unwrap();
+        assert_eq!(
+            (found.id, found.scope, found.expires),
+            ("a1b2".to_string(), token.scope, token.expires)
+        );
+        assert!(db.tokens.find_by_hash("other").await?.is_none());
+
+        let used = created + chrono::Duration::hours(1);
+        db.tokens.touch("a1b2", used).await?;
+        let all = db.tokens.load_all().await?;
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].last_used, Some(used));
 
+        assert!(db.tokens.delete("a1b2").await?);
+        assert!(!db.tokens.delete("a1b2").await?);
+        assert!(db.tokens.find_by_hash("hash").await?.is_none());
         Ok(())
     }
 }
@@ -0,0 +1,600 @@
+//! Translates destination links written for [golink], which use Go's
+//! [text/template] syntax, into the equivalent handlebars template.
+//!
+//! [golink]: https://github.com/tailscale/golink
+//! [text/template]: https://pkg.go.dev/text/template
+
+use serde::Deserialize;
+
+use crate::model;
+
+// golink's template functions and Go's builtins, with the helpers that replace them
+const FUNCTIONS: [(&str, &str); 18] = [
+    ("PathEscape", "path_escape"),
+    ("QueryEscape", "query_escape"),
+    ("TrimPrefix", "trimprefix"),
+    ("TrimSuffix", "trimsuffix"),
+    ("ToLower", "lowercase"),
+    ("ToUpper", "uppercase"),
+    ("Match", "match"),
+    ("urlquery", "query_escape"),
+    ("eq", "eq"),
+    ("ne", "ne"),
+    ("lt", "lt"),
+    ("le", "lte"),
+    ("gt", "gt"),
+    ("ge", "gte"),
+    ("and", "and"),
+    ("or", "or"),
+    ("not", "not"),
+    ("len", "len"),
+];
+
+// Go reference time layout elements and their strftime equivalents, longest first
+const LAYOUT_ELEMENTS: [(&str, &str); 31] = [
+    ("January", "%B"),
+    ("Monday", "%A"),
+    (".000000000", "%.9f"),
+    (".000000", "%.6f"),
+    (".000", "%.3f"),
+    ("Z07:00", "%:z"),
+    ("-07:00", "%:z"),
+    ("-0700", "%z"),
+    ("2006", "%Y"),
+    ("Jan", "%b"),
+    ("Mon", "%a"),
+    ("MST", "%Z"),
+    ("002", "%j"),
+    ("_2", "%e"),
+    ("01", "%m"),
+    ("02", "%d"),
+    ("03", "%I"),
+    ("04", "%M"),
+    ("05", "%S"),
+    ("06", "%y"),
+    ("15", "%H"),
+    ("PM", "%p"),
+    ("pm", "%P"),
+    ("-07", "%:::z"),
+    ("1", "%-m"),
+    ("2", "%-d"),
+    ("3", "%-I"),
+    ("4", "%-M"),
+    ("5", "%-S"),
+    ("%", "%%"),
+    ("Z", "Z"),
+];
+
+/// TranslateError describes why a golink destination link could not be translated.
+#[derive(Debug, PartialEq)]
+pub struct TranslateError {
+    message: String,
+}
+
+impl TranslateError {
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for TranslateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid golink template: {}", self.message)
+    }
+}
+
+impl std::error::Error for TranslateError {}
+
+/// GolinkLink is a link as exported by golink's `/.export` endpoint.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct GolinkLink {
+    pub short: String,
+    pub long: String,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub last_edit: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<GolinkLink> for model::Link {
+    fn from(val: GolinkLink) -> Self {
+        model::Link {
+            short: val.short,
+            long: val.long,
+            created: val.created,
+            updated: val.last_edit,
+            status: model::RedirectStatus::Found, // golink always redirects with 302
+            max_age: None,
+            passthrough: model::Passthrough::default(),
+            dont_track: false,
+        }
+    }
+}
+
+/// ExportedLink is a link in either gohome's or golink's export format.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ExportedLink {
+    Gohome(model::Link),
+    Golink(GolinkLink),
+}
+
+impl From<ExportedLink> for model::Link {
+    fn from(val: ExportedLink) -> Self {
+        match val {
+            ExportedLink::Gohome(link) => link,
+            ExportedLink::Golink(link) => link.into(),
+        }
+    }
+}
+
+/// Returns true if the destination link uses Go template syntax rather than handlebars.
+pub fn is_go_template(long: &str) -> bool {
+    let mut rest = long;
+    while let Some(start) = rest.find("{{") {
+        rest = &rest[start + 2..];
+        let action = rest.strip_prefix("- ").unwrap_or(rest).trim_start();
+        let word: String = action
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '.' || *c == '_')
+            .collect();
+        if action.starts_with("/*")
+            || word == "."
+            || word.starts_with('.') && word[1..].starts_with(char::is_uppercase)
+            || ["if", "with", "end"].contains(&word.as_str())
+            || FUNCTIONS
+                .iter()
+                .any(|(go, _)| go.starts_with(char::is_uppercase) && *go == word)
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// Translates a Go template destination link into handlebars. Links that are
+/// not Go templates are returned unchanged.
+pub fn translate(long: &str) -> Result<String, TranslateError> {
+    if !is_go_template(long) {
+        return Ok(long.to_string());
+    }
+
+    let mut translated = String::new();
+    let mut blocks: Vec<&str> = Vec::new();
+    let mut rest = long;
+    let mut trim_next = false;
+    while let Some(start) = rest.find("{{") {
+        translated.push_str(if trim_next {
+            rest[..start].trim_start()
+        } else {
+            &rest[..start]
+        });
+        let (action, remaining) = Action::parse(&rest[start + 2..])?;
+        rest = remaining;
+
+        // comments produce no output, so whitespace around them is trimmed here
+        if action.tokens.is_empty() {
+            if action.trim_left {
+                translated.truncate(translated.trim_end().len());
+            }
+            trim_next = action.trim_right;
+            continue;
+        }
+        trim_next = false;
+
+        let open = if action.trim_left { "{{~" } else { "{{" };
+        let close = if action.trim_right { "~}}" } else { "}}" };
+        let body = match action.tokens.as_slice() {
+            [Token::Ident(keyword), condition @ ..] if keyword == "if" || keyword == "with" => {
+                blocks.push(if keyword == "if" { "if" } else { "with" });
+                format!("#{} {}", keyword, Parser::new(condition).argument()?)
+            }
+            [Token::Ident(keyword), Token::Ident(chained), condition @ ..] if keyword == "else" && chained == "if" => {
+                if blocks.last() != Some(&"if") {
+                    return Err(TranslateError::new("{{else if}} outside of {{if}}"));
+                }
+                format!("else if {}", Parser::new(condition).argument()?)
+            }
+            [Token::Ident(keyword)] if keyword == "else" => "else".to_string(),
+            [Token::Ident(keyword)] if keyword == "end" => {
+                let block = blocks
+                    .pop()
+                    .ok_or_else(|| TranslateError::new("{{end}} without a matching {{if}}"))?;
+                format!("/{}", block)
+            }
+            [Token::Ident(keyword), ..] if keyword == "range" || keyword == "define" || keyword == "template" => {
+                return Err(TranslateError::new(format!("{{{{{}}}}} is not supported", keyword)));
+            }
+            tokens => Parser::new(tokens).expression()?,
+        };
+        translated.push_str(&format!("{}{}{}", open, body, close));
+    }
+    translated.push_str(if trim_next { rest.trim_start() } else { rest });
+
+    match blocks.last() {
+        Some(block) => Err(TranslateError::new(format!(
+            "{{{{{}}}}} is missing its {{{{end}}}}",
+            block
+        ))),
+        None => Ok(translated),
+    }
+}
+
+/// Converts a Go reference time layout such as "2006-01-02" into a strftime format.
+pub fn strftime_layout(layout: &str) -> String {
+    let mut format = String::new();
+    let mut rest = layout;
+    while let Some(c) = rest.chars().next() {
+        match LAYOUT_ELEMENTS.iter().find(|(element, _)| rest.starts_with(element)) {
+            Some((element, strftime)) => {
+                format.push_str(strftime);
+                rest = &rest[element.len()..];
+            }
+            None => {
+                format.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    format
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Field(Vec<String>), // .Path, .Now.Format, or . on its own
+    Ident(String),
+    Literal(String), // a number or boolean, written the same in both languages
+    Str(String),
+    Pipe,
+    Open,
+    Close,
+}
+
+// Action is the tokenized content of a single {{ }}.
+struct Action {
+    tokens: Vec<Token>,
+    trim_left: bool,
+    trim_right: bool,
+}
+
+impl Action {
+    // parses an action starting after its "{{", returning it and the text after its "}}"
+    fn parse(source: &str) -> Result<(Self, &str), TranslateError> {
+        let (trim_left, mut rest) = match source.strip_prefix("- ") {
+            Some(rest) => (true, rest),
+            None => (false, source),
+        };
+        let mut tokens = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix("}}") {
+                return Ok((
+                    Self {
+                        tokens,
+                        trim_left,
+                        trim_right: false,
+                    },
+                    after,
+                ));
+            }
+            if let Some(after) = rest.strip_prefix("-}}") {
+                return Ok((
+                    Self {
+                        tokens,
+                        trim_left,
+                        trim_right: true,
+                    },
+                    after,
+                ));
+            }
+            if let Some(comment) = rest.strip_prefix("/*") {
+                let end = comment
+                    .find("*/")
+                    .ok_or_else(|| TranslateError::new("unterminated comment"))?;
+                rest = &comment[end + 2..];
+                continue;
+            }
+
+            let c = rest
+                .chars()
+                .next()
+                .ok_or_else(|| TranslateError::new("unterminated action"))?;
+            let (token, after) = match c {
+                '|' => (Token::Pipe, &rest[1..]),
+                '(' => (Token::Open, &rest[1..]),
+                ')' => (Token::Close, &rest[1..]),
+                '"' => Self::quoted(&rest[1..])?,
+                '`' => {
+                    let end = rest[1..]
+                        .find('`')
+                        .ok_or_else(|| TranslateError::new("unterminated raw string"))?;
+                    (Token::Str(rest[1..end + 1].to_string()), &rest[end + 2..])
+                }
+                '.' => {
+                    let end = Self::word_end(rest, 1);
+                    let names = rest[1..end]
+                        .split('.')
+                        .filter(|name| !name.is_empty())
+                        .map(str::to_string)
+                        .collect();
+                    (Token::Field(names), &rest[end..])
+                }
+                '$' => return Err(TranslateError::new("variables are not supported")),
+                c if c.is_ascii_digit() || c == '-' => {
+                    let end = Self::word_end(rest, 1);
+                    (Token::Literal(rest[..end].to_string()), &rest[end..])
+                }
+                c if c.is_alphabetic() || c == '_' => {
+                    let end = Self::word_end(rest, 0);
+                    let word = &rest[..end];
+                    let token = if word == "true" || word == "false" {
+                        Token::Literal(word.to_string())
+                    } else {
+                        Token::Ident(word.to_string())
+                    };
+                    (token, &rest[end..])
+                }
+                c => return Err(TranslateError::new(format!("unexpected \"{}\"", c))),
+            };
+            tokens.push(token);
+            rest = after;
+        }
+    }
+
+    fn word_end(s: &str, from: usize) -> usize {
+        s[from..]
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+            .map_or(s.len(), |end| end + from)
+    }
+
+    // reads an interpreted string literal that starts after its opening quote
+    fn quoted(s: &str) -> Result<(Token, &str), TranslateError> {
+        let mut value = String::new();
+        let mut chars = s.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Token::Str(value), &s[i + 1..])),
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, 'r')) => value.push('\r'),
+                    Some((_, escaped)) => value.push(escaped),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        Err(TranslateError::new("unterminated string"))
+    }
+}
+
+// Expression is a translated operand or helper call.
+enum Expression {
+    Value(String),
+    Call(String, Vec<String>),
+}
+
+impl Expression {
+    // the expression as a helper argument, where calls need parentheses
+    fn argument(self) -> String {
+        match self {
+            Expression::Value(value) => value,
+            Expression::Call(name, args) if args.is_empty() => format!("({})", name),
+            Expression::Call(name, args) => format!("({} {})", name, args.join(" ")),
+        }
+    }
+
+    // the expression on its own inside {{ }}
+    fn statement(self) -> String {
+        match self {
+            Expression::Value(value) => value,
+            Expression::Call(name, args) if args.is_empty() => name,
+            Expression::Call(name, args) => format!("{} {}", name, args.join(" ")),
+        }
+    }
+}
+
+// Head is the first word of a command, which decides how the rest are used.
+enum Head<'a> {
+    Function(String),
+    Field(&'a [String]),
+    Value(String),
+}
+
+// Parser translates the pipeline in an action.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Token]) -> Self {
+        Self { tokens, position: 0 }
+    }
+
+    fn expression(mut self) -> Result<String, TranslateError> {
+        let expression = self.pipeline()?;
+        self.finish()?;
+        Ok(expression.statement())
+    }
+
+    fn argument(mut self) -> Result<String, TranslateError> {
+        let expression = self.pipeline()?;
+        self.finish()?;
+        Ok(expression.argument())
+    }
+
+    fn finish(&self) -> Result<(), TranslateError> {
+        match self.tokens.get(self.position) {
+            Some(token) => Err(TranslateError::new(format!("unexpected {:?}", token))),
+            None => Ok(()),
+        }
+    }
+
+    // command ("|" command)*, where each result becomes the last argument of the next command
+    fn pipeline(&mut self) -> Result<Expression, TranslateError> {
+        let mut expression = self.command(None)?;
+        while self.tokens.get(self.position) == Some(&Token::Pipe) {
+            self.position += 1;
+            expression = self.command(Some(expression.argument()))?;
+        }
+        Ok(expression)
+    }
+
+    fn command(&mut self, piped: Option<String>) -> Result<Expression, TranslateError> {
+        let head = match self.tokens.get(self.position) {
+            Some(Token::Ident(function)) => {
+                self.position += 1;
+                let helper = FUNCTIONS
+                    .iter()
+                    .find(|(go, _)| go == function)
+                    .map(|(_, helper)| helper.to_string())
+                    .ok_or_else(|| TranslateError::new(format!("unsupported function \"{}\"", function)))?;
+                Head::Function(helper)
+            }
+            Some(Token::Field(names)) => {
+                self.position += 1;
+                Head::Field(names)
+            }
+            _ => Head::Value(self.operand()?),
+        };
+        let mut args = Vec::new();
+        while !matches!(self.tokens.get(self.position), None | Some(Token::Pipe | Token::Close)) {
+            args.push(self.operand()?);
+        }
+        args.extend(piped);
+
+        match head {
+            Head::Function(helper) => Ok(Expression::Call(helper, args)),
+            Head::Field(names) => Self::field(names, args),
+            Head::Value(value) if args.is_empty() => Ok(Expression::Value(value)),
+            Head::Value(value) => Err(TranslateError::new(format!("{} cannot be called", value))),
+        }
+    }
+
+    fn operand(&mut self) -> Result<String, TranslateError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or_else(|| TranslateError::new("missing value"))?;
+        self.position += 1;
+        match token {
+            Token::Str(s) => Ok(serde_json::to_string(s).unwrap_or_default()),
+            Token::Literal(literal) => Ok(literal.clone()),
+            Token::Field(names) => Ok(Self::field(names, Vec::new())?.argument()),
+            Token::Open => {
+                let expression = self.pipeline()?;
+                match self.tokens.get(self.position) {
+                    Some(Token::Close) => {
+                        self.position += 1;
+                        Ok(expression.argument())
+                    }
+                    _ => Err(TranslateError::new("missing \")\"")),
+                }
+            }
+            Token::Ident(function) => Err(TranslateError::new(format!(
+                "function \"{}\" must be called in parentheses",
+                function
+            ))),
+            token => Err(TranslateError::new(format!("unexpected {:?}", token))),
+        }
+    }
+
+    fn field(names: &[String], args: Vec<String>) -> Result<Expression, TranslateError> {
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        match (names.as_slice(), args.as_slice()) {
+            ([], []) => Ok(Expression::Value("this".to_string())),
+            (["Path"], []) => Ok(Expression::Value("path".to_string())),
+            (["User"], []) => Ok(Expression::Value("user".to_string())),
+            (["Now"], []) => Ok(Expression::Call("now".to_string(), Vec::new())),
+            (["Now", "Format"], [layout]) => {
+                let layout: String = serde_json::from_str(layout)
+                    .map_err(|_| TranslateError::new(".Now.Format needs a literal layout"))?;
+                let format = serde_json::to_string(&strftime_layout(&layout)).unwrap_or_default();
+                Ok(Expression::Call("nowformat".to_string(), vec![format]))
+            }
+            _ => Err(TranslateError::new(format!(
+                "unsupported field \".{}\"",
+                names.join(".")
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_go_template() {
+        assert!(is_go_template("http://host/{{.Path}}"));
+        assert!(is_go_template("http://host/{{if .Path}}x{{end}}"));
+        assert!(is_go_template("http://host/{{QueryEscape .Path}}"));
+        assert!(is_go_template("http://host/{{- /* comment */ -}}"));
+        assert!(!is_go_template("http://host/"));
+        assert!(!is_go_template("http://host/{{path}}"));
+        assert!(!is_go_template("http://host/{{#if path}}{{query_escape path}}{{/if}}"));
+    }
+
+    #[test]
+    fn test_translate_fields() {
+        assert_eq!(translate("http://host/{{.Path}}").unwrap(), "http://host/{{path}}");
+        assert_eq!(translate("http://host/{{ .User }}").unwrap(), "http://host/{{user}}");
+        assert_eq!(
+            translate(r#"http://wiki/{{.Now.Format "2006-01-02"}}"#).unwrap(),
+            r#"http://wiki/{{nowformat "%Y-%m-%d"}}"#
+        );
+        assert_eq!(translate("http://host/").unwrap(), "http://host/");
+    }
+
+    #[test]
+    fn test_translate_functions() {
+        assert_eq!(
+            translate("https://www.google.com/{{if .Path}}search?q={{QueryEscape .Path}}{{end}}").unwrap(),
+            "https://www.google.com/{{#if path}}search?q={{query_escape path}}{{/if}}"
+        );
+        assert_eq!(
+            translate(r#"http://host/{{TrimPrefix .Path "x/" | ToLower | PathEscape}}"#).unwrap(),
+            "http://host/{{path_escape (lowercase (trimprefix path \"x/\"))}}"
+        );
+        assert_eq!(
+            translate(r#"http://host/{{if Match "\\d+" .Path}}b/{{.Path}}{{else if eq .Path "x"}}x{{else}}s{{end}}"#)
+                .unwrap(),
+            r#"http://host/{{#if (match "\\d+" path)}}b/{{path}}{{else if (eq path "x")}}x{{else}}s{{/if}}"#
+        );
+        assert_eq!(
+            translate("http://host/{{with .User}}{{.}}{{end}}").unwrap(),
+            "http://host/{{#with user}}{{this}}{{/with}}"
+        );
+    }
+
+    #[test]
+    fn test_translate_trim_and_comments() {
+        assert_eq!(
+            translate("http://host/{{- /* who */ -}} {{- .Path -}}").unwrap(),
+            "http://host/{{~path~}}"
+        );
+    }
+
+    #[test]
+    fn test_translate_errors() {
+        assert!(translate("http://host/{{if .Path}}").is_err());
+        assert!(translate("http://host/{{.Path}}{{end}}").is_err());
+        assert!(translate("http://host/{{.Query}}").is_err());
+        assert!(translate("http://host/{{.Path | printf \"%s\"}}").is_err());
+        assert!(translate("http://host/{{range .Path}}{{end}}").is_err());
+        assert!(translate("http://host/{{.Path").is_err());
+    }
+
+    #[test]
+    fn test_strftime_layout() {
+        assert_eq!(strftime_layout("2006-01-02"), "%Y-%m-%d");
+        assert_eq!(
+            strftime_layout("Jan 2, 2006 at 3:04pm (MST)"),
+            "%b %-d, %Y at %-I:%M%P (%Z)"
+        );
+        assert_eq!(strftime_layout("Monday 15:04:05.000 -0700"), "%A %H:%M:%S%.3f %z");
+        assert_eq!(strftime_layout("100%"), "%-m00%%");
+    }
+}
@@ -0,0 +1,535 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, Datelike, Days, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, JsonRender, JsonTruthy, JsonValue, RenderContext, RenderError,
+    RenderErrorReason, ScopedJson,
+};
+use regex::{Regex, RegexBuilder};
+
+use crate::render::MAX_LINK_LENGTH;
+
+// Regular expressions come from destination links, so both the patterns and the
+// strings they run over are bounded to keep matching cheap.
+const MAX_PATTERN_LENGTH: usize = 512;
+const MAX_INPUT_LENGTH: usize = 4 * 1024;
+const REGEX_SIZE_LIMIT: usize = 256 * 1024;
+const REGEX_DFA_SIZE_LIMIT: usize = 1024 * 1024;
+
+handlebars::handlebars_helper!(query_escape: |query_string: String| url_escape::encode_query(&query_string).clone());
+handlebars::handlebars_helper!(path_escape: |path: String| url_escape::encode_path(&path));
+handlebars::handlebars_helper!(component_escape: |s: String| url_escape::encode_component(&s));
+handlebars::handlebars_helper!(trim_suffix: |path: String, suffix: String| {
+    match path.strip_suffix(&suffix) {
+        Some(result) => result,
+        _ => &path
+    }.to_string()
+});
+handlebars::handlebars_helper!(trim_prefix: |path: String, prefix: String| {
+    match path.strip_prefix(&prefix) {
+        Some(result) => result,
+        _ => &path
+    }.to_string()
+});
+handlebars::handlebars_helper!(to_lower: |s: String| s.to_lowercase());
+handlebars::handlebars_helper!(to_upper: |s: String| s.to_uppercase());
+handlebars::handlebars_helper!(match_string: |pattern: String, path: String| {
+    match regex(&pattern, &path) {
+        Some(r) => {
+            r.is_match(&path)
+        },
+        _ => false
+    }
+});
+handlebars::handlebars_helper!(split: |s: String, separator: String| {
+    if s.is_empty() {
+        Vec::new()
+    } else {
+        s.split(separator.as_str()).map(str::to_string).collect::<Vec<String>>()
+    }
+});
+handlebars::handlebars_helper!(segment: |path: String, index: i64| {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    // negative indexes count back from the last segment
+    let index = if index < 0 { segments.len() as i64 + index } else { index };
+    usize::try_from(index)
+        .ok()
+        .and_then(|i| segments.get(i))
+        .map_or_else(String::new, |s| s.to_string())
+});
+handlebars::handlebars_helper!(join: |values: array, separator: String| {
+    values.iter().map(|v| v.render()).collect::<Vec<String>>().join(&separator)
+});
+handlebars::handlebars_helper!(regex_replace: |pattern: String, replacement: String, s: String| {
+    match regex(&pattern, &s) {
+        Some(r) => r.replace_all(&s, replacement.as_str()).to_string(),
+        _ => s
+    }
+});
+handlebars::handlebars_helper!(capture: |pattern: String, s: String, group: Json| {
+    let captures = regex(&pattern, &s).and_then(|r| {
+        r.captures(&s).and_then(|c| match group {
+            JsonValue::String(name) => c.name(name),
+            _ => group.as_u64().and_then(|i| c.get(i as usize)),
+        }).map(|m| m.as_str().to_string())
+    });
+    captures.unwrap_or_default()
+});
+handlebars::handlebars_helper!(base64: |s: String| data_encoding::BASE64.encode(s.as_bytes()));
+handlebars::handlebars_helper!(base64url: |s: String| data_encoding::BASE64URL_NOPAD.encode(s.as_bytes()));
+handlebars::handlebars_helper!(hex: |s: String| data_encoding::HEXLOWER.encode(s.as_bytes()));
+handlebars::handlebars_helper!(sha256: |s: String| {
+    data_encoding::HEXLOWER.encode(ring::digest::digest(&ring::digest::SHA256, s.as_bytes()).as_ref())
+});
+handlebars::handlebars_helper!(format_number: |n: Json, precision: u64, {separator: str = ""}| {
+    match n.render().trim().parse::<f64>() {
+        Ok(number) => group_thousands(&format!("{:.*}", bounded(precision, "precision")?, number), separator),
+        _ => n.render()
+    }
+});
+handlebars::handlebars_helper!(pad_left: |s: Json, width: u64, {pad: str = " "}| {
+    let s = s.render();
+    format!("{}{}", padding(&s, bounded(width, "width")?, pad), s)
+});
+handlebars::handlebars_helper!(pad_right: |s: Json, width: u64, {pad: str = " "}| {
+    let s = s.render();
+    format!("{}{}", s, padding(&s, bounded(width, "width")?, pad))
+});
+
+// builds the pattern if it and the input are within the limits, treating any other pattern as not matching
+fn regex(pattern: &str, input: &str) -> Option<Regex> {
+    if pattern.len() > MAX_PATTERN_LENGTH || input.len() > MAX_INPUT_LENGTH {
+        return None;
+    }
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_DFA_SIZE_LIMIT)
+        .build()
+        .ok()
+}
+
+// inserts the separator between each group of three digits in the integer part of a formatted number
+fn group_thousands(number: &str, separator: &str) -> String {
+    let (sign, unsigned) = match number.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", number),
+    };
+    let (integer, fraction) = match unsigned.split_once('.') {
+        Some((integer, fraction)) => (integer, format!(".{fraction}")),
+        None => (unsigned, String::new()),
+    };
+    let digits: Vec<char> = integer.chars().collect();
+    let grouped: Vec<String> = digits.rchunks(3).rev().map(|chunk| chunk.iter().collect()).collect();
+    format!("{}{}{}", sign, grouped.join(separator), fraction)
+}
+
+// checks that a width or precision is no longer than a link may be, so that a link cannot make a huge string
+// before its output is bounded
+fn bounded(value: u64, name: &str) -> Result<usize, RenderError> {
+    usize::try_from(value)
+        .ok()
+        .filter(|value| *value <= MAX_LINK_LENGTH)
+        .ok_or_else(|| RenderErrorReason::Other(format!("{name} {value} is more than {MAX_LINK_LENGTH}")).into())
+}
+
+// the characters needed to pad s to the given width
+fn padding(s: &str, width: usize, pad: &str) -> String {
+    let missing = width.saturating_sub(s.chars().count());
+    pad.chars()
+        .cycle()
+        .take(if pad.is_empty() { 0 } else { missing })
+        .collect()
+}
+
+/// DefaultHelper returns its first parameter when it is truthy and its second otherwise.
+/// Unlike helpers made with `handlebars_helper!`, it accepts a missing first parameter
+/// in strict mode, since that is what it is for.
+#[derive(Clone, Copy)]
+struct DefaultHelper;
+
+impl HelperDef for DefaultHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let value = h
+            .param(0)
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex("default", 0))?;
+        let fallback = h
+            .param(1)
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex("default", 1))?;
+        let chosen = if value.value().is_truthy(false) {
+            value
+        } else {
+            fallback
+        };
+        Ok(ScopedJson::Derived(chosen.value().clone()))
+    }
+}
+
+/// DateHelper is a date and time helper that works in the timezone given by its
+/// `tz` hash argument, falling back to the server-wide default timezone.
+#[derive(Clone, Copy)]
+struct DateHelper {
+    timezone: Tz,
+    f: fn(&Helper, Tz) -> Result<JsonValue, RenderError>,
+}
+
+impl HelperDef for DateHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let timezone = match h.hash_get("tz").map(|tz| tz.value().render()) {
+            Some(name) => name
+                .parse::<Tz>()
+                .map_err(|_| RenderErrorReason::Other(format!("unknown timezone \"{}\"", name)))?,
+            None => self.timezone,
+        };
+        (self.f)(h, timezone).map(ScopedJson::Derived)
+    }
+}
+
+// reads the RFC 3339 date at the given parameter index, in the given timezone
+fn date_param(h: &Helper, index: usize, timezone: Tz) -> Result<DateTime<Tz>, RenderError> {
+    let param = h
+        .param(index)
+        .ok_or(RenderErrorReason::ParamNotFoundForIndex("date", index))?
+        .value()
+        .render();
+    DateTime::parse_from_rfc3339(&param)
+        .map(|t| t.with_timezone(&timezone))
+        .map_err(|e| RenderErrorReason::Other(format!("\"{}\" is not an RFC 3339 date: {}", param, e)).into())
+}
+
+fn str_param<'a>(h: &'a Helper, index: usize, name: &'static str) -> Result<&'a str, RenderError> {
+    h.param(index)
+        .and_then(|p| p.value().as_str())
+        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex(name, index).into())
+}
+
+fn i64_param(h: &Helper, index: usize, name: &'static str) -> Result<i64, RenderError> {
+    h.param(index)
+        .and_then(|p| p.value().as_i64())
+        .ok_or_else(|| RenderErrorReason::ParamNotFoundForIndex(name, index).into())
+}
+
+// formats the date, rejecting invalid format strings instead of panicking
+fn format_date(t: &DateTime<Tz>, format: &str) -> Result<JsonValue, RenderError> {
+    let mut formatted = String::new();
+    write!(formatted, "{}", t.format(format))
+        .map_err(|_| RenderErrorReason::Other(format!("invalid date format \"{}\"", format)))?;
+    Ok(JsonValue::String(formatted))
+}
+
+fn now(_: &Helper, timezone: Tz) -> Result<JsonValue, RenderError> {
+    Ok(JsonValue::String(Utc::now().with_timezone(&timezone).to_rfc3339()))
+}
+
+fn now_format(h: &Helper, timezone: Tz) -> Result<JsonValue, RenderError> {
+    format_date(&Utc::now().with_timezone(&timezone), str_param(h, 0, "format")?)
+}
+
+fn date_format(h: &Helper, timezone: Tz) -> Result<JsonValue, RenderError> {
+    format_date(&date_param(h, 0, timezone)?, str_param(h, 1, "format")?)
+}
+
+// adds calendar days, so the local time of day is kept across daylight saving changes
+fn add_days(h: &Helper, timezone: Tz) -> Result<JsonValue, RenderError> {
+    shift_days(date_param(h, 0, timezone)?, i64_param(h, 1, "days")?)
+}
+
+fn sub_days(h: &Helper, timezone: Tz) -> Result<JsonValue, RenderError> {
+    let t = date_param(h, 0, timezone)?;
+    let days = i64_param(h, 1, "days")?;
+    let negated = days
+        .checked_neg()
+        .ok_or_else(|| RenderErrorReason::Other(format!("cannot shift {} back by {} days", t.to_rfc3339(), days)))?;
+    shift_days(t, negated)
+}
+
+fn shift_days(t: DateTime<Tz>, days: i64) -> Result<JsonValue, RenderError> {
+    let shifted = if days < 0 {
+        t.checked_sub_days(Days::new(days.unsigned_abs()))
+    } else {
+        t.checked_add_days(Days::new(days as u64))
+    };
+    shifted
+        .map(|t| JsonValue::String(t.to_rfc3339()))
+        .ok_or_else(|| RenderErrorReason::Other(format!("cannot shift {} by {} days", t.to_rfc3339(), days)).into())
+}
+
+// midnight on the Monday of the date's week
+fn start_of_week(h: &Helper, timezone: Tz) -> Result<JsonValue, RenderError> {
+    let t = date_param(h, 0, timezone)?;
+    let monday = t.date_naive() - Days::new(t.weekday().num_days_from_monday() as u64);
+    timezone
+        .from_local_datetime(&monday.and_time(NaiveTime::MIN))
+        .earliest()
+        .map(|t| JsonValue::String(t.to_rfc3339()))
+        .ok_or_else(|| RenderErrorReason::Other(format!("no midnight on {} in {}", monday, timezone)).into())
+}
+
+fn iso_week(h: &Helper, timezone: Tz) -> Result<JsonValue, RenderError> {
+    Ok(JsonValue::from(date_param(h, 0, timezone)?.iso_week().week()))
+}
+
+fn quarter(h: &Helper, timezone: Tz) -> Result<JsonValue, RenderError> {
+    Ok(JsonValue::from(date_param(h, 0, timezone)?.month0() / 3 + 1))
+}
+
+/// Registers the helpers available to both page templates and destination link templates.
+/// Date helpers work in the given timezone unless a template asks for another.
+pub(crate) fn register_helpers(handlebars: &mut Handlebars, timezone: Tz) {
+    let date_helper = |f| Box::new(DateHelper { timezone, f });
+    handlebars.register_helper("query_escape", Box::new(query_escape));
+    handlebars.register_helper("path_escape", Box::new(path_escape));
+    handlebars.register_helper("component_escape", Box::new(component_escape));
+    handlebars.register_helper("lowercase", Box::new(to_lower));
+    handlebars.register_helper("uppercase", Box::new(to_upper));
+    handlebars.register_helper("trimsuffix", Box::new(trim_suffix));
+    handlebars.register_helper("trimprefix", Box::new(trim_prefix));
+    handlebars.register_helper("now", date_helper(now));
+    handlebars.register_helper("nowformat", date_helper(now_format));
+    handlebars.register_helper("dateformat", date_helper(date_format));
+    handlebars.register_helper("adddays", date_helper(add_days));
+    handlebars.register_helper("subdays", date_helper(sub_days));
+    handlebars.register_helper("startofweek", date_helper(start_of_week));
+    handlebars.register_helper("isoweek", date_helper(iso_week));
+    handlebars.register_helper("quarter", date_helper(quarter));
+    handlebars.register_helper("match", Box::new(match_string));
+    handlebars.register_helper("split", Box::new(split));
+    handlebars.register_helper("segment", Box::new(segment));
+    handlebars.register_helper("join", Box::new(join));
+    handlebars.register_helper("replace", Box::new(regex_replace));
+    handlebars.register_helper("capture", Box::new(capture));
+    handlebars.register_helper("default", Box::new(DefaultHelper));
+    handlebars.register_helper("base64", Box::new(base64));
+    handlebars.register_helper("base64url", Box::new(base64url));
+    handlebars.register_helper("hex", Box::new(hex));
+    handlebars.register_helper("sha256", Box::new(sha256));
+    handlebars.register_helper("formatnumber", Box::new(format_number));
+    handlebars.register_helper("padleft", Box::new(pad_left));
+    handlebars.register_helper("padright", Box::new(pad_right));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // renders the template against the given path
+    fn render(template: &str, path: &str) -> String {
+        render_in(template, path, Tz::UTC).unwrap()
+    }
+
+    fn render_in(template: &str, path: &str, timezone: Tz) -> Result<String, RenderError> {
+        let mut handlebars = Handlebars::new();
+        handlebars.register_escape_fn(handlebars::no_escape);
+        register_helpers(&mut handlebars, timezone);
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        handlebars.render_template(template, &serde_json::json!({"path": path, "segments": segments}))
+    }
+
+    // reads the examples in the tables of help.hbs, each with the path it is shown
+    // with and its result, or with neither where the result depends on when it is rendered
+    fn help_examples() -> Vec<(String, Option<(String, String)>)> {
+        let help = std::fs::read_to_string("templates/help.hbs").unwrap();
+        let cell = Regex::new(r"(?s)<td>(.*?)</td>").unwrap();
+        let unescape = |html: &str| {
+            html.replace("<code>", "")
+                .replace("</code>", "")
+                .replace("&lbrace;", "{")
+                .replace("&rbrace;", "}")
+                .replace("&amp;", "&")
+        };
+        help.split("<tr>")
+            .filter_map(|row| {
+                let cells: Vec<&str> = cell.captures_iter(row).map(|c| c.get(1).unwrap().as_str()).collect();
+                match cells.as_slice() {
+                    // the helper functions, with or without a path
+                    [_, example, path, result] if example.starts_with("<code>") => {
+                        Some((unescape(example), Some((unescape(path), unescape(result)))))
+                    }
+                    [_, example, _] if example.starts_with("<code>") => Some((unescape(example), None)),
+                    // the example destination links
+                    [_, _, long] if long.contains("&lbrace;") => Some((unescape(long), None)),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_help_examples() {
+        let examples = help_examples();
+        assert_eq!(examples.iter().filter(|(_, result)| result.is_some()).count(), 13);
+        assert_eq!(examples.len(), 26);
+        for (template, result) in examples {
+            match result {
+                Some((path, result)) => assert_eq!(render(&template, &path), result, "{template}"),
+                None => assert!(render_in(&template, "pangolins", Tz::UTC).is_ok(), "{template}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            render(r#"{{#each (split path ",")}}&tag={{this}}{{/each}}"#, "a,b"),
+            "&tag=a&tag=b"
+        );
+        assert_eq!(render(r#"{{#each (split path ",")}}&tag={{this}}{{/each}}"#, ""), "");
+    }
+
+    #[test]
+    fn test_segment() {
+        assert_eq!(render("{{segment path 0}}", "rust-lang/rust/issues"), "rust-lang");
+        assert_eq!(render("{{segment path -1}}", "rust-lang/rust/issues"), "issues");
+        assert_eq!(render("{{segment path 5}}", "rust-lang/rust/issues"), "");
+    }
+
+    #[test]
+    fn test_join() {
+        assert_eq!(render(r#"{{join segments "+"}}"#, "foo/bar"), "foo+bar");
+    }
+
+    #[test]
+    fn test_replace() {
+        assert_eq!(render(r#"{{replace " +" "-" path}}"#, "foo bar  baz"), "foo-bar-baz");
+        assert_eq!(
+            render(r#"{{replace "([a-z]+)@([a-z]+)" "$2/$1" path}}"#, "amelie@corp"),
+            "corp/amelie"
+        );
+    }
+
+    #[test]
+    fn test_capture() {
+        assert_eq!(render(r#"{{capture "JIRA-([0-9]+)" path 1}}"#, "JIRA-123"), "123");
+        assert_eq!(render(r#"{{capture "(?<id>[0-9]+)" path "id"}}"#, "JIRA-123"), "123");
+        assert_eq!(render(r#"{{capture "JIRA-([0-9]+)" path 1}}"#, "nothing"), "");
+    }
+
+    #[test]
+    fn test_default() {
+        assert_eq!(render(r#"{{default (segment path 0) "main"}}"#, ""), "main");
+        assert_eq!(render(r#"{{default (segment path 0) "main"}}"#, "dev"), "dev");
+    }
+
+    #[test]
+    fn test_encodings() {
+        assert_eq!(render("{{base64 path}}", "hello"), "aGVsbG8=");
+        assert_eq!(render("{{base64url path}}", "hello?"), "aGVsbG8_");
+        assert_eq!(render("{{hex path}}", "hi"), "6869");
+        assert_eq!(render("{{component_escape path}}", "a&b=c d/e"), "a%26b%3Dc%20d%2Fe");
+        assert_eq!(
+            render("{{sha256 path}}", "hello"),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(render("{{formatnumber path 2}}", "3.14159"), "3.14");
+        assert_eq!(
+            render(r#"{{formatnumber path 0 separator=","}}"#, "1234567"),
+            "1,234,567"
+        );
+        assert_eq!(
+            render(r#"{{formatnumber path 1 separator=","}}"#, "-1234.56"),
+            "-1,234.6"
+        );
+        assert_eq!(render("{{formatnumber path 2}}", "pi"), "pi");
+        assert!(render_in("{{formatnumber path 100000}}", "1", Tz::UTC).is_err());
+    }
+
+    #[test]
+    fn test_padding() {
+        assert_eq!(render(r#"{{padleft path 4 pad="0"}}"#, "42"), "0042");
+        assert_eq!(render(r#"{{padright path 4 pad="."}}"#, "42"), "42..");
+        assert_eq!(render("{{padleft path 1}}", "42"), "42");
+        assert_eq!(render("{{padright path 8192}}", "").len(), MAX_LINK_LENGTH);
+        assert!(render_in("{{padleft path 8193}}", "42", Tz::UTC).is_err());
+        assert!(render_in("{{padright path 18446744073709551615}}", "42", Tz::UTC).is_err());
+    }
+
+    #[test]
+    fn test_regex_limits() {
+        assert_eq!(render(r#"{{match "^a+$" path}}"#, &"a".repeat(100)), "true");
+        assert_eq!(
+            render(r#"{{match "^a+$" path}}"#, &"a".repeat(MAX_INPUT_LENGTH + 1)),
+            "false"
+        );
+        // compiles to far more than the size limit
+        assert_eq!(render(r#"{{match "\\w{500}" path}}"#, "a"), "false");
+        assert_eq!(render(r#"{{replace "\\w{500}" "b" path}}"#, "a"), "a");
+    }
+
+    #[test]
+    fn test_date_format_timezone() {
+        assert_eq!(
+            render(
+                r#"{{dateformat path "%Y-%m-%d" tz="America/New_York"}}"#,
+                "2024-03-10T01:30:00Z"
+            ),
+            "2024-03-09"
+        );
+        assert_eq!(
+            render_in(
+                r#"{{dateformat path "%Y-%m-%d"}}"#,
+                "2024-01-01T23:30:00Z",
+                Tz::Europe__Paris
+            )
+            .unwrap(),
+            "2024-01-02"
+        );
+        assert!(render_in(r#"{{now tz="Mars/Olympus_Mons"}}"#, "", Tz::UTC).is_err());
+        assert!(render_in(r#"{{dateformat path "%Y"}}"#, "yesterday", Tz::UTC).is_err());
+        assert!(render_in(r#"{{nowformat "%Q"}}"#, "", Tz::UTC).is_err());
+    }
+
+    #[test]
+    fn test_now_timezone() {
+        let now: DateTime<chrono::FixedOffset> = render(r#"{{now tz="Asia/Kolkata"}}"#, "").parse().unwrap();
+        assert_eq!(now.offset().local_minus_utc(), 5 * 3600 + 30 * 60);
+        let yesterday = (Utc::now() - Days::new(1)).format("%Y-%m-%d").to_string();
+        assert_eq!(render(r#"{{dateformat (adddays (now) -1) "%Y-%m-%d"}}"#, ""), yesterday);
+    }
+
+    #[test]
+    fn test_date_arithmetic() {
+        // the local time of day is kept across the start of daylight saving time
+        assert_eq!(
+            render(
+                r#"{{adddays path 1 tz="America/New_York"}}"#,
+                "2024-03-09T12:00:00-05:00"
+            ),
+            "2024-03-10T12:00:00-04:00"
+        );
+        assert_eq!(
+            render("{{subdays path 31}}", "2024-03-01T00:00:00Z"),
+            "2024-01-30T00:00:00+00:00"
+        );
+        assert_eq!(
+            render("{{startofweek path}}", "2024-03-14T15:00:00Z"),
+            "2024-03-11T00:00:00+00:00"
+        );
+        assert_eq!(
+            render(r#"{{startofweek path tz="Australia/Sydney"}}"#, "2024-03-17T20:00:00Z"),
+            "2024-03-18T00:00:00+11:00"
+        );
+        for days in [i64::MIN, i64::MAX] {
+            let template = format!("{{{{subdays path {days}}}}}");
+            assert!(render_in(&template, "2024-03-01T00:00:00Z", Tz::UTC).is_err());
+        }
+        assert_eq!(render("{{isoweek path}}", "2024-12-30T12:00:00Z"), "1");
+        assert_eq!(render("{{quarter path}}", "2024-05-01T12:00:00Z"), "2");
+        assert_eq!(render("{{quarter path}}", "2024-12-31T12:00:00Z"), "4");
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::{BTreeMap, HashMap};
+
+use url::Url;
+
+use crate::model;
+
+// short names shorter than this are too short to tell typos from different names
+const MIN_COLLISION_LENGTH: usize = 4;
+
+/// Returns a destination link without the differences that do not change
+/// where it goes: the scheme of web links, a leading "www.", a trailing slash
+/// and the fragment. Templated destination links are only trimmed.
+pub(crate) fn normalized_target(long: &str) -> String {
+    let long = long.trim();
+    if long.contains("{{") {
+        return long.to_string();
+    }
+    let Ok(url) = Url::parse(long) else {
+        return long.to_lowercase();
+    };
+    let host = url.host_str().unwrap_or_default();
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    let port = url.port().map(|port| format!(":{port}")).unwrap_or_default();
+    let path = url.path().trim_end_matches('/');
+    let query = url.query().map(|query| format!("?{query}")).unwrap_or_default();
+    match url.scheme() {
+        "http" | "https" => format!("{host}{port}{path}{query}"),
+        scheme => format!("{scheme}:{host}{port}{path}{query}"),
+    }
+}
+
+/// Returns whether two normalized ids differ by a single inserted, deleted or
+/// replaced character, like "teamnotes" and "teamnote".
+pub(crate) fn near_collision(a: &str, b: &str) -> bool {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    if a == b || a.len().min(b.len()) < MIN_COLLISION_LENGTH || a.len().abs_diff(b.len()) > 1 {
+        return false;
+    }
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    a.len().max(b.len()) - prefix - suffix <= 1
+}
+
+/// Groups links that have the same normalized destination link.
+pub(crate) fn duplicates(links: &[model::HygieneLink]) -> Vec<model::LinkGroup> {
+    let mut groups: BTreeMap<String, Vec<model::HygieneLink>> = BTreeMap::new();
+    for link in links {
+        groups
+            .entry(normalized_target(&link.long))
+            .or_default()
+            .push(link.clone());
+    }
+    groups
+        .into_iter()
+        .filter(|(_, links)| links.len() > 1)
+        .map(|(key, links)| model::LinkGroup { key, links })
+        .collect()
+}
+
+/// Groups links whose short names are near collisions of each other, directly
+/// or through other links in the group.
+pub(crate) fn collisions(links: &[model::HygieneLink]) -> Vec<model::LinkGroup> {
+    let ids: Vec<String> = links.iter().map(|link| model::normalized_id(&link.short)).collect();
+    // each link starts in its own group, and groups are joined by pointing one at the other
+    let mut parent: Vec<usize> = (0..links.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for i in 0..ids.len() {
+        for j in i + 1..ids.len() {
+            if near_collision(&ids[i], &ids[j]) {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<model::HygieneLink>> = HashMap::new();
+    for (i, link) in links.iter().enumerate() {
+        let group = root(&mut parent, i);
+        groups.entry(group).or_default().push(link.clone());
+    }
+    let mut groups: Vec<model::LinkGroup> = groups
+        .into_values()
+        .filter(|links| links.len() > 1)
+        .map(|links| model::LinkGroup {
+            key: model::normalized_id(&links[0].short),
+            links,
+        })
+        .collect();
+    groups.sort_by(|a, b| a.key.cmp(&b.key));
+    groups
+}
+
+/// Returns what looks wrong with a destination link that is not a template,
+/// beyond what saving a link checks.
+pub(crate) fn target_problems(long: &str) -> Vec<String> {
+    if long.contains("{{") {
+        return Vec::new();
+    }
+    let mut problems = Vec::new();
+    if long.trim() != long || long.contains(char::is_whitespace) {
+        problems.push("contains whitespace".to_string());
+    }
+    match Url::parse(long.trim()) {
+        Ok(url) if !matches!(url.scheme(), "http" | "https") => {
+            problems.push(format!("is not a web link ({}:)", url.scheme()))
+        }
+        Ok(url) if url.host_str().is_none_or(str::is_empty) => problems.push("has no host".to_string()),
+        Ok(_) => {}
+        Err(e) => problems.push(format!("is not a URL: {e}")),
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(short: &str, long: &str) -> model::HygieneLink {
+        model::HygieneLink {
+            short: short.to_string(),
+            long: long.to_string(),
+            created: chrono::Utc::now(),
+            usage: model::LinkUsage::default(),
+        }
+    }
+
+    #[test]
+    fn test_normalized_target() {
+        for (long, normalized) in [
+            ("https://www.Example.com/docs/", "example.com/docs"),
+            ("http://example.com/docs#intro", "example.com/docs"),
+            ("http://example.com:8080/?q=1", "example.com:8080?q=1"),
+            ("ftp://files.example/pub", "ftp:files.example/pub"),
+            (" https://example.com/{{path}} ", "https://example.com/{{path}}"),
+        ] {
+            assert_eq!(normalized_target(long), normalized, "{long}");
+        }
+    }
+
+    #[test]
+    fn test_near_collision() {
+        assert!(near_collision("teamnotes", "teamnote"));
+        assert!(near_collision("oncall", "oncal1"));
+        assert!(near_collision("wiki", "wikis"));
+        assert!(!near_collision("wiki", "wiki"));
+        assert!(!near_collision("vpn", "vpm")); // too short to tell
+        assert!(!near_collision("teamnotes", "teamnotess2"));
+        assert!(!near_collision("calendar", "calender2"));
+    }
+
+    #[test]
+    fn test_groups() {
+        let links = [
+            link("docs", "https://www.example.com/docs/"),
+            link("documentation", "http://example.com/docs"),
+            link("notes", "http://notes.example"),
+            link("note", "http://other.example"),
+            link("nodes", "http://nodes.example"),
+            link("wiki", "http://wiki.example"),
+        ];
+        let shorts = |group: &model::LinkGroup| group.links.iter().map(|l| l.short.clone()).collect::<Vec<_>>();
+
+        let duplicates = duplicates(&links);
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].key, "example.com/docs");
+        assert_eq!(shorts(&duplicates[0]), vec!["docs", "documentation"]);
+
+        let collisions = collisions(&links);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(shorts(&collisions[0]), vec!["notes", "note", "nodes"]);
+    }
+
+    #[test]
+    fn test_target_problems() {
+        assert!(target_problems("https://example.com/a").is_empty());
+        assert!(target_problems("https://example.com/{{ path }}").is_empty());
+        assert_eq!(
+            target_problems("javascript:alert(1)"),
+            vec!["is not a web link (javascript:)"]
+        );
+        assert_eq!(target_problems("http://example.com/a b"), vec!["contains whitespace"]);
+        assert_eq!(target_problems("example.com").len(), 1);
+    }
+}
@@ -1,14 +1,39 @@
+use std::net::IpAddr;
+
 use serde::{Deserialize, Serialize};
+use shadow_rs::shadow;
 
+mod api;
+mod cache;
+pub mod checker;
+mod clicks;
 pub mod db;
+pub mod golink;
+mod helpers;
+mod hygiene;
+pub mod metrics;
 pub mod model;
+pub mod openapi;
+pub mod ratelimit;
 pub mod render;
 pub mod routes;
+pub mod server;
+pub mod template;
+
+shadow!(build);
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CreateUpdateRequest {
     pub short: String,
     pub target: String,
+    #[serde(default)]
+    pub status: Option<model::RedirectStatus>, // the redirect status, or none for the default or to keep the current one
+    #[serde(default, deserialize_with = "model::present")]
+    pub max_age: Option<Option<u32>>, // the max age or null for no caching, or left out for no caching or to keep the current one
+    #[serde(default)]
+    pub passthrough: Option<model::Passthrough>, // path and query handling, or none for the default or to keep the current one
+    #[serde(default)]
+    pub dont_track: Option<bool>, // whether clicks go unrecorded, or none to record them or to keep the current setting
 }
 
 impl From<CreateUpdateRequest> for model::Link {
@@ -18,6 +43,20 @@ impl From<CreateUpdateRequest> for model::Link {
             long: val.target.clone(),
             created: chrono::Utc::now(),
             updated: chrono::Utc::now(),
+            status: val.status.unwrap_or_default(),
+            max_age: val.max_age.flatten(),
+            passthrough: val.passthrough.unwrap_or_default(),
+            dont_track: val.dont_track.unwrap_or_default(),
         }
     }
 }
+
+/// RequestContext describes the client request a link is being resolved for.
+#[derive(Clone, Debug, Default)]
+pub struct RequestContext {
+    pub method: warp::http::Method,
+    pub host: Option<String>,
+    pub client_ip: Option<IpAddr>,
+    pub headers: warp::http::HeaderMap,
+    pub user: Option<String>,
+}
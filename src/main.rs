@@ -1,13 +1,15 @@
 use std::{net::SocketAddr, path::Path, time::Duration};
 
 use clap::Parser;
-use gohome::render::Renderer;
+use gohome::{
+    build,
+    checker::{Checker, CheckerOptions},
+    ratelimit::RateLimit,
+    render::{self, Options, Renderer},
+};
 use handlebars::Handlebars;
-use shadow_rs::shadow;
 use tracing_subscriber::EnvFilter;
 
-shadow!(build);
-
 #[derive(Parser, Debug)]
 #[command(version = build::VERSION, long_version = build::CLAP_LONG_VERSION, about = "", long_about = "")]
 struct Args {
@@ -21,6 +23,52 @@ struct Args {
     templates_dir: String,
     #[arg(long, default_value = "/usr/src/assets")]
     assets_dir: String,
+    #[arg(long, env = "TIMEZONE", default_value = "UTC")]
+    timezone: chrono_tz::Tz,
+    #[arg(long, env = "LINK_CACHE_SIZE", default_value_t = 1024)]
+    link_cache_size: usize,
+    #[arg(long, env = "CLICK_FLUSH_INTERVAL", default_value_t = 1000)]
+    click_flush_interval: u64,
+    #[arg(long, env = "BOT_USER_AGENTS", value_delimiter = ',', default_value = render::DEFAULT_BOT_USER_AGENTS)]
+    bot_user_agents: Vec<String>,
+    #[arg(long, env = "DISABLE_TRACKING")]
+    disable_tracking: bool,
+    #[arg(long, env = "CLICK_RETENTION_DAYS", default_value_t = render::CLICK_RETENTION_DAYS)]
+    click_retention_days: u32,
+    #[arg(long, env = "CLICK_SUBNET_KEY", hide_env_values = true)]
+    click_subnet_key: Option<String>,
+    #[arg(long, env = "CHECK_LINKS_INTERVAL")]
+    check_links_interval: Option<u64>,
+    #[arg(long, env = "CHECK_LINKS_CONCURRENCY", default_value_t = 4)]
+    check_links_concurrency: usize,
+    #[arg(long, env = "CHECK_LINKS_RATE", default_value_t = 2.0, value_parser = parse_rate)]
+    check_links_rate: f64,
+    #[arg(long, env = "CHECK_LINKS_TIMEOUT", default_value_t = 10)]
+    check_links_timeout: u64,
+    #[arg(long, env = "REQUIRE_API_TOKENS")]
+    require_api_tokens: bool,
+    #[arg(long, env = "WRITE_RATE_LIMIT", default_value_t = RateLimit::WRITES.per_minute)]
+    write_rate_limit: u32,
+    #[arg(long, env = "WRITE_RATE_BURST", default_value_t = RateLimit::WRITES.burst)]
+    write_rate_burst: u32,
+    #[arg(long, env = "REDIRECT_RATE_LIMIT", default_value_t = RateLimit::REDIRECTS.per_minute)]
+    redirect_rate_limit: u32,
+    #[arg(long, env = "REDIRECT_RATE_BURST", default_value_t = RateLimit::REDIRECTS.burst)]
+    redirect_rate_burst: u32,
+    #[arg(long, env = "TRUSTED_PROXIES", value_delimiter = ',')]
+    trusted_proxies: Vec<ipnet::IpNet>,
+    /// Prints a new admin API token with this name and exits, to create the first token with
+    #[arg(long, value_name = "NAME", value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    issue_admin_token: Option<String>,
+}
+
+// parses a number of requests per second, which must be above zero
+fn parse_rate(value: &str) -> Result<f64, String> {
+    let rate: f64 = value.parse().map_err(|e: std::num::ParseFloatError| e.to_string())?;
+    match gohome::checker::request_period(rate) {
+        Some(_) => Ok(rate),
+        None => Err(format!("{value} is not a number of requests per second above zero")),
+    }
 }
 
 #[tokio::main]
@@ -44,6 +92,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // templating config
     let mut handlebars = Handlebars::new();
+    handlebars
+        .register_template_file("admin", format!("{}/admin.hbs", args.templates_dir))
+        .unwrap();
+    handlebars
+        .register_template_file("hygiene", format!("{}/hygiene.hbs", args.templates_dir))
+        .unwrap();
     handlebars
         .register_template_file("all", format!("{}/all.hbs", args.templates_dir))
         .unwrap();
@@ -65,23 +119,72 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     handlebars
         .register_template_file("success", format!("{}/success.hbs", args.templates_dir))
         .unwrap();
+    handlebars
+        .register_template_file("wanted", format!("{}/wanted.hbs", args.templates_dir))
+        .unwrap();
 
-    let renderer = Renderer::new(&args.domain, db, handlebars);
-    let routes = gohome::routes::get_routes(renderer, args.assets_dir);
+    let options = Options {
+        timezone: args.timezone,
+        link_cache_size: args.link_cache_size,
+        bot_user_agents: args.bot_user_agents.clone(),
+        track_clicks: !args.disable_tracking,
+        click_retention_days: args.click_retention_days,
+        subnet_key: args.click_subnet_key.clone(),
+        require_api_tokens: args.require_api_tokens,
+        write_limit: RateLimit {
+            per_minute: args.write_rate_limit,
+            burst: args.write_rate_burst,
+        },
+        redirect_limit: RateLimit {
+            per_minute: args.redirect_rate_limit,
+            burst: args.redirect_rate_burst,
+        },
+        trusted_proxies: args.trusted_proxies.clone(),
+    };
+    // broken link checks are opt-in, since they send requests to every destination link
+    if let Some(interval) = args.check_links_interval {
+        let checker_options = CheckerOptions {
+            interval: Duration::from_secs(interval.max(1)),
+            concurrency: args.check_links_concurrency,
+            requests_per_second: args.check_links_rate,
+            timeout: Duration::from_secs(args.check_links_timeout),
+        };
+        tokio::spawn(Checker::new(db.clone(), checker_options)?.run());
+    }
+    let renderer = Renderer::with_options(&args.domain, db, handlebars, options);
+    if let Some(name) = args.issue_admin_token {
+        let issued = renderer
+            .issue_token(gohome::model::TokenInput {
+                name,
+                scope: gohome::model::TokenScope::Admin,
+                expires_in_days: None,
+            })
+            .await?;
+        println!("{}", issued.token);
+        return Ok(());
+    }
+    let flusher = renderer.clone();
+    let mut flush_interval = tokio::time::interval(Duration::from_millis(args.click_flush_interval.max(1)));
+    tokio::spawn(async move {
+        loop {
+            flush_interval.tick().await;
+            flusher.flush_clicks().await;
+        }
+    });
+    let routes = gohome::routes::get_routes(renderer.clone(), args.assets_dir);
 
+    gohome::metrics::init();
     tracing::info!("starting warp server: {}", &args.host);
     tracing::info!("sqlitedb: {}", db_path.to_str().unwrap());
-    warp::serve(routes)
-        .bind(args.host)
-        .await
-        .graceful(async {
-            tokio::signal::ctrl_c()
-                .await
-                .expect("\nfailed to install CTRL+C signal handler");
-        })
-        .run()
-        .await;
+    let listener = tokio::net::TcpListener::bind(args.host).await?;
+    gohome::server::serve(warp::service(routes), listener, async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("\nfailed to install CTRL+C signal handler");
+    })
+    .await;
 
+    renderer.flush_clicks().await;
     tracing::info!("gracefully exited.");
     tokio::time::sleep(Duration::from_secs(2)).await;
 
@@ -93,20 +196,21 @@ mod tests {
     use std::{collections::HashMap, net::IpAddr};
 
     use gohome::model;
+    use reqwest::Method;
 
     use super::*;
 
     #[tokio::test]
     async fn test_api_routes() -> Result<(), Box<dyn std::error::Error>> {
         let renderer = Renderer::empty();
-        let routes = gohome::routes::get_routes(renderer, "static".to_string());
+        let routes = gohome::routes::get_routes(renderer.clone(), "static".to_string());
 
         let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
         let addr = listener.local_addr()?;
         let ipv4_addr = IpAddr::from([127, 0, 0, 1]);
 
         let handler = tokio::task::spawn(async move {
-            warp::serve(routes).incoming(listener).run().await;
+            gohome::server::serve(warp::service(routes), listener, std::future::pending()).await;
         });
 
         let client = reqwest::Client::builder()
@@ -133,6 +237,20 @@ mod tests {
         assert_eq!(created_link.short, "nyt".to_string());
         assert_eq!(created_link.long, "http://www.nytimes.com".to_string());
 
+        // post an invalid destination link
+        form_data.insert("short".to_string(), "broken".to_string());
+        form_data.insert("long".to_string(), "{{#if path}}http://www.nytimes.com".to_string());
+        let invalid_post_request = client
+            .post(format!("http://{}/", addr))
+            .header("Sec-Golink", "1")
+            .form(&form_data)
+            .build()?;
+
+        let invalid_post_response = client.execute(invalid_post_request).await?;
+        assert_eq!(invalid_post_response.status(), warp::http::StatusCode::BAD_REQUEST);
+        let error = invalid_post_response.json::<model::ErrorResponse>().await?;
+        assert!(!error.details.is_empty());
+
         // read details go/short+
         let read_request = client.get(format!("http://{}/nyt+", addr)).build()?;
 
@@ -145,23 +263,61 @@ mod tests {
         assert_eq!(details.updated, created_link.updated); // updated should be the same as created
         assert!(details.clicks.is_some_and(|s| s == 0));
 
+        // preview go/short/path without following it
+        let preview_request = client
+            .get(format!("http://{}/.preview", addr))
+            .query(&[("path", "nyt/section/arts?a=1")])
+            .build()?;
+
+        let preview_response = client.execute(preview_request).await?;
+        assert_eq!(preview_response.status(), warp::http::StatusCode::OK);
+        let preview = preview_response.json::<model::Preview>().await?;
+        assert_eq!(preview.path, "section/arts".to_string());
+        assert_eq!(
+            preview.rendered,
+            Some("http://www.nytimes.com/section/arts".to_string())
+        );
+        assert_eq!(
+            preview.location,
+            Some("http://www.nytimes.com/section/arts?a=1".to_string())
+        );
+        assert!(preview.error.is_none());
+
         // trigger a click go/short
         let gohome_request = client.get(format!("http://{}/nyt", addr)).build()?;
 
         let gohome_response = client.execute(gohome_request).await?;
-        assert_eq!(gohome_response.status(), warp::http::StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(gohome_response.status(), warp::http::StatusCode::FOUND);
         assert_eq!(
             gohome_response.headers().get("Location").unwrap().to_str().unwrap(),
             "http://www.nytimes.com/"
         );
 
-        // read details go/short+ (again)
+        // read details go/short+ (again), once the click is written
+        renderer.flush_clicks().await;
         let read_request_post_click = client.get(format!("http://{}/nyt+", addr)).build()?;
 
         let read_response_post_click = client.execute(read_request_post_click).await?;
         assert_eq!(read_response_post_click.status(), warp::http::StatusCode::OK);
         let details_post_click = read_response_post_click.json::<model::LinkDetails>().await?;
-        assert!(details_post_click.clicks.is_some_and(|s| s == 1));
+        assert!(details_post_click.clicks.is_some_and(|s| s == 1)); // the preview is not counted
+
+        // windows too long to count back from now are answered rather than failing the request
+        client.get(format!("http://{}/?days=4000000000", addr)).send().await?;
+        let long_window_response = client
+            .get(format!("http://{}/nyt+?days=4000000000", addr))
+            .send()
+            .await?;
+        assert_eq!(long_window_response.status(), warp::http::StatusCode::OK);
+
+        // diagnostics go/.diagnostics
+        let diagnostics_request = client.get(format!("http://{}/.diagnostics", addr)).build()?;
+
+        let diagnostics_response = client.execute(diagnostics_request).await?;
+        assert_eq!(diagnostics_response.status(), warp::http::StatusCode::OK);
+        let diagnostics = diagnostics_response.json::<model::Diagnostics>().await?;
+        assert!(diagnostics.link_cache.entries > 0);
+        assert!(diagnostics.link_cache.misses > 0);
 
         // export go/.export
         let export_request = client.get(format!("http://{}/.export", addr)).build()?;
@@ -176,6 +332,345 @@ mod tests {
         assert_eq!(exported_link.created, created_link.created);
         assert_eq!(exported_link.updated, details_post_click.updated); // updated should be the same as post-click
 
+        // create, list and change links with the JSON API
+        let api_create_response = client
+            .post(format!("http://{}/api/v1/links", addr))
+            .header("Sec-Golink", "1")
+            .json(&serde_json::json!({"short": "cnn", "long": "https://www.cnn.com"}))
+            .send()
+            .await?;
+        assert_eq!(api_create_response.status(), warp::http::StatusCode::CREATED);
+        let api_forbidden_response = client
+            .delete(format!("http://{}/api/v1/links/cnn", addr))
+            .send()
+            .await?;
+        assert_eq!(api_forbidden_response.status(), warp::http::StatusCode::FORBIDDEN);
+        let api_unauthorized_response = client
+            .delete(format!("http://{}/api/v1/links/cnn", addr))
+            .bearer_auth("gohome_nope")
+            .header("Sec-Golink", "1")
+            .send()
+            .await?;
+        assert_eq!(api_unauthorized_response.status(), warp::http::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            api_unauthorized_response.headers().get("WWW-Authenticate").unwrap(),
+            "Bearer"
+        );
+        let api_patch_response = client
+            .patch(format!("http://{}/api/v1/links/cnn", addr))
+            .header("Sec-Golink", "1")
+            .json(&serde_json::json!({"dont_track": true}))
+            .send()
+            .await?;
+        assert!(api_patch_response.json::<model::Link>().await?.dont_track);
+        let api_list_response = client
+            .get(format!("http://{}/api/v1/links?limit=1", addr))
+            .send()
+            .await?;
+        assert_eq!(api_list_response.status(), warp::http::StatusCode::OK);
+        let page = api_list_response.json::<model::LinkPage>().await?;
+        assert_eq!(
+            (page.links[0].short.as_str(), page.total, page.next),
+            ("cnn", 2, Some(1))
+        );
+        let api_stats_response = client
+            .get(format!("http://{}/api/v1/links/nyt/stats", addr))
+            .send()
+            .await?;
+        assert_eq!(api_stats_response.json::<model::LinkStats>().await?.rollups.clicks, 1);
+        for (method, path, status) in [
+            (Method::GET, "/api/v1/nope", warp::http::StatusCode::NOT_FOUND),
+            (Method::PUT, "/api/v1/links", warp::http::StatusCode::METHOD_NOT_ALLOWED),
+        ] {
+            let api_error_response = client
+                .request(method, format!("http://{}{}", addr, path))
+                .send()
+                .await?;
+            assert_eq!(api_error_response.status(), status);
+            assert!(
+                !api_error_response
+                    .json::<model::ErrorResponse>()
+                    .await?
+                    .error
+                    .is_empty()
+            );
+        }
+
+        handler.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits() -> Result<(), Box<dyn std::error::Error>> {
+        let options = Options {
+            write_limit: RateLimit {
+                per_minute: 1,
+                burst: 2,
+            },
+            redirect_limit: RateLimit {
+                per_minute: 1,
+                burst: 3,
+            },
+            trusted_proxies: vec!["127.0.0.1/32".parse()?],
+            ..Default::default()
+        };
+        let renderer = Renderer::with_options("go", gohome::db::Db::in_memory()?, Handlebars::new(), options);
+        let routes = gohome::routes::get_routes(renderer.clone(), "static".to_string());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let handler = tokio::task::spawn(async move {
+            gohome::server::serve(warp::service(routes), listener, std::future::pending()).await;
+        });
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let create = |short: &str, forwarded_for: &str| {
+            client
+                .post(format!("http://{}/", addr))
+                .header("Sec-Golink", "1")
+                .header("X-Forwarded-For", forwarded_for)
+                .form(&[("short", short), ("long", "http://example.com")])
+                .send()
+        };
+
+        // writes are limited per client, who is told by the trusted proxy
+        assert_eq!(
+            create("a", "192.0.2.1").await?.status(),
+            warp::http::StatusCode::CREATED
+        );
+        assert_eq!(
+            create("b", "192.0.2.1").await?.status(),
+            warp::http::StatusCode::CREATED
+        );
+        let limited = create("c", "192.0.2.1").await?;
+        assert_eq!(limited.status(), warp::http::StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = limited.headers().get("Retry-After").unwrap().to_str()?.parse()?;
+        assert!((1..=60).contains(&retry_after));
+        assert_eq!(limited.json::<model::ErrorResponse>().await?.error, "too many requests");
+        assert_eq!(
+            create("c", "192.0.2.2").await?.status(),
+            warp::http::StatusCode::CREATED
+        );
+
+        // lookups have a budget of their own, and pages are not limited
+        for _ in 0..3 {
+            let response = client.get(format!("http://{}/a", addr)).send().await?;
+            assert_eq!(response.status(), warp::http::StatusCode::FOUND);
+        }
+        let response = client.get(format!("http://{}/a", addr)).send().await?;
+        assert_eq!(response.status(), warp::http::StatusCode::TOO_MANY_REQUESTS);
+        let response = client.get(format!("http://{}/.diagnostics", addr)).send().await?;
+        assert_eq!(response.status(), warp::http::StatusCode::OK);
+
+        handler.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_form_authorization() -> Result<(), Box<dyn std::error::Error>> {
+        let db = gohome::db::Db::in_memory()?;
+        let options = Options {
+            require_api_tokens: true,
+            trusted_proxies: vec!["127.0.0.1/32".parse()?],
+            ..Default::default()
+        };
+        let renderer = Renderer::with_options("go", db.clone(), Handlebars::new(), options);
+        let routes = gohome::routes::get_routes(renderer.clone(), "static".to_string());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let handler = tokio::task::spawn(async move {
+            gohome::server::serve(warp::service(routes), listener, std::future::pending()).await;
+        });
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let xsrf = renderer.xsrf();
+        let create_token = |user: Option<&str>| {
+            let mut request = client.post(format!("http://{}/.admin/tokens", addr)).form(&[
+                ("name", "ci"),
+                ("scope", "admin"),
+                ("xsrf", &xsrf),
+            ]);
+            if let Some(user) = user {
+                request = request.header("X-Forwarded-User", user);
+            }
+            request.send()
+        };
+
+        // an XSRF token scraped from a page is not enough to mint a token
+        let response = create_token(None).await?;
+        assert_eq!(response.status(), warp::http::StatusCode::UNAUTHORIZED);
+        assert!(db.tokens.load_all().await?.is_empty());
+
+        // nor to change links once API tokens are required
+        let response = client
+            .post(format!("http://{}/.create", addr))
+            .form(&[("short", "nyt"), ("long", "http://www.nytimes.com"), ("xsrf", &xsrf)])
+            .send()
+            .await?;
+        assert_eq!(response.status(), warp::http::StatusCode::UNAUTHORIZED);
+        assert!(db.link.load_all().await?.is_empty());
+
+        // but a user the trusted proxy signed in may
+        create_token(Some("amelie")).await?;
+        assert_eq!(db.tokens.load_all().await?.len(), 1);
+
+        // purging usage data needs an admin token, as managing tokens does
+        let write = renderer
+            .issue_token(gohome::model::TokenInput {
+                name: "ci".to_string(),
+                scope: gohome::model::TokenScope::Write,
+                expires_in_days: None,
+            })
+            .await?;
+        let response = client
+            .post(format!("http://{}/.admin/purge", addr))
+            .bearer_auth(&write.token)
+            .form(&[("xsrf", &xsrf)])
+            .send()
+            .await?;
+        assert_eq!(response.status(), warp::http::StatusCode::FORBIDDEN);
+
+        handler.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_link_forms() -> Result<(), Box<dyn std::error::Error>> {
+        let db = gohome::db::Db::in_memory()?;
+        let mut handlebars = Handlebars::new();
+        handlebars.register_templates_directory("templates", handlebars::DirectorySourceOptions::default())?;
+        let renderer = Renderer::with_options("go", db.clone(), handlebars, Options::default());
+        let routes = gohome::routes::get_routes(renderer.clone(), "static".to_string());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let handler = tokio::task::spawn(async move {
+            gohome::server::serve(warp::service(routes), listener, std::future::pending()).await;
+        });
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let xsrf = renderer.xsrf();
+        let post = |path: &str, form: &[(&str, &str)]| {
+            client
+                .post(format!("http://{}{}", addr, path))
+                .form(&[form, &[("xsrf", &xsrf)]].concat())
+                .send()
+        };
+
+        // a field that cannot be read is shown on the form, which keeps what was filled in
+        let response = post(
+            "/.create",
+            &[
+                ("short", "nyt"),
+                ("long", "http://www.nytimes.com"),
+                ("max_age", "soon"),
+            ],
+        )
+        .await?;
+        assert_eq!(response.status(), warp::http::StatusCode::BAD_REQUEST);
+        let body = response.text().await?;
+        assert!(body.contains("invalid max_age"));
+        assert!(body.contains("http://www.nytimes.com"));
+        assert!(db.link.load_all().await?.is_empty());
+
+        post(
+            "/.create",
+            &[
+                ("short", "nyt"),
+                ("long", "http://www.nytimes.com"),
+                ("max_age", "60"),
+                ("dont_track", "on"),
+            ],
+        )
+        .await?;
+        let response = post(
+            "/.update",
+            &[("short", "nyt"), ("long", "http://nytimes.com"), ("max_age", "-1")],
+        )
+        .await?;
+        assert_eq!(response.status(), warp::http::StatusCode::BAD_REQUEST);
+        assert!(response.text().await?.contains("invalid max_age"));
+        assert_eq!(db.link.load("nyt").await?.long, "http://www.nytimes.com");
+
+        // fields left out of an update keep what the link has
+        post("/.update", &[("short", "nyt"), ("long", "http://nytimes.com")]).await?;
+        let link = db.link.load("nyt").await?;
+        assert_eq!(link.long, "http://nytimes.com");
+        assert_eq!(link.max_age, Some(60));
+        assert!(link.dont_track);
+
+        // while the edit form clears them, with "off" sent for an unchecked box
+        post(
+            "/.update",
+            &[
+                ("short", "nyt"),
+                ("long", "http://nytimes.com"),
+                ("max_age", ""),
+                ("dont_track", "off"),
+            ],
+        )
+        .await?;
+        let link = db.link.load("nyt").await?;
+        assert_eq!(link.max_age, None);
+        assert!(!link.dont_track);
+        post(
+            "/.update",
+            &[
+                ("short", "nyt"),
+                ("long", "http://nytimes.com"),
+                ("dont_track", "off"),
+                ("dont_track", "on"),
+            ],
+        )
+        .await?;
+        assert!(db.link.load("nyt").await?.dont_track);
+
+        handler.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_metrics() -> Result<(), Box<dyn std::error::Error>> {
+        let renderer = Renderer::empty();
+        let routes = gohome::routes::get_routes(renderer.clone(), "static".to_string());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let handler = tokio::task::spawn(async move {
+            gohome::server::serve(warp::service(routes), listener, std::future::pending()).await;
+        });
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+
+        let response = client
+            .post(format!("http://{}/", addr))
+            .header("Sec-Golink", "1")
+            .form(&[("short", "metered"), ("long", "http://example.com/{{ path }}")])
+            .send()
+            .await?;
+        assert_eq!(response.status(), warp::http::StatusCode::CREATED);
+        client.get(format!("http://{}/metered/x", addr)).send().await?;
+        client.get(format!("http://{}/unmetered", addr)).send().await?;
+
+        let response = client.get(format!("http://{}/.metrics", addr)).send().await?;
+        assert_eq!(response.status(), warp::http::StatusCode::OK);
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            gohome::metrics::CONTENT_TYPE
+        );
+        let text = response.text().await?;
+        // requests are counted per route, not per link
+        assert!(text.contains(r#"gohome_http_requests_total{route="/",method="POST",status="201"}"#));
+        assert!(text.contains(r#"gohome_http_requests_total{route="/{short}",method="GET",status="302"}"#));
+        assert!(text.contains(r#"gohome_http_requests_total{route="/{short}",method="GET",status="404"}"#));
+        assert!(!text.contains("metered"));
+        assert!(text.contains(r#"gohome_redirects_total{outcome="hit"}"#));
+        assert!(text.contains(r#"gohome_redirects_total{outcome="miss"}"#));
+        assert!(text.contains(r#"gohome_db_operation_duration_seconds_count{operation="links.save"}"#));
+        assert!(text.contains("\ngohome_links 1\n"));
+        assert!(text.contains(&format!(r#"gohome_build_info{{version="{}""#, build::PKG_VERSION)));
+
         handler.abort();
         Ok(())
     }
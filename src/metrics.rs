@@ -0,0 +1,270 @@
+use std::{
+    fmt::Write,
+    sync::{LazyLock, atomic::AtomicU64},
+    time::{Duration, Instant, SystemTime},
+};
+
+use prometheus_client::{
+    encoding::{EncodeLabelSet, EncodeLabelValue, LabelValueEncoder},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{Histogram, exponential_buckets},
+        info::Info,
+    },
+    registry::{Registry, Unit},
+};
+
+use crate::build;
+
+/// The content type of the metrics served at /.metrics.
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RequestLabels {
+    route: &'static str,
+    method: &'static str,
+    status: u16,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RouteLabels {
+    route: &'static str,
+    method: &'static str,
+}
+
+/// RedirectOutcome is what became of a request to follow a link.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub(crate) enum RedirectOutcome {
+    Hit,
+    Miss, // the link does not exist, or does not accept the path
+    TemplateError,
+}
+
+impl EncodeLabelValue for RedirectOutcome {
+    fn encode(&self, encoder: &mut LabelValueEncoder) -> Result<(), std::fmt::Error> {
+        encoder.write_str(match self {
+            RedirectOutcome::Hit => "hit",
+            RedirectOutcome::Miss => "miss",
+            RedirectOutcome::TemplateError => "template_error",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RedirectLabels {
+    outcome: RedirectOutcome,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct OperationLabels {
+    operation: &'static str,
+}
+
+type Histograms<L> = Family<L, Histogram, fn() -> Histogram>;
+
+struct Metrics {
+    registry: Registry,
+    requests: Family<RequestLabels, Counter>,
+    request_duration: Histograms<RouteLabels>,
+    redirects: Family<RedirectLabels, Counter>,
+    db_duration: Histograms<OperationLabels>,
+    links: Gauge,
+    resident_memory: Gauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        // from half a millisecond to about 16 seconds
+        let seconds: fn() -> Histogram = || Histogram::new(exponential_buckets(0.0005, 2.0, 16));
+        let metrics = Self {
+            registry: Registry::default(),
+            requests: Family::default(),
+            request_duration: Family::new_with_constructor(seconds),
+            redirects: Family::default(),
+            db_duration: Family::new_with_constructor(seconds),
+            links: Gauge::default(),
+            resident_memory: Gauge::default(),
+        };
+        let mut registry = metrics.registry;
+        registry.register(
+            "gohome_http_requests",
+            "Requests served, by route",
+            metrics.requests.clone(),
+        );
+        registry.register_with_unit(
+            "gohome_http_request_duration",
+            "How long requests took to serve, by route",
+            Unit::Seconds,
+            metrics.request_duration.clone(),
+        );
+        registry.register(
+            "gohome_redirects",
+            "Links followed, by outcome",
+            metrics.redirects.clone(),
+        );
+        registry.register_with_unit(
+            "gohome_db_operation_duration",
+            "How long database operations took, including waiting for the connection",
+            Unit::Seconds,
+            metrics.db_duration.clone(),
+        );
+        registry.register("gohome_links", "Links saved", metrics.links.clone());
+        registry.register(
+            "gohome_build",
+            "The version gohome was built from",
+            Info::new(vec![
+                ("version", build::PKG_VERSION),
+                ("commit", build::SHORT_COMMIT),
+                ("branch", build::BRANCH),
+                ("build_time", build::BUILD_TIME),
+                ("rust_version", build::RUST_VERSION),
+            ]),
+        );
+
+        // the process metrics are named the same as other exporters name them
+        let process = registry.sub_registry_with_prefix("process");
+        let start_time = Gauge::<f64, AtomicU64>::default();
+        start_time.set(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        );
+        process.register_with_unit(
+            "start_time",
+            "When the process started, since the Unix epoch",
+            Unit::Seconds,
+            start_time,
+        );
+        process.register_with_unit(
+            "resident_memory",
+            "Memory the process has in RAM",
+            Unit::Bytes,
+            metrics.resident_memory.clone(),
+        );
+        Self { registry, ..metrics }
+    }
+}
+
+/// Starts the metrics clock, so that the start time is when the server
+/// started rather than when the metrics were first used.
+pub fn init() {
+    LazyLock::force(&METRICS);
+}
+
+// returns the label of a request method, which is "other" for extension methods so that
+// clients cannot add series of their own
+fn method_label(method: &str) -> &'static str {
+    ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"]
+        .into_iter()
+        .find(|known| *known == method)
+        .unwrap_or("other")
+}
+
+/// Counts a request served by a route, named as in [`crate::routes::route_name`].
+pub(crate) fn observe_request(route: &'static str, method: &str, status: u16, elapsed: Duration) {
+    let method = method_label(method);
+    METRICS
+        .requests
+        .get_or_create(&RequestLabels { route, method, status })
+        .inc();
+    METRICS
+        .request_duration
+        .get_or_create(&RouteLabels { route, method })
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Counts a link being followed.
+pub(crate) fn observe_redirect(outcome: RedirectOutcome) {
+    METRICS.redirects.get_or_create(&RedirectLabels { outcome }).inc();
+}
+
+/// DbTimer times a database operation from when it is created until it is dropped.
+pub(crate) struct DbTimer {
+    operation: &'static str,
+    start: Instant,
+}
+
+impl Drop for DbTimer {
+    fn drop(&mut self) {
+        METRICS
+            .db_duration
+            .get_or_create(&OperationLabels {
+                operation: self.operation,
+            })
+            .observe(self.start.elapsed().as_secs_f64());
+    }
+}
+
+/// Starts timing a database operation, such as "links.save".
+pub(crate) fn time_db(operation: &'static str) -> DbTimer {
+    DbTimer {
+        operation,
+        start: Instant::now(),
+    }
+}
+
+/// Encodes every metric in the OpenMetrics text format, with the number of
+/// links saved as given.
+pub(crate) fn encode(links: Option<usize>) -> String {
+    if let Some(links) = links {
+        METRICS.links.set(i64::try_from(links).unwrap_or(i64::MAX));
+    }
+    if let Some(bytes) = resident_memory() {
+        METRICS.resident_memory.set(i64::try_from(bytes).unwrap_or(i64::MAX));
+    }
+    let mut text = String::new();
+    // writing to a String cannot fail
+    let _ = prometheus_client::encoding::text::encode(&mut text, &METRICS.registry);
+    text
+}
+
+// reads the resident set size from /proc, where there is one
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_resident_memory(&status)
+}
+
+fn parse_resident_memory(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes = line.trim_start_matches("VmRSS:").trim().trim_end_matches("kB").trim();
+    kilobytes.parse::<u64>().ok().map(|kb| kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        observe_request("/{short}", "GET", 302, Duration::from_millis(3));
+        observe_request("/{short}", "FOOA", 405, Duration::from_millis(1));
+        observe_request("/{short}", "FOOB", 405, Duration::from_millis(1));
+        observe_redirect(RedirectOutcome::TemplateError);
+        drop(time_db("links.find"));
+
+        let text = encode(Some(7));
+        assert!(text.contains(r#"gohome_http_requests_total{route="/{short}",method="GET",status="302"}"#));
+        assert!(text.contains(r#"gohome_http_request_duration_seconds_count{route="/{short}",method="GET"}"#));
+        // methods a client makes up share one series
+        assert!(text.contains(r#"gohome_http_requests_total{route="/{short}",method="other",status="405"} 2"#));
+        assert!(!text.contains("FOOA"));
+        assert!(text.contains(r#"gohome_redirects_total{outcome="template_error"}"#));
+        assert!(text.contains(r#"gohome_db_operation_duration_seconds_count{operation="links.find"}"#));
+        assert!(text.contains("gohome_links 7\n"));
+        assert!(text.contains(&format!(r#"gohome_build_info{{version="{}""#, build::PKG_VERSION)));
+        assert!(text.contains("\nprocess_start_time_seconds "));
+        assert!(text.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_parse_resident_memory() {
+        let status = "Name:\tgohome\nVmPeak:\t  20000 kB\nVmRSS:\t   12345 kB\nThreads:\t4\n";
+        assert_eq!(parse_resident_memory(status), Some(12345 * 1024));
+        assert_eq!(parse_resident_memory("Name:\tgohome\n"), None);
+    }
+}
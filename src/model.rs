@@ -1,13 +1,22 @@
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 /// Link is the structure stored for each go short link.
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, ToSchema)]
 pub struct Link {
     pub short: String, // the user-provided "foo" part of "http://go/foo"
     pub long: String,  // the target URL or text/template pattern to run
     pub created: chrono::DateTime<Utc>,
     pub updated: chrono::DateTime<Utc>,
+    #[serde(default)]
+    pub status: RedirectStatus, // the HTTP status clicks are redirected with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u32>, // seconds a browser may cache the redirect, or none to ask again every time
+    #[serde(flatten)]
+    pub passthrough: Passthrough, // what happens to the path and query parameters of a click
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dont_track: bool, // clicks on the link are not recorded
 }
 
 impl std::fmt::Display for Link {
@@ -18,11 +27,193 @@ impl std::fmt::Display for Link {
     }
 }
 
+/// RedirectStatus is the HTTP status a link redirects with. Browsers remember
+/// permanent redirects, so a change to a link using one may not be seen by
+/// people who have followed it before; temporary redirects are the default.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum RedirectStatus {
+    MovedPermanently,
+    #[default]
+    Found,
+    TemporaryRedirect,
+    PermanentRedirect,
+}
+
+impl RedirectStatus {
+    pub const ALL: [RedirectStatus; 4] = [
+        RedirectStatus::MovedPermanently,
+        RedirectStatus::Found,
+        RedirectStatus::TemporaryRedirect,
+        RedirectStatus::PermanentRedirect,
+    ];
+
+    pub fn code(self) -> u16 {
+        match self {
+            RedirectStatus::MovedPermanently => 301,
+            RedirectStatus::Found => 302,
+            RedirectStatus::TemporaryRedirect => 307,
+            RedirectStatus::PermanentRedirect => 308,
+        }
+    }
+}
+
+impl From<RedirectStatus> for u16 {
+    fn from(val: RedirectStatus) -> Self {
+        val.code()
+    }
+}
+
+impl TryFrom<u16> for RedirectStatus {
+    type Error = String;
+
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        RedirectStatus::ALL
+            .into_iter()
+            .find(|status| status.code() == code)
+            .ok_or_else(|| format!("unsupported redirect status {code}, expected one of 301, 302, 307 or 308"))
+    }
+}
+
+// the status is written as its code, like 302
+impl utoipa::PartialSchema for RedirectStatus {
+    fn schema() -> utoipa::openapi::RefOr<utoipa::openapi::schema::Schema> {
+        utoipa::openapi::ObjectBuilder::new()
+            .schema_type(utoipa::openapi::Type::Integer)
+            .enum_values(Some(RedirectStatus::ALL.map(RedirectStatus::code)))
+            .default(Some(RedirectStatus::default().code().into()))
+            .into()
+    }
+}
+
+impl ToSchema for RedirectStatus {}
+
+impl std::str::FromStr for RedirectStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .parse::<u16>()
+            .map_err(|_| format!("invalid redirect status {s:?}"))?
+            .try_into()
+    }
+}
+
+/// Passthrough controls what a link does with the path after its short name
+/// and with the query parameters of a click.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize, ToSchema)]
+#[serde(default)]
+pub struct Passthrough {
+    pub path_mode: PathMode,
+    pub query_mode: QueryMode,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub query_allow: Vec<String>, // the query parameters passed on when query_mode is "allow"
+}
+
+impl Passthrough {
+    /// Returns the path to expand the link with, or `None` if the link does not accept one.
+    pub fn path<'a>(&self, path: &'a str) -> Option<&'a str> {
+        match self.path_mode {
+            PathMode::Append => Some(path),
+            PathMode::Ignore => Some(""),
+            PathMode::Reject if path.trim_matches('/').is_empty() => Some(""),
+            PathMode::Reject => None,
+        }
+    }
+
+    /// Returns true if the query parameter is passed on to the destination.
+    pub fn passes(&self, key: &str) -> bool {
+        match self.query_mode {
+            QueryMode::Pass => true,
+            QueryMode::Drop => false,
+            QueryMode::Allow => self.query_allow.iter().any(|allowed| allowed == key),
+        }
+    }
+}
+
+/// PathMode is what a link does with any path after its short name.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PathMode {
+    #[default]
+    Append, // add the path to the end of a plain destination link, or give it to a template
+    Ignore, // expand the link as if there was no path
+    Reject, // answer with 404 Not Found when there is a path
+}
+
+/// QueryMode is what a link does with the query parameters of a click that its
+/// destination link template does not read itself.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryMode {
+    #[default]
+    Pass, // add all of them to the destination URL
+    Drop,  // add none of them
+    Allow, // add only those named in query_allow
+}
+
+impl PathMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PathMode::Append => "append",
+            PathMode::Ignore => "ignore",
+            PathMode::Reject => "reject",
+        }
+    }
+}
+
+impl std::str::FromStr for PathMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "append" => Ok(PathMode::Append),
+            "ignore" => Ok(PathMode::Ignore),
+            "reject" => Ok(PathMode::Reject),
+            _ => Err(format!("invalid path mode {s:?}, expected append, ignore or reject")),
+        }
+    }
+}
+
+impl QueryMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QueryMode::Pass => "pass",
+            QueryMode::Drop => "drop",
+            QueryMode::Allow => "allow",
+        }
+    }
+}
+
+impl std::str::FromStr for QueryMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "pass" => Ok(QueryMode::Pass),
+            "drop" => Ok(QueryMode::Drop),
+            "allow" => Ok(QueryMode::Allow),
+            _ => Err(format!("invalid query mode {s:?}, expected pass, drop or allow")),
+        }
+    }
+}
+
+/// Splits a list of query parameter names separated by commas or whitespace.
+pub fn parse_query_allow(names: &str) -> Vec<String> {
+    names
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ClickStats {
     pub created: chrono::DateTime<Utc>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub clicks: Option<i32>, // number of times link has been clicked
+    #[serde(default)]
+    pub skipped: i32, // number of prefetches, bot visits and HEAD requests, which are not clicks
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -32,14 +223,413 @@ pub struct PopularLink {
     pub clicks: Option<i32>, // number of times link has been clicked
 }
 
+/// AgentFamily is the coarse kind of client that followed a link.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AgentFamily {
+    Browser,
+    Cli, // command line tools and HTTP libraries
+    #[default]
+    Other,
+}
+
+impl AgentFamily {
+    // substrings of lowercase User-Agents of command line tools and HTTP libraries
+    const CLI_AGENTS: [&str; 12] = [
+        "curl/",
+        "wget/",
+        "httpie/",
+        "python-requests/",
+        "python-urllib/",
+        "aiohttp/",
+        "go-http-client/",
+        "powershell/",
+        "libwww-perl/",
+        "okhttp/",
+        "node-fetch",
+        "reqwest/",
+    ];
+
+    /// Classifies a User-Agent header.
+    pub fn from_user_agent(user_agent: &str) -> Self {
+        let user_agent = user_agent.to_lowercase();
+        if Self::CLI_AGENTS.iter().any(|agent| user_agent.contains(agent)) {
+            AgentFamily::Cli
+        } else if user_agent.starts_with("mozilla/") {
+            AgentFamily::Browser
+        } else {
+            AgentFamily::Other
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AgentFamily::Browser => "browser",
+            AgentFamily::Cli => "cli",
+            AgentFamily::Other => "other",
+        }
+    }
+}
+
+impl std::str::FromStr for AgentFamily {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "browser" => Ok(AgentFamily::Browser),
+            "cli" => Ok(AgentFamily::Cli),
+            "other" => Ok(AgentFamily::Other),
+            _ => Err(format!("unknown user agent family {s:?}")),
+        }
+    }
+}
+
+/// ClickSource describes where a single click came from.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ClickSource {
+    pub subnet: Option<String>,   // the client's /24 or /48 subnet, or its keyed hash
+    pub referrer: Option<String>, // the host of the Referer header
+    pub agent: AgentFamily,
+    pub clicked: chrono::DateTime<Utc>,
+}
+
+/// Tally is a number of clicks sharing one value, such as a referrer.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct Tally {
+    pub name: String,
+    pub clicks: u64,
+}
+
+/// ClickBreakdown summarizes where the clicks on a link came from.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct ClickBreakdown {
+    pub days: Option<u32>,     // the window the breakdown covers, or none for all time
+    pub referrers: Vec<Tally>, // the most common Referer hosts
+    pub subnets: Vec<Tally>,   // the most common client subnets
+    pub agents: Vec<Tally>,    // clicks per user agent family
+}
+
+/// DailyClicks is the number of clicks on a link in one UTC day.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct DailyClicks {
+    pub day: chrono::NaiveDate,
+    pub clicks: u64,
+}
+
+/// Rollups is the click trend of a link: its clicks per day over a window, oldest first.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct Rollups {
+    pub short: String,
+    pub days: u32,   // the number of days in daily, ending today
+    pub clicks: u64, // all time clicks
+    pub last_clicked: Option<chrono::DateTime<Utc>>,
+    pub daily: Vec<DailyClicks>, // one entry per day, including days without clicks
+}
+
+/// LinkUsage is how much a link has been clicked.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct LinkUsage {
+    pub clicks: i32,                             // all time clicks
+    pub last_clicked: Option<chrono::NaiveDate>, // the last UTC day with a recorded click
+}
+
+/// HygieneLink is a link listed by the hygiene report.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct HygieneLink {
+    pub short: String,
+    pub long: String,
+    pub created: chrono::DateTime<Utc>,
+    #[serde(flatten)]
+    pub usage: LinkUsage,
+}
+
+/// LinkGroup is a set of links that are likely the same link twice.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct LinkGroup {
+    pub key: String, // what the links have in common, such as their normalized destination
+    pub links: Vec<HygieneLink>,
+}
+
+/// MalformedLink is a link whose destination does not look like a working URL.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct MalformedLink {
+    pub short: String,
+    pub long: String,
+    pub problems: Vec<String>,
+}
+
+/// HygieneReport lists links that are likely unused, duplicated or broken.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct HygieneReport {
+    pub days: u32,                       // links not clicked in this many days are stale
+    pub never_clicked: Vec<HygieneLink>, // empty when clicks are not recorded
+    pub stale: Vec<HygieneLink>,         // empty when clicks are not recorded
+    pub duplicates: Vec<LinkGroup>,      // links with the same or nearly the same destination
+    pub collisions: Vec<LinkGroup>,      // links with nearly the same short name
+    pub malformed: Vec<MalformedLink>,
+}
+
+/// LinkCheck is the result of the last request made to a link's destination
+/// by the broken link checker.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct LinkCheck {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>, // the HTTP status, or none if the request failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>, // where a redirect pointed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // why the request failed
+    pub checked: chrono::DateTime<Utc>,
+    pub broken: bool, // the request failed or was answered with an error status
+}
+
+impl LinkCheck {
+    pub fn new(
+        status: Option<u16>,
+        location: Option<String>,
+        error: Option<String>,
+        checked: chrono::DateTime<Utc>,
+    ) -> Self {
+        Self {
+            broken: status.is_none_or(|status| status >= 400),
+            status,
+            location,
+            error,
+            checked,
+        }
+    }
+}
+
+/// WantedLink is a short name that was looked up but has no link.
 #[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WantedLink {
+    pub short: String, // the short name as it was last requested
+    pub misses: u64,   // the number of times it was looked up
+    pub last_seen: chrono::DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
 pub struct LinkDetails {
     pub short: String, // the user-provided "foo" part of "http://go/foo"
     pub long: String,  // the target URL or text/template pattern to run
     pub created: chrono::DateTime<Utc>,
     pub updated: chrono::DateTime<Utc>,
+    pub status: RedirectStatus, // the HTTP status clicks are redirected with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u32>, // seconds a browser may cache the redirect
+    #[serde(flatten)]
+    pub passthrough: Passthrough,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dont_track: bool, // clicks on the link are not recorded
     #[serde(skip_serializing_if = "Option::is_none")]
     pub clicks: Option<i32>, // number of times link has been clicked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<i32>, // number of prefetches, bot visits and HEAD requests, which are not clicks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub breakdown: Option<ClickBreakdown>, // where recent clicks came from
+}
+
+/// Preview is the result of resolving a link without following it.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct Preview {
+    pub short: String, // the short name the path resolved to
+    pub path: String,  // the remaining path given to the destination link template
+    pub long: String,  // the destination link template that was expanded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rendered: Option<String>, // the template output, before query parameters are appended
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>, // the URL a click would be redirected to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // why the link could not be expanded
+}
+
+/// ErrorResponse is returned by API endpoints when a request cannot be fulfilled.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<String>, // a description of each problem found
+}
+
+/// ImportResult summarizes the links read by an import.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct ImportResult {
+    pub imported: usize, // the number of links created or replaced
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>, // a description of each line that could not be imported
+}
+
+/// CacheStats describes the use of an in-memory cache.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct CacheStats {
+    pub capacity: usize, // the most entries the cache holds
+    pub entries: usize,  // the entries currently held
+    pub hits: u64,       // lookups answered by the cache
+    pub misses: u64,     // lookups that had to go to the database
+}
+
+/// Diagnostics reports the internal state of the server.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct Diagnostics {
+    pub link_cache: CacheStats,
+    pub templates: TemplateStats,
+}
+
+/// PurgeResult summarizes the usage data removed by a purge.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PurgeResult {
+    pub links: usize,  // the links whose click stats were reset
+    pub clicks: usize, // the individual click records forgotten
+    pub misses: usize, // the missed lookups forgotten
+}
+
+/// TemplateStats describes the compiled destination link templates.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct TemplateStats {
+    pub cached: usize,   // the compiled links currently held
+    pub compiles: usize, // the number of templates compiled since startup
+}
+
+/// LinkPage is one page of the links listed by the API.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct LinkPage {
+    pub links: Vec<Link>,
+    pub total: usize,  // the number of links on all pages
+    pub offset: usize, // the number of links on the pages before this one
+    pub limit: usize,  // the most links on a page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<usize>, // the offset of the next page, if there is one
+}
+
+/// LinkInput is the body of an API request creating or replacing a link. It
+/// has the same fields as a [`Link`], so a link read from the API can be
+/// changed and written back; fields that are left out take their defaults.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct LinkInput {
+    #[serde(default)]
+    pub short: String, // required to create a link, and may be left out when replacing one
+    pub long: String,
+    #[serde(default)]
+    pub status: RedirectStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u32>,
+    #[serde(flatten)]
+    pub passthrough: Passthrough,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dont_track: bool,
+}
+
+/// LinkPatch is the body of an API request changing some of the fields of a
+/// link; fields that are left out keep their current value.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkPatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub long: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<RedirectStatus>,
+    #[serde(deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<Option<u32>>, // null stops browsers caching the redirect
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_mode: Option<PathMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_mode: Option<QueryMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_allow: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dont_track: Option<bool>,
+}
+
+// reads a field that is present, even if it is null, so that null can be told apart from a missing field
+pub(crate) fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// LinkStats is the clicks on a link returned by the API.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct LinkStats {
+    #[serde(flatten)]
+    pub rollups: Rollups,
+    pub skipped: i32, // number of prefetches, bot visits and HEAD requests, which are not clicks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breakdown: Option<ClickBreakdown>, // where recent clicks came from
+}
+
+/// TokenScope is what an API token may do. Each scope allows everything the
+/// scopes before it allow.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    #[default]
+    Read, // list and read links and their clicks
+    Write, // create, change and delete links
+    Admin, // create and revoke API tokens
+}
+
+impl TokenScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TokenScope::Read => "read",
+            TokenScope::Write => "write",
+            TokenScope::Admin => "admin",
+        }
+    }
+}
+
+impl std::str::FromStr for TokenScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "read" => Ok(TokenScope::Read),
+            "write" => Ok(TokenScope::Write),
+            "admin" => Ok(TokenScope::Admin),
+            _ => Err(format!("invalid scope {s:?}, expected read, write or admin")),
+        }
+    }
+}
+
+/// ApiToken describes a token that scripts send to the API. The token itself
+/// is only shown when it is created; just its hash is kept.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct ApiToken {
+    pub id: String,   // identifies the token, which starts with it
+    pub name: String, // what the token is for
+    pub scope: TokenScope,
+    pub created: chrono::DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<chrono::DateTime<Utc>>, // when the token stops working, if ever
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<chrono::DateTime<Utc>>,
+}
+
+impl ApiToken {
+    pub fn is_expired(&self, now: chrono::DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// TokenInput is the body of an API request creating an API token.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct TokenInput {
+    pub name: String,
+    #[serde(default)]
+    pub scope: TokenScope,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_in_days: Option<u32>, // the token never expires when left out
+}
+
+/// NewToken is an API token that was just created, along with the token
+/// itself, which cannot be read again.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct NewToken {
+    #[serde(flatten)]
+    pub api_token: ApiToken,
+    pub token: String, // sent as "Authorization: Bearer <token>"
 }
 
 /// returns the normalized Id for a link short name.
@@ -102,6 +692,64 @@ mod tests {
         assert_eq!(normalized_id(input), expected);
     }
 
+    #[test]
+    fn test_redirect_status() {
+        assert_eq!(RedirectStatus::default().code(), 302);
+        assert_eq!("307".parse::<RedirectStatus>(), Ok(RedirectStatus::TemporaryRedirect));
+        assert!("200".parse::<RedirectStatus>().is_err());
+        assert!("moved".parse::<RedirectStatus>().is_err());
+
+        let link: Link = serde_json::from_str(
+            r#"{"short":"nyt","long":"http://www.nytimes.com","created":"2022-05-31T13:04:44Z","updated":"2022-05-31T13:04:44Z"}"#,
+        )
+        .unwrap();
+        assert_eq!(link.status, RedirectStatus::Found);
+        assert_eq!(link.max_age, None);
+
+        let link = Link {
+            status: RedirectStatus::MovedPermanently,
+            max_age: Some(3600),
+            ..link
+        };
+        let json = serde_json::to_value(&link).unwrap();
+        assert_eq!(json["status"], 301);
+        assert_eq!(json["max_age"], 3600);
+        assert!(serde_json::from_str::<Link>(&json.to_string().replace("301", "200")).is_err());
+    }
+
+    #[test]
+    fn test_passthrough() {
+        let link: Link = serde_json::from_str(
+            r#"{"short":"vpn","long":"http://vpn","created":"2022-05-31T13:04:44Z","updated":"2022-05-31T13:04:44Z"}"#,
+        )
+        .unwrap();
+        assert_eq!(link.passthrough, Passthrough::default());
+        assert_eq!(link.passthrough.path("foo"), Some("foo"));
+        assert!(link.passthrough.passes("utm_source"));
+
+        let reject: Passthrough = serde_json::from_str(r#"{"path_mode":"reject","query_mode":"drop"}"#).unwrap();
+        assert_eq!(reject.path("/"), Some(""));
+        assert_eq!(reject.path("foo"), None);
+        assert!(!reject.passes("q"));
+
+        let allow = Passthrough {
+            path_mode: PathMode::Ignore,
+            query_mode: QueryMode::Allow,
+            query_allow: parse_query_allow("q, lang"),
+        };
+        assert_eq!(allow.path("foo"), Some(""));
+        assert!(allow.passes("lang"));
+        assert!(!allow.passes("utm_source"));
+        let json = serde_json::to_value(Link {
+            passthrough: allow,
+            ..link
+        })
+        .unwrap();
+        assert_eq!(json["path_mode"], "ignore");
+        assert_eq!(json["query_allow"], serde_json::json!(["q", "lang"]));
+        assert!("bogus".parse::<QueryMode>().is_err());
+    }
+
     // Test case 7: A string with multiple hyphens.
     #[test]
     fn test_multiple_hyphens() {
@@ -109,4 +757,54 @@ mod tests {
         let expected = "abc".to_string();
         assert_eq!(normalized_id(input), expected);
     }
+
+    #[test]
+    fn test_agent_family() {
+        for (user_agent, family) in [
+            (
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 Safari/605.1.15",
+                AgentFamily::Browser,
+            ),
+            ("curl/8.5.0", AgentFamily::Cli),
+            ("Wget/1.21.4", AgentFamily::Cli),
+            (
+                "Mozilla/5.0 (Windows NT; Windows NT 10.0) WindowsPowerShell/5.1",
+                AgentFamily::Cli,
+            ),
+            ("python-requests/2.32.3", AgentFamily::Cli),
+            ("", AgentFamily::Other),
+            ("Java/17", AgentFamily::Other),
+        ] {
+            assert_eq!(AgentFamily::from_user_agent(user_agent), family, "{user_agent}");
+            assert_eq!(family.as_str().parse::<AgentFamily>(), Ok(family));
+        }
+    }
+
+    #[test]
+    fn test_token_scope() {
+        assert!(TokenScope::Admin > TokenScope::Write && TokenScope::Write > TokenScope::Read);
+        for scope in [TokenScope::Read, TokenScope::Write, TokenScope::Admin] {
+            assert_eq!(scope.as_str().parse::<TokenScope>(), Ok(scope));
+        }
+        assert!("root".parse::<TokenScope>().is_err());
+
+        let input: TokenInput = serde_json::from_str(r#"{"name":"deploy"}"#).unwrap();
+        assert_eq!((input.scope, input.expires_in_days), (TokenScope::Read, None));
+
+        let now = chrono::Utc::now();
+        let token = ApiToken {
+            id: "abc".to_string(),
+            name: "deploy".to_string(),
+            scope: TokenScope::Write,
+            created: now,
+            expires: None,
+            last_used: None,
+        };
+        assert!(!token.is_expired(now));
+        let token = ApiToken {
+            expires: Some(now - chrono::Duration::seconds(1)),
+            ..token
+        };
+        assert!(token.is_expired(now));
+    }
 }
@@ -0,0 +1,692 @@
+use std::sync::LazyLock;
+
+use utoipa::{
+    Modify, OpenApi, ToSchema,
+    openapi::{
+        ContentBuilder, Ref, ResponseBuilder,
+        header::Header,
+        schema::{ObjectBuilder, Type},
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    },
+};
+
+use crate::model;
+
+/// The path the OpenAPI document is served at.
+pub const PATH: &str = "/.well-known/openapi.json";
+
+/// ApiDoc is the OpenAPI document describing the HTTP endpoints meant for
+/// scripts and other programs, rather than the pages of the web interface.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "gohome",
+        license(name = "BSD-3-Clause"),
+        description = "Short links: go/name redirects to a destination link, which may be a template."
+    ),
+    paths(
+        paths::create_link,
+        paths::create_link_form,
+        paths::update_link_form,
+        paths::delete_link_form,
+        paths::follow_link,
+        paths::link_details,
+        paths::preview_link,
+        paths::link_rollups,
+        paths::export_links,
+        paths::import_links,
+        paths::hygiene_report,
+        paths::diagnostics,
+        paths::metrics,
+        paths::openapi_document,
+        paths::api_list_links,
+        paths::api_create_link,
+        paths::api_get_link,
+        paths::api_replace_link,
+        paths::api_patch_link,
+        paths::api_delete_link,
+        paths::api_link_stats,
+        paths::api_list_tokens,
+        paths::api_create_token,
+        paths::api_revoke_token,
+    ),
+    modifiers(&ApiTokenScheme, &RateLimits),
+    tags(
+        (name = "links", description = "Creating, following and describing links"),
+        (name = "forms", description = "The forms of the web interface, which need an XSRF token from its pages"),
+        (name = "api", description = "The versioned JSON API"),
+        (name = "server", description = "The state of the server"),
+    )
+)]
+pub struct ApiDoc;
+
+// adds the API tokens sent as "Authorization: Bearer", which operations list with the scope they need
+struct ApiTokenScheme;
+
+impl Modify for ApiTokenScheme {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_token",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some(
+                        "An API token created on the admin page, with the read, write or admin scope",
+                    ))
+                    .build(),
+            ),
+        );
+    }
+}
+
+static DOCUMENT: LazyLock<utoipa::openapi::OpenApi> = LazyLock::new(ApiDoc::openapi);
+
+/// Returns the OpenAPI document, which is only generated once.
+pub fn document() -> &'static utoipa::openapi::OpenApi {
+    &DOCUMENT
+}
+
+// adds the response to requests from a client that has spent its budget to the operations counted
+// against one: those that change something, and link lookups
+struct RateLimits;
+
+impl Modify for RateLimits {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let response = ResponseBuilder::new()
+            .description("The client has made too many requests, and may retry after the Retry-After seconds")
+            .header(
+                "Retry-After",
+                Header::new(ObjectBuilder::new().schema_type(Type::Integer)),
+            )
+            .content(
+                "application/json",
+                ContentBuilder::new()
+                    .schema(Some(Ref::from_schema_name("ErrorResponse")))
+                    .build(),
+            )
+            .build();
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components
+            .responses
+            .insert("TooManyRequests".to_string(), response.into());
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            let lookup = path.starts_with("/{short}");
+            let operations = [
+                (&mut item.get, lookup),
+                (&mut item.post, true),
+                (&mut item.put, true),
+                (&mut item.patch, true),
+                (&mut item.delete, true),
+            ];
+            for (operation, limited) in operations {
+                if let Some(operation) = operation.as_mut().filter(|_| limited) {
+                    operation
+                        .responses
+                        .responses
+                        .insert("429".to_string(), Ref::from_response_name("TooManyRequests").into());
+                }
+            }
+        }
+    }
+}
+
+/// LinkForm is the form posted to create or edit a link, where an empty field
+/// is the same as one that is left out.
+#[derive(ToSchema)]
+#[allow(dead_code)] // only describes the fields read from the form
+struct LinkForm {
+    short: String,
+    long: String,
+    status: Option<model::RedirectStatus>,
+    max_age: Option<u32>,
+    path_mode: Option<model::PathMode>,
+    query_mode: Option<model::QueryMode>,
+    /// the comma-separated query parameters passed on when query_mode is "allow"
+    query_allow: Option<String>,
+    /// "on", "true" or "1" to stop recording clicks on the link
+    dont_track: Option<String>,
+    /// the XSRF token of the page the form is on, needed by /.create and /.update
+    xsrf: Option<String>,
+}
+
+/// XsrfForm is a form that only holds the XSRF token of the page it is on.
+#[derive(ToSchema)]
+#[allow(dead_code)] // only describes the fields read from the form
+struct XsrfForm {
+    xsrf: String,
+}
+
+// each endpoint is described by a function of its own, since a warp filter can serve several
+#[allow(dead_code)]
+mod paths {
+    use super::*;
+
+    #[utoipa::path(
+        post,
+        path = "/",
+        tag = "links",
+        params(("Sec-Golink" = Option<String>, Header, description = "Any non-empty value, which JavaScript in a browser cannot send, allows the change without an API token unless API tokens are required")),
+        request_body(content = LinkForm, content_type = "application/x-www-form-urlencoded"),
+        responses(
+            (status = 201, description = "The link was created", body = model::Link),
+            (status = 400, description = "The form is invalid, the destination link does not work or the link already exists", body = model::ErrorResponse),
+            (status = 401, description = "The API token is invalid or has expired, or API tokens are required", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the write scope, or there is no API token or Sec-Golink header", body = model::ErrorResponse),
+        ),
+        security((), ("api_token" = ["write"]))
+    )]
+    pub(super) fn create_link() {}
+
+    #[utoipa::path(
+        post,
+        path = "/.create",
+        tag = "forms",
+        request_body(content = LinkForm, content_type = "application/x-www-form-urlencoded"),
+        responses(
+            (status = 200, description = "The link was created", content_type = "text/html", body = String),
+            (status = 301, description = "The XSRF token is invalid"),
+            (status = 400, description = "The form is invalid, the destination link does not work or the link already exists", content_type = "text/html", body = String),
+            (status = 401, description = "The API token is invalid or has expired, or API tokens are required and neither one nor a user signed in by a trusted proxy was sent", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the write scope", body = model::ErrorResponse),
+        ),
+        security((), ("api_token" = ["write"]))
+    )]
+    pub(super) fn create_link_form() {}
+
+    #[utoipa::path(
+        post,
+        path = "/.update",
+        tag = "forms",
+        request_body(content = LinkForm, content_type = "application/x-www-form-urlencoded"),
+        responses(
+            (status = 200, description = "The link was saved", content_type = "text/html", body = String),
+            (status = 301, description = "The XSRF token is invalid"),
+            (status = 400, description = "The form is invalid or the destination link does not work", content_type = "text/html", body = String),
+            (status = 401, description = "The API token is invalid or has expired, or API tokens are required and neither one nor a user signed in by a trusted proxy was sent", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the write scope", body = model::ErrorResponse),
+            (status = 404, description = "The link does not exist"),
+        ),
+        security((), ("api_token" = ["write"]))
+    )]
+    pub(super) fn update_link_form() {}
+
+    #[utoipa::path(
+        post,
+        path = "/.delete/{short}",
+        tag = "forms",
+        params(("short" = String, Path, description = "The short name of the link")),
+        request_body(content = XsrfForm, content_type = "application/x-www-form-urlencoded"),
+        responses(
+            (status = 200, description = "The link was deleted", content_type = "text/html", body = String),
+            (status = 301, description = "The XSRF token is invalid"),
+            (status = 401, description = "The API token is invalid or has expired, or API tokens are required and neither one nor a user signed in by a trusted proxy was sent", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the write scope", body = model::ErrorResponse),
+            (status = 404, description = "The link does not exist"),
+        ),
+        security((), ("api_token" = ["write"]))
+    )]
+    pub(super) fn delete_link_form() {}
+
+    #[utoipa::path(
+        get,
+        path = "/{short}",
+        tag = "links",
+        params(("short" = String, Path, description = "The short name of the link, which may be followed by a path")),
+        responses(
+            (status = 301, description = "Redirects to the destination link, if the link redirects with 301"),
+            (status = 302, description = "Redirects to the destination link"),
+            (status = 307, description = "Redirects to the destination link, if the link redirects with 307"),
+            (status = 308, description = "Redirects to the destination link, if the link redirects with 308"),
+            (status = 404, description = "The link does not exist, or does not accept a path, and redirects home"),
+        )
+    )]
+    pub(super) fn follow_link() {}
+
+    #[utoipa::path(
+        get,
+        path = "/{short}+",
+        tag = "links",
+        params(
+            ("short" = String, Path, description = "The short name of the link"),
+            ("days" = Option<String>, Query, description = "How many days of clicks to break down, or \"all\""),
+        ),
+        responses(
+            (status = 200, description = "The link and its clicks", body = model::LinkDetails),
+            (status = 404, description = "The link does not exist"),
+        )
+    )]
+    pub(super) fn link_details() {}
+
+    #[utoipa::path(
+        get,
+        path = "/.preview",
+        tag = "links",
+        params(
+            ("path" = String, Query, description = "The link path to resolve, like \"search/foo?hl=en\""),
+            ("long" = Option<String>, Query, description = "An unsaved destination link to resolve the path with"),
+        ),
+        responses(
+            (status = 200, description = "Where the path resolves to", body = model::Preview),
+            (status = 404, description = "The link does not exist", body = model::ErrorResponse),
+        )
+    )]
+    pub(super) fn preview_link() {}
+
+    #[utoipa::path(
+        get,
+        path = "/.rollups/{short}",
+        tag = "links",
+        params(
+            ("short" = String, Path, description = "The short name of the link"),
+            ("days" = Option<u32>, Query, description = "How many days of clicks to return"),
+        ),
+        responses(
+            (status = 200, description = "The clicks on the link per day", body = model::Rollups),
+            (status = 404, description = "The link does not exist or clicks are not recorded", content_type = "text/plain", body = String),
+        )
+    )]
+    pub(super) fn link_rollups() {}
+
+    #[utoipa::path(
+        get,
+        path = "/.export",
+        tag = "links",
+        responses(
+            (status = 200, description = "Every link, one JSON object per line", content_type = "application/x-ndjson", body = model::Link),
+        )
+    )]
+    pub(super) fn export_links() {}
+
+    #[utoipa::path(
+        post,
+        path = "/.import",
+        tag = "links",
+        params(("Sec-Golink" = Option<String>, Header, description = "Any non-empty value, which JavaScript in a browser cannot send, allows the change without an API token unless API tokens are required")),
+        request_body(content = model::Link, description = "Links exported by gohome or golink, one JSON object per line", content_type = "application/x-ndjson"),
+        responses(
+            (status = 200, description = "How many links were imported, and why the others were not", body = model::ImportResult),
+            (status = 400, description = "The links are not UTF-8 encoded", body = model::ErrorResponse),
+            (status = 401, description = "The API token is invalid or has expired, or API tokens are required", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the write scope, or there is no API token or Sec-Golink header", body = model::ErrorResponse),
+        ),
+        security((), ("api_token" = ["write"]))
+    )]
+    pub(super) fn import_links() {}
+
+    #[utoipa::path(
+        get,
+        path = "/.hygiene.json",
+        tag = "links",
+        params(("days" = Option<u32>, Query, description = "How many days without clicks make a link stale")),
+        responses(
+            (status = 200, description = "Links that may be unused, duplicated or broken", body = model::HygieneReport),
+        )
+    )]
+    pub(super) fn hygiene_report() {}
+
+    #[utoipa::path(
+        get,
+        path = "/.diagnostics",
+        tag = "server",
+        responses(
+            (status = 200, description = "The state of the link and template caches", body = model::Diagnostics),
+        )
+    )]
+    pub(super) fn diagnostics() {}
+
+    #[utoipa::path(
+        get,
+        path = "/.metrics",
+        tag = "server",
+        responses(
+            (status = 200, description = "Request, redirect and database metrics for Prometheus to scrape", content_type = "application/openmetrics-text", body = String),
+        )
+    )]
+    pub(super) fn metrics() {}
+
+    #[utoipa::path(
+        get,
+        path = "/.well-known/openapi.json",
+        tag = "server",
+        responses(
+            (status = 200, description = "This document", content_type = "application/json", body = Object),
+        )
+    )]
+    pub(super) fn openapi_document() {}
+
+    #[utoipa::path(
+        get,
+        path = "/api/v1/links",
+        tag = "api",
+        params(
+            ("offset" = Option<usize>, Query, description = "The number of links to skip"),
+            ("limit" = Option<usize>, Query, description = "The most links to return, from 1 to 1000"),
+            ("q" = Option<String>, Query, description = "Only links whose short name or destination link contains this"),
+            ("template" = Option<bool>, Query, description = "Only templates, or only links that are not templates"),
+        ),
+        responses(
+            (status = 200, description = "A page of links in short name order", body = model::LinkPage),
+            (status = 400, description = "The query is invalid", body = model::ErrorResponse),
+            (status = 401, description = "The API token is invalid or has expired, or API tokens are required", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the read scope", body = model::ErrorResponse),
+        ),
+        security((), ("api_token" = ["read"]))
+    )]
+    pub(super) fn api_list_links() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/v1/links",
+        tag = "api",
+        params(("Sec-Golink" = Option<String>, Header, description = "Any non-empty value, which JavaScript in a browser cannot send, allows the change without an API token unless API tokens are required")),
+        request_body = model::LinkInput,
+        responses(
+            (status = 201, description = "The link was created", body = model::Link),
+            (status = 400, description = "The link is invalid", body = model::ErrorResponse),
+            (status = 401, description = "The API token is invalid or has expired, or API tokens are required", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the write scope, or there is no API token or Sec-Golink header", body = model::ErrorResponse),
+            (status = 409, description = "The link already exists", body = model::ErrorResponse),
+        ),
+        security((), ("api_token" = ["write"]))
+    )]
+    pub(super) fn api_create_link() {}
+
+    #[utoipa::path(
+        get,
+        path = "/api/v1/links/{short}",
+        tag = "api",
+        params(("short" = String, Path, description = "The short name of the link")),
+        responses(
+            (status = 200, description = "The link", body = model::Link),
+            (status = 401, description = "The API token is invalid or has expired, or API tokens are required", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the read scope", body = model::ErrorResponse),
+            (status = 404, description = "The link does not exist", body = model::ErrorResponse),
+        ),
+        security((), ("api_token" = ["read"]))
+    )]
+    pub(super) fn api_get_link() {}
+
+    #[utoipa::path(
+        put,
+        path = "/api/v1/links/{short}",
+        tag = "api",
+        params(
+            ("short" = String, Path, description = "The short name of the link"),
+            ("Sec-Golink" = Option<String>, Header, description = "Any non-empty value, which JavaScript in a browser cannot send, allows the change without an API token unless API tokens are required"),
+        ),
+        request_body = model::LinkInput,
+        responses(
+            (status = 200, description = "The replaced link", body = model::Link),
+            (status = 400, description = "The link is invalid", body = model::ErrorResponse),
+            (status = 401, description = "The API token is invalid or has expired, or API tokens are required", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the write scope, or there is no API token or Sec-Golink header", body = model::ErrorResponse),
+            (status = 404, description = "The link does not exist", body = model::ErrorResponse),
+        ),
+        security((), ("api_token" = ["write"]))
+    )]
+    pub(super) fn api_replace_link() {}
+
+    #[utoipa::path(
+        patch,
+        path = "/api/v1/links/{short}",
+        tag = "api",
+        params(
+            ("short" = String, Path, description = "The short name of the link"),
+            ("Sec-Golink" = Option<String>, Header, description = "Any non-empty value, which JavaScript in a browser cannot send, allows the change without an API token unless API tokens are required"),
+        ),
+        request_body = model::LinkPatch,
+        responses(
+            (status = 200, description = "The changed link", body = model::Link),
+            (status = 400, description = "The patch is invalid", body = model::ErrorResponse),
+            (status = 401, description = "The API token is invalid or has expired, or API tokens are required", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the write scope, or there is no API token or Sec-Golink header", body = model::ErrorResponse),
+            (status = 404, description = "The link does not exist", body = model::ErrorResponse),
+        ),
+        security((), ("api_token" = ["write"]))
+    )]
+    pub(super) fn api_patch_link() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/api/v1/links/{short}",
+        tag = "api",
+        params(
+            ("short" = String, Path, description = "The short name of the link"),
+            ("Sec-Golink" = Option<String>, Header, description = "Any non-empty value, which JavaScript in a browser cannot send, allows the change without an API token unless API tokens are required"),
+        ),
+        responses(
+            (status = 204, description = "The link and its clicks were deleted"),
+            (status = 401, description = "The API token is invalid or has expired, or API tokens are required", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the write scope, or there is no API token or Sec-Golink header", body = model::ErrorResponse),
+            (status = 404, description = "The link does not exist", body = model::ErrorResponse),
+        ),
+        security((), ("api_token" = ["write"]))
+    )]
+    pub(super) fn api_delete_link() {}
+
+    #[utoipa::path(
+        get,
+        path = "/api/v1/links/{short}/stats",
+        tag = "api",
+        params(
+            ("short" = String, Path, description = "The short name of the link"),
+            ("days" = Option<u32>, Query, description = "How many days of clicks to return and break down"),
+        ),
+        responses(
+            (status = 200, description = "The clicks on the link", body = model::LinkStats),
+            (status = 400, description = "The query is invalid", body = model::ErrorResponse),
+            (status = 401, description = "The API token is invalid or has expired, or API tokens are required", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the read scope", body = model::ErrorResponse),
+            (status = 404, description = "The link does not exist or clicks are not recorded", body = model::ErrorResponse),
+        ),
+        security((), ("api_token" = ["read"]))
+    )]
+    pub(super) fn api_link_stats() {}
+
+    #[utoipa::path(
+        get,
+        path = "/api/v1/tokens",
+        tag = "api",
+        responses(
+            (status = 200, description = "The API tokens, oldest first, without the tokens themselves", body = Vec<model::ApiToken>),
+            (status = 401, description = "There is no API token, or it is invalid or has expired", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the admin scope", body = model::ErrorResponse),
+        ),
+        security(("api_token" = ["admin"]))
+    )]
+    pub(super) fn api_list_tokens() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/v1/tokens",
+        tag = "api",
+        request_body = model::TokenInput,
+        responses(
+            (status = 201, description = "The API token was created; the token itself cannot be read again", body = model::NewToken),
+            (status = 400, description = "The API token is invalid", body = model::ErrorResponse),
+            (status = 401, description = "There is no API token, or it is invalid or has expired", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the admin scope", body = model::ErrorResponse),
+        ),
+        security(("api_token" = ["admin"]))
+    )]
+    pub(super) fn api_create_token() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/api/v1/tokens/{id}",
+        tag = "api",
+        params(("id" = String, Path, description = "The id of the API token")),
+        responses(
+            (status = 204, description = "The API token was revoked"),
+            (status = 401, description = "There is no API token, or it is invalid or has expired", body = model::ErrorResponse),
+            (status = 403, description = "The API token does not have the admin scope", body = model::ErrorResponse),
+            (status = 404, description = "The API token does not exist", body = model::ErrorResponse),
+        ),
+        security(("api_token" = ["admin"]))
+    )]
+    pub(super) fn api_revoke_token() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use handlebars::{DirectorySourceOptions, Handlebars};
+
+    use super::*;
+    use crate::{db, render::Renderer, routes};
+
+    // returns the fields an object schema requires, including those of the schemas it refers
+    // to and of the flattened schemas it is made of
+    fn required(schema: &serde_json::Value, schemas: &serde_json::Value) -> Vec<String> {
+        if let Some(name) = schema["$ref"].as_str() {
+            return required(&schemas[name.trim_start_matches("#/components/schemas/")], schemas);
+        }
+        let parts = schema["allOf"].as_array().into_iter().flatten();
+        let fields = schema["required"].as_array().into_iter().flatten();
+        parts
+            .flat_map(|part| required(part, schemas))
+            .chain(fields.map(|field| field.as_str().unwrap().to_string()))
+            .collect()
+    }
+
+    // sends a request each operation in the document should succeed with to the routes, and checks
+    // that it is answered with a success or redirect status documented for it, and with the fields
+    // its schema requires, so an operation whose path or method is not routed fails
+    #[tokio::test]
+    async fn test_routes_match_document() {
+        let mut handlebars = Handlebars::new();
+        handlebars
+            .register_templates_directory("templates", DirectorySourceOptions::default())
+            .unwrap();
+        let renderer = Renderer::new("go", db::Db::in_memory().unwrap(), handlebars);
+        let routes = routes::get_routes(renderer.clone(), "static".to_string());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(crate::server::serve(
+            warp::service(routes),
+            listener,
+            std::future::pending(),
+        ));
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let document = serde_json::to_value(document()).unwrap();
+        let schemas = &document["components"]["schemas"];
+        // an admin token may do anything, and the token revoked is another one
+        let token = |scope| model::TokenInput {
+            name: "test".to_string(),
+            scope,
+            expires_in_days: None,
+        };
+        let admin = renderer.issue_token(token(model::TokenScope::Admin)).await.unwrap();
+        let revoked = renderer.issue_token(token(model::TokenScope::Read)).await.unwrap();
+
+        let mut checked = 0;
+        for (path, item) in document["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                // every operation works on go/docs, which is recreated in case the last one deleted it
+                if renderer.db.link.find("docs").await.unwrap().is_none() {
+                    renderer
+                        .api_create(br#"{"short": "docs", "long": "http://docs.example"}"#)
+                        .await
+                        .unwrap();
+                }
+                checked += 1;
+                let short = format!("new{checked}");
+                let xsrf = url::form_urlencoded::byte_serialize(renderer.xsrf().as_bytes()).collect::<String>();
+                let content_type = operation["requestBody"]["content"]
+                    .as_object()
+                    .and_then(|content| content.keys().next().cloned());
+                let body = match (method.as_str(), content_type.as_deref()) {
+                    (_, Some("application/x-www-form-urlencoded")) => {
+                        // edits are of go/docs, and everything else creates a new link
+                        let short = if path == "/.update" { "docs" } else { &short };
+                        format!("short={short}&long=http%3A%2F%2Fexample.com&xsrf={xsrf}")
+                    }
+                    (_, Some("application/x-ndjson")) => {
+                        format!(r#"{{"short": "{short}", "long": "http://example.com"}}"#)
+                    }
+                    ("post", Some(_)) if path == "/api/v1/tokens" => r#"{"name": "script"}"#.to_string(),
+                    ("put", _) => r#"{"long": "http://example.com"}"#.to_string(),
+                    ("patch", _) => r#"{"max_age": 60}"#.to_string(),
+                    (_, Some(_)) => format!(r#"{{"short": "{short}", "long": "http://example.com"}}"#),
+                    (_, None) => String::new(),
+                };
+                // required query parameters, like the path to preview, are also go/docs
+                let query: Vec<(&str, &str)> = operation["parameters"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter(|param| param["in"] == "query" && param["required"] == true)
+                    .map(|param| (param["name"].as_str().unwrap(), "docs"))
+                    .collect();
+                let response = client
+                    .request(
+                        method.to_uppercase().parse().unwrap(),
+                        format!(
+                            "http://{addr}{}",
+                            path.replace("{short}", "docs").replace("{id}", &revoked.api_token.id)
+                        ),
+                    )
+                    .query(&query)
+                    .bearer_auth(&admin.token)
+                    .header("Content-Type", content_type.as_deref().unwrap_or("text/plain"))
+                    .body(body)
+                    .send()
+                    .await
+                    .unwrap();
+
+                let status = response.status().as_u16().to_string();
+                let documented = &operation["responses"][&status];
+                assert!(
+                    documented.is_object() && response.status().as_u16() < 400,
+                    "{method} {path} answered {status}"
+                );
+                let schema = &documented["content"]["application/json"]["schema"];
+                if schema.is_null() {
+                    continue;
+                }
+                let body: serde_json::Value = serde_json::from_slice(&response.bytes().await.unwrap())
+                    .unwrap_or_else(|e| panic!("{method} {path} answered {status} with invalid JSON: {e}"));
+                for field in required(schema, schemas) {
+                    assert!(
+                        body.get(&field).is_some(),
+                        "{method} {path} answered {status} without {field}"
+                    );
+                }
+            }
+        }
+        assert_eq!(checked, 24);
+        // only the operations counted against a rate limit can be answered with 429
+        let paths = &document["paths"];
+        assert!(paths["/api/v1/links"]["post"]["responses"]["429"].is_object());
+        assert!(paths["/{short}"]["get"]["responses"]["429"].is_object());
+        assert!(paths["/api/v1/links"]["get"]["responses"]["429"].is_null());
+
+        let response = client.get(format!("http://{addr}{PATH}")).send().await.unwrap();
+        let served: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(served, document);
+
+        // and every route is documented, but for the pages of the web interface
+        let pages = [
+            "/.all",
+            "/.wanted",
+            "/.hygiene",
+            "/.admin",
+            "/.admin/purge",
+            "/.admin/tokens",
+            "/.admin/tokens/{id}/revoke",
+            "/.help",
+            "/.detail/{short}",
+            "/assets",
+        ];
+        for route in routes::FIXED_ROUTES.iter().chain(routes::PARAMETERISED_ROUTES) {
+            assert!(
+                paths.get(*route).is_some() != pages.contains(route),
+                "{route} is {}documented",
+                if pages.contains(route) { "" } else { "not " }
+            );
+        }
+    }
+}
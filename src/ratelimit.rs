@@ -0,0 +1,262 @@
+use std::{
+    net::{IpAddr, Ipv6Addr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use hashlink::LruCache;
+use ipnet::IpNet;
+
+// the most clients whose budgets are remembered, beyond which the client counted least recently is forgotten
+const MAX_CLIENTS: usize = 10_000;
+
+/// RateLimit is the budget of requests each client gets: a burst of up to
+/// `burst` requests at once, refilled at `per_minute` requests a minute.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub per_minute: u32, // 0 turns the limit off
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// The limit of requests that change something, like creating or importing links.
+    pub const WRITES: RateLimit = RateLimit {
+        per_minute: 60,
+        burst: 30,
+    };
+    /// The limit of link lookups, like following a link.
+    pub const REDIRECTS: RateLimit = RateLimit {
+        per_minute: 600,
+        burst: 120,
+    };
+    pub const UNLIMITED: RateLimit = RateLimit {
+        per_minute: 0,
+        burst: 0,
+    };
+
+    fn is_unlimited(self) -> bool {
+        self.per_minute == 0
+    }
+
+    // the number of requests the budget refills by each second
+    fn refill_rate(self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+// the requests a client may still make, as of when it was last counted
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// RateLimiter keeps a token bucket for each client. Clients are told apart
+/// by IP address, except that IPv6 clients are told apart by their /64
+/// network, since a single host usually has a whole /64 to choose from.
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<LruCache<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(LruCache::new(MAX_CLIENTS)),
+        }
+    }
+
+    /// Counts a request from the client, returning how long it must wait
+    /// before its next request is allowed if its budget is spent.
+    pub(crate) fn check(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        if self.limit.is_unlimited() {
+            return Ok(());
+        }
+        let capacity = f64::from(self.limit.burst.max(1));
+        let rate = self.limit.refill_rate();
+        let refilled = |bucket: &Bucket| {
+            (bucket.tokens + now.saturating_duration_since(bucket.updated).as_secs_f64() * rate).min(capacity)
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let key = client_key(client);
+        if !buckets.contains_key(&key) {
+            // forgetting the least recent client at most refills its budget early, since its
+            // budget has been refilling for longer than any other client's
+            buckets.insert(
+                key,
+                Bucket {
+                    tokens: capacity,
+                    updated: now,
+                },
+            );
+        }
+        let bucket = buckets.get_mut(&key).expect("the bucket was just inserted");
+        bucket.tokens = refilled(bucket);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+// returns the address a client's budget is kept under
+fn client_key(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => IpAddr::V4(ip),
+        IpAddr::V6(ip) => {
+            let [a, b, c, d, ..] = ip.segments();
+            IpAddr::V6(Ipv6Addr::new(a, b, c, d, 0, 0, 0, 0))
+        }
+    }
+}
+
+/// Returns the address of the client that made a request which arrived from
+/// `peer`. A request from a trusted proxy is on behalf of the last address in
+/// its X-Forwarded-For header that is not itself a trusted proxy; any other
+/// request is from its peer, whatever it claims.
+pub fn client_ip(peer: IpAddr, forwarded_for: Option<&str>, trusted_proxies: &[IpNet]) -> IpAddr {
+    let trusted = |ip: &IpAddr| is_trusted_proxy(*ip, trusted_proxies);
+    if !trusted(&peer) {
+        return peer;
+    }
+    // each proxy appends the address it received the request from, so the
+    // addresses are read from the right until one was not added by a trusted proxy
+    let forwarded = forwarded_for
+        .unwrap_or_default()
+        .rsplit(',')
+        .map_while(|ip| ip.trim().parse::<IpAddr>().ok());
+    let mut client = peer;
+    for ip in forwarded {
+        client = ip;
+        if !trusted(&ip) {
+            break;
+        }
+    }
+    client
+}
+
+/// Returns whether the address is one of the trusted proxies, whose
+/// forwarding headers are believed.
+pub fn is_trusted_proxy(ip: IpAddr, trusted_proxies: &[IpNet]) -> bool {
+    trusted_proxies.iter().any(|net| net.contains(&ip.to_canonical()))
+}
+
+/// Budget is which limit a request counts against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Budget {
+    Write,
+    Redirect,
+}
+
+/// RateLimits are the limiters of every [`Budget`].
+pub(crate) struct RateLimits {
+    write: RateLimiter,
+    redirect: RateLimiter,
+}
+
+impl RateLimits {
+    pub(crate) fn new(write: RateLimit, redirect: RateLimit) -> Self {
+        Self {
+            write: RateLimiter::new(write),
+            redirect: RateLimiter::new(redirect),
+        }
+    }
+
+    /// Counts a request from the client against the budget, like [`RateLimiter::check`].
+    pub(crate) fn check(&self, budget: Budget, client: IpAddr) -> Result<(), Duration> {
+        match budget {
+            Budget::Write => self.write.check(client, Instant::now()),
+            Budget::Redirect => self.redirect.check(client, Instant::now()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_minute: 60,
+            burst: 2,
+        });
+        let start = Instant::now();
+        assert_eq!(limiter.check(ip("192.0.2.1"), start), Ok(()));
+        assert_eq!(limiter.check(ip("192.0.2.1"), start), Ok(()));
+        assert_eq!(limiter.check(ip("192.0.2.1"), start), Err(Duration::from_secs(1)));
+        assert_eq!(limiter.check(ip("192.0.2.2"), start), Ok(())); // another client has its own budget
+
+        // the budget refills at a request a second, up to the burst
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(limiter.check(ip("192.0.2.1"), later), Ok(()));
+        assert!(
+            limiter
+                .check(ip("192.0.2.1"), later)
+                .is_err_and(|wait| wait <= Duration::from_millis(500))
+        );
+        let much_later = start + Duration::from_secs(60);
+        assert_eq!(limiter.check(ip("192.0.2.1"), much_later), Ok(()));
+        assert_eq!(limiter.check(ip("192.0.2.1"), much_later), Ok(()));
+        assert!(limiter.check(ip("192.0.2.1"), much_later).is_err());
+
+        // IPv6 clients share the budget of their /64
+        assert_eq!(limiter.check(ip("2001:db8::1"), start), Ok(()));
+        assert_eq!(limiter.check(ip("2001:db8::2"), start), Ok(()));
+        assert!(limiter.check(ip("2001:db8::3"), start).is_err());
+        assert_eq!(limiter.check(ip("2001:db8:0:1::1"), start), Ok(()));
+
+        let unlimited = RateLimiter::new(RateLimit::UNLIMITED);
+        for _ in 0..1000 {
+            assert_eq!(unlimited.check(ip("192.0.2.1"), start), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_forgets_least_recent_clients() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_minute: 60,
+            burst: 1,
+        });
+        let start = Instant::now();
+        for n in 0..MAX_CLIENTS as u32 {
+            limiter.check(IpAddr::from(n.to_be_bytes()), start).unwrap();
+        }
+        assert!(limiter.check(IpAddr::from(0u32.to_be_bytes()), start).is_err());
+        limiter.check(ip("192.0.2.1"), start).unwrap();
+        assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_CLIENTS);
+
+        // the client counted least recently was forgotten, while the others are still limited
+        assert_eq!(limiter.check(IpAddr::from(1u32.to_be_bytes()), start), Ok(()));
+        assert!(limiter.check(IpAddr::from(0u32.to_be_bytes()), start).is_err());
+        assert!(limiter.check(ip("192.0.2.1"), start).is_err());
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let proxies: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()];
+        let client = |peer, forwarded_for| client_ip(ip(peer), forwarded_for, &proxies);
+
+        assert_eq!(client("192.0.2.1", None), ip("192.0.2.1"));
+        // only trusted proxies may say who the client is
+        assert_eq!(client("192.0.2.1", Some("198.51.100.7")), ip("192.0.2.1"));
+        assert_eq!(client("10.0.0.2", Some("198.51.100.7")), ip("198.51.100.7"));
+        assert_eq!(client("::1", Some("198.51.100.7")), ip("198.51.100.7"));
+        // addresses added before the trusted proxies could be made up by the client
+        assert_eq!(
+            client("10.0.0.2", Some("203.0.113.9, 198.51.100.7, 10.0.0.3")),
+            ip("198.51.100.7")
+        );
+        assert_eq!(client("10.0.0.2", Some("10.0.0.4, 10.0.0.3")), ip("10.0.0.4"));
+        assert_eq!(client("10.0.0.2", None), ip("10.0.0.2"));
+        assert_eq!(client("10.0.0.2", Some("unknown")), ip("10.0.0.2"));
+    }
+}
@@ -2,19 +2,85 @@ use std::{
     collections::HashMap,
     convert::Infallible,
     io::{BufWriter, Write},
+    net::{IpAddr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use csrf::{AesGcmCsrfProtection, CsrfProtection};
-use handlebars::Handlebars;
+use handlebars::{Context, Handlebars, Output, RenderContext, RenderError, Renderable};
+use ipnet::IpNet;
 use rand::Rng;
-use regex::Regex;
 use url::Url;
 
-use crate::{CreateUpdateRequest, db, model};
+use crate::{
+    CreateUpdateRequest, RequestContext,
+    cache::LinkCache,
+    clicks::ClickRecorder,
+    db, golink,
+    helpers::register_helpers,
+    hygiene,
+    metrics::{self, RedirectOutcome},
+    model,
+    ratelimit::{self, RateLimit, RateLimits},
+    template::{
+        BoundedOutput, CompiledLink, DEADLINE_HELPER, ExpandError, TemplateCache, TemplateContext, check_deadline,
+    },
+};
 
 const PARENT_PARTIAL: &str = "base";
 
+// Destination links are rendered for every click, so they are bounded in the
+// length of URL they can produce and the time they can take to produce it.
+pub(crate) const MAX_LINK_LENGTH: usize = 8 * 1024;
+const MAX_RENDER_TIME: Duration = Duration::from_millis(50);
+
+/// The User-Agents, separated by commas, of link unfurlers, crawlers and
+/// monitors whose requests are not counted as clicks by default.
+pub const DEFAULT_BOT_USER_AGENTS: &str = "bot,crawler,spider,slurp,facebookexternalhit,Slack-ImgProxy,Discordbot,WhatsApp,\
+TelegramBot,SkypeUriPreview,HeadlessChrome,Pingdom,UptimeRobot,StatusCake,Site24x7";
+
+// Requests made ahead of a click, such as link prefetches, carry one of these
+// headers with a value naming the purpose, like "prefetch".
+const PURPOSE_HEADERS: [&str; 4] = ["purpose", "sec-purpose", "x-purpose", "x-moz"];
+
+// the most requested short names without a link shown on the wanted links page
+const WANTED_LINKS: usize = 100;
+
+/// The number of days of clicks broken down by source when no window is chosen.
+pub const BREAKDOWN_DAYS: u32 = 30;
+
+/// The number of days where each click came from is kept by default; clicks per day are kept forever.
+pub const CLICK_RETENTION_DAYS: u32 = 90;
+
+// the most common referrers and subnets shown for a link
+const TOP_SOURCES: usize = 10;
+
+// the most popular links shown on the home page at most, and the recently created and updated links shown
+const MAX_POPULAR_LINKS: usize = 100;
+const RECENT_LINKS: usize = 5;
+
+/// The number of days without clicks after which the hygiene report lists a link as stale.
+pub const HYGIENE_DAYS: u32 = 180;
+
+/// The most days the hygiene report looks back for clicks.
+pub const MAX_HYGIENE_DAYS: u32 = 3650;
+
+// how long a hygiene report is reused while no link changes, since checking every link is slow
+// and only the clicks it shows can change in the meantime
+const HYGIENE_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// The most days of clicks per day returned at once.
+pub const MAX_ROLLUP_DAYS: u32 = 366;
+
+// the size of the click sparkline on the detail page
+const SPARKLINE_WIDTH: f64 = 300.0;
+const SPARKLINE_HEIGHT: f64 = 40.0;
+
+// destination links are expanded with each of these paths before they are saved
+const VALIDATION_PATHS: [&str; 4] = ["", "foo", "foo/bar", "foo bar"];
+
 struct Message {
     msg: String,
 }
@@ -33,40 +99,60 @@ impl warp::Reply for Message {
     }
 }
 
-handlebars::handlebars_helper!(query_escape: |query_string: String| url_escape::encode_query(&query_string).clone());
-handlebars::handlebars_helper!(path_escape: |path: String| url_escape::encode_path(&path));
-handlebars::handlebars_helper!(trim_suffix: |path: String, suffix: String| {
-    match path.strip_suffix(&suffix) {
-        Some(result) => result,
-        _ => &path
-    }.to_string()
-});
-handlebars::handlebars_helper!(trim_prefix: |path: String, prefix: String| {
-    match path.strip_prefix(&prefix) {
-        Some(result) => result,
-        _ => &path
-    }.to_string()
-});
-handlebars::handlebars_helper!(to_lower: |s: String| s.to_lowercase());
-handlebars::handlebars_helper!(to_upper: |s: String| s.to_uppercase());
-handlebars::handlebars_helper!(now: |*_kwargs| {
-    Utc::now().to_rfc3339()
-});
-handlebars::handlebars_helper!(now_format: |format: String| {
-    Utc::now().format(&format).to_string()
-});
-handlebars::handlebars_helper!(date_format: |t: DateTime<Utc>, format: String| {
-    t.format(&format).to_string()
-});
-handlebars::handlebars_helper!(match_string: |pattern: String, path: String| {
-    let re: Result<Regex, _> = pattern.try_into();
-    match re {
-        Ok(r) => {
-            r.is_match(&path)
-        },
-        _ => false
-    }
-});
+/// Options configures how a [`Renderer`] renders pages and destination links.
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub timezone: Tz,                 // the default timezone of the date helpers
+    pub link_cache_size: usize,       // the most links held in memory, or 0 to always read the database
+    pub bot_user_agents: Vec<String>, // requests with a User-Agent containing any of these are not counted as clicks
+    pub track_clicks: bool,           // whether clicks and missed lookups are recorded at all
+    pub click_retention_days: u32,    // the days where each click came from is kept, or 0 to keep it forever
+    pub subnet_key: Option<String>,   // when set, client subnets are recorded as a keyed hash of the subnet
+    pub require_api_tokens: bool,     // whether scripts need an API token, rather than the Sec-Golink header
+    pub write_limit: RateLimit,       // each client's budget of requests that change something
+    pub redirect_limit: RateLimit,    // each client's budget of link lookups
+    pub trusted_proxies: Vec<IpNet>,  // proxies whose X-Forwarded-For header says who the client is
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            timezone: Tz::UTC,
+            link_cache_size: 1024,
+            bot_user_agents: DEFAULT_BOT_USER_AGENTS.split(',').map(str::to_string).collect(),
+            track_clicks: true,
+            click_retention_days: CLICK_RETENTION_DAYS,
+            subnet_key: None,
+            require_api_tokens: false,
+            write_limit: RateLimit::WRITES,
+            redirect_limit: RateLimit::REDIRECTS,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+/// Popularity chooses which links the home page shows as popular.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Popularity {
+    pub days: Option<u32>, // links are ranked by their clicks in the last this many days, or of all time
+    pub limit: usize,      // the number of links shown
+}
+
+impl Default for Popularity {
+    fn default() -> Self {
+        Self {
+            days: Some(30),
+            limit: 10,
+        }
+    }
+}
+
+// the last hygiene report built, with the link cache generation it was built at and when
+struct CachedHygiene {
+    report: model::HygieneReport,
+    generation: u64,
+    built: Instant,
+}
 
 #[derive(Clone)]
 pub struct Renderer {
@@ -74,6 +160,18 @@ pub struct Renderer {
     csrf_key: csrf::AesGcmCsrfProtection,
     pub(crate) db: db::Db,
     pub(crate) handlebars: handlebars::Handlebars<'static>,
+    // destination links are URLs rather than HTML so are rendered by their own registry without escaping
+    pub(crate) links: handlebars::Handlebars<'static>,
+    pub(crate) templates: Arc<TemplateCache>,
+    pub(crate) link_cache: Arc<LinkCache>,
+    pub(crate) clicks: Arc<ClickRecorder>,
+    bot_user_agents: Arc<Vec<String>>, // lowercase
+    pub(crate) track_clicks: bool,
+    subnet_key: Option<ring::hmac::Key>,
+    pub(crate) require_api_tokens: bool,
+    pub(crate) rate_limits: Arc<RateLimits>,
+    trusted_proxies: Arc<Vec<IpNet>>,
+    hygiene: Arc<Mutex<Option<CachedHygiene>>>,
 }
 
 impl Renderer {
@@ -82,26 +180,210 @@ impl Renderer {
     }
 
     pub fn new(host: &str, db: db::Db, handlebars: handlebars::Handlebars<'static>) -> Self {
+        Self::with_options(host, db, handlebars, Options::default())
+    }
+
+    pub fn with_options(host: &str, db: db::Db, handlebars: handlebars::Handlebars<'static>, options: Options) -> Self {
         let mut secret_key = [0u8; 32];
         rand::rng().fill_bytes(&mut secret_key);
         let aes_gcm_csrf_protection = AesGcmCsrfProtection::from_key(secret_key);
 
         let mut bars = handlebars.clone();
-        bars.register_helper("query_escape", Box::new(query_escape));
-        bars.register_helper("path_escape", Box::new(path_escape));
-        bars.register_helper("lowercase", Box::new(to_lower));
-        bars.register_helper("uppercase", Box::new(to_upper));
-        bars.register_helper("trimsuffix", Box::new(trim_suffix));
-        bars.register_helper("trimprefix", Box::new(trim_prefix));
-        bars.register_helper("now", Box::new(now));
-        bars.register_helper("nowformat", Box::new(now_format));
-        bars.register_helper("dateformat", Box::new(date_format));
-        bars.register_helper("match", Box::new(match_string));
+        register_helpers(&mut bars, options.timezone);
+        let mut links = Handlebars::new();
+        links.register_escape_fn(handlebars::no_escape);
+        links.set_strict_mode(true);
+        links.unregister_helper("log");
+        register_helpers(&mut links, options.timezone);
+        links.register_helper(DEADLINE_HELPER, Box::new(check_deadline));
         Self {
             host: host.to_string(),
             csrf_key: aes_gcm_csrf_protection,
             db,
             handlebars: bars,
+            links,
+            templates: Arc::new(TemplateCache::default()),
+            link_cache: Arc::new(LinkCache::new(options.link_cache_size)),
+            clicks: Arc::new(ClickRecorder::new(options.click_retention_days)),
+            bot_user_agents: Arc::new(
+                options
+                    .bot_user_agents
+                    .iter()
+                    .map(|agent| agent.trim().to_lowercase())
+                    .filter(|agent| !agent.is_empty())
+                    .collect(),
+            ),
+            track_clicks: options.track_clicks,
+            subnet_key: options
+                .subnet_key
+                .map(|key| ring::hmac::Key::new(ring::hmac::HMAC_SHA256, key.as_bytes())),
+            require_api_tokens: options.require_api_tokens,
+            rate_limits: Arc::new(RateLimits::new(options.write_limit, options.redirect_limit)),
+            trusted_proxies: Arc::new(options.trusted_proxies),
+            hygiene: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Returns the address of the client that made a request arriving from the
+    /// peer, which is told by the X-Forwarded-For header of a trusted proxy.
+    pub(crate) fn client_ip(&self, peer: IpAddr, headers: &warp::http::HeaderMap) -> IpAddr {
+        // a header repeated by several proxies is the same as one listing all of their addresses
+        let forwarded_for = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        ratelimit::client_ip(peer, Some(&forwarded_for), &self.trusted_proxies)
+    }
+
+    /// Returns the user a trusted proxy says made a request arriving from the
+    /// peer, in its X-Forwarded-User header. Anyone else could claim to be anyone.
+    pub(crate) fn forwarded_user(&self, peer: IpAddr, headers: &warp::http::HeaderMap) -> Option<String> {
+        if !ratelimit::is_trusted_proxy(peer, &self.trusted_proxies) {
+            return None;
+        }
+        headers
+            .get("x-forwarded-user")
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|user| !user.is_empty())
+            .map(str::to_string)
+    }
+
+    // drops the cached link and compiled template for the short name once its link is saved or deleted
+    pub(crate) fn invalidate(&self, short: &str) {
+        self.link_cache.invalidate(short);
+        self.templates.invalidate(short);
+    }
+
+    // deletes the link of the short name along with its click stats, recorded clicks and last check
+    pub(crate) async fn delete_link(&self, short: &str) -> Result<(), Box<db::DbError>> {
+        self.db.link.delete(short).await?;
+        self.invalidate(short);
+        let _ = self.db.stats.delete(short).await;
+        let _ = self.db.clicks.delete(short).await;
+        let _ = self.db.checks.delete(short).await;
+        Ok(())
+    }
+
+    /// Writes the clicks counted since the last flush to the database. Clicks
+    /// are only counted in memory by redirects, so this is called periodically
+    /// and on shutdown.
+    pub async fn flush_clicks(&self) {
+        match self.clicks.flush(&self.db).await {
+            Ok(0) => {}
+            Ok(clicks) => tracing::debug!("flushed {clicks} clicks"),
+            Err(e) => tracing::error!("failed to flush clicks: {e}"),
+        }
+    }
+
+    // returns why a request for a link is not a click, if it is not
+    fn not_a_click(&self, request: &RequestContext) -> Option<&'static str> {
+        let header = |name| request.headers.get(name).and_then(|value| value.to_str().ok());
+        if request.method == warp::http::Method::HEAD {
+            return Some("HEAD request");
+        }
+        if PURPOSE_HEADERS.iter().filter_map(|name| header(*name)).any(|purpose| {
+            let purpose = purpose.to_lowercase();
+            purpose.contains("prefetch") || purpose.contains("prerender") || purpose.contains("preview")
+        }) {
+            return Some("prefetch");
+        }
+        let user_agent = header("user-agent").unwrap_or_default().to_lowercase();
+        if self
+            .bot_user_agents
+            .iter()
+            .any(|agent| user_agent.contains(agent.as_str()))
+        {
+            return Some("bot");
+        }
+        None
+    }
+
+    // describes where a click came from: the client's subnet, the Referer host and the kind of client
+    fn click_source(&self, request: &RequestContext) -> model::ClickSource {
+        let header = |name| request.headers.get(name).and_then(|value| value.to_str().ok());
+        let subnet = request.client_ip.map(|ip| {
+            let subnet = match ip.to_canonical() {
+                IpAddr::V4(ip) => {
+                    let [a, b, c, _] = ip.octets();
+                    format!("{a}.{b}.{c}.0/24")
+                }
+                IpAddr::V6(ip) => {
+                    let [a, b, c, ..] = ip.segments();
+                    format!("{}/48", Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0))
+                }
+            };
+            match &self.subnet_key {
+                // a short hash still tells subnets apart without revealing them
+                Some(key) => data_encoding::HEXLOWER.encode(&ring::hmac::sign(key, subnet.as_bytes()).as_ref()[..8]),
+                None => subnet,
+            }
+        });
+        let referrer = header("referer")
+            .and_then(|referrer| Url::parse(referrer).ok())
+            .and_then(|url| url.host_str().map(str::to_lowercase));
+        model::ClickSource {
+            subnet,
+            referrer,
+            agent: model::AgentFamily::from_user_agent(header("user-agent").unwrap_or_default()),
+            clicked: chrono::Utc::now(),
+        }
+    }
+
+    // breaks down the recent clicks on the short name by source, unless clicks are not being recorded
+    pub(crate) async fn breakdown(&self, short: &str, days: Option<u32>) -> Option<model::ClickBreakdown> {
+        if !self.track_clicks {
+            return None;
+        }
+        self.db
+            .clicks
+            .breakdown(short, days, TOP_SOURCES)
+            .await
+            .map_err(|e| tracing::error!("{e}"))
+            .ok()
+    }
+
+    // returns the clicks per day on the short name over the last `days` days, including today,
+    // or none if it has no link
+    pub(crate) async fn rollups(&self, short: &str, days: u32) -> Result<Option<model::Rollups>, Box<db::DbError>> {
+        let Some(stats) = self.db.stats.load(short).await? else {
+            return Ok(None);
+        };
+        let today = chrono::Utc::now().date_naive();
+        let since = today - chrono::Duration::days(i64::from(days.max(1)) - 1);
+        let mut recorded = self.db.clicks.daily(short, since).await?.into_iter().peekable();
+        let daily = since
+            .iter_days()
+            .take_while(|day| *day <= today)
+            .map(|day| model::DailyClicks {
+                day,
+                clicks: recorded.next_if(|recorded| recorded.day == day).map_or(0, |d| d.clicks),
+            })
+            .collect::<Vec<_>>();
+        Ok(Some(model::Rollups {
+            short: short.to_string(),
+            days: daily.len() as u32,
+            clicks: stats.clicks.unwrap_or(0) as u64,
+            last_clicked: self.db.clicks.last_clicked(short).await?,
+            daily,
+        }))
+    }
+
+    /// Returns the clicks per day on a link over the last `days` days as JSON.
+    pub async fn json_rollups(&self, short: &str, days: u32) -> Result<Box<dyn warp::Reply>, Infallible> {
+        if !self.track_clicks {
+            // click stats are not shown when clicks are not being recorded
+            return response("click tracking is disabled", warp::http::StatusCode::NOT_FOUND);
+        }
+        match self.rollups(short, days.min(MAX_ROLLUP_DAYS)).await {
+            Ok(Some(rollups)) => json(rollups, warp::http::StatusCode::OK),
+            Ok(None) => response("link not found", warp::http::StatusCode::NOT_FOUND),
+            Err(e) => {
+                tracing::error!("{e}");
+                response("failed to load clicks", warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+            }
         }
     }
 
@@ -129,14 +411,49 @@ fn redirect_with_status(location: &str, status: warp::http::StatusCode) -> Resul
     )))
 }
 
+// redirects a click with the link's redirect status, letting browsers cache it for the link's max age
+fn redirect_for_link(location: &str, link: &model::Link) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let status = warp::http::StatusCode::from_u16(link.status.code()).unwrap();
+    let cache_control = link
+        .max_age
+        .map_or_else(|| "no-cache".to_string(), |max_age| format!("max-age={max_age}"));
+    Ok(Box::new(warp::reply::with_header(
+        warp::reply::with_status(warp::redirect(location.parse::<warp::http::Uri>().unwrap()), status),
+        "Cache-Control",
+        cache_control,
+    )))
+}
+
 fn response(message: &str, status: warp::http::StatusCode) -> Result<Box<dyn warp::Reply>, Infallible> {
     Ok(Box::new(warp::reply::with_status(Message::new(message), status)))
 }
 
+// returns the SVG polyline points plotting counts, oldest first, from the bottom left of the sparkline
+fn sparkline(counts: &[u64]) -> String {
+    let max = counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let step = SPARKLINE_WIDTH / counts.len().saturating_sub(1).max(1) as f64;
+    counts
+        .iter()
+        .enumerate()
+        .map(|(i, count)| {
+            format!(
+                "{:.1},{:.1}",
+                i as f64 * step,
+                SPARKLINE_HEIGHT - *count as f64 / max * SPARKLINE_HEIGHT
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 fn html(response: String) -> Result<Box<dyn warp::Reply>, Infallible> {
     Ok(Box::new(warp::reply::html(response)))
 }
 
+fn html_with_status(response: String, status: warp::http::StatusCode) -> Result<Box<dyn warp::Reply>, Infallible> {
+    Ok(Box::new(warp::reply::with_status(warp::reply::html(response), status)))
+}
+
 fn json<T>(json: T, status: warp::http::StatusCode) -> Result<Box<dyn warp::Reply>, Infallible>
 where
     T: serde::Serialize,
@@ -145,16 +462,27 @@ where
 }
 
 impl Renderer {
-    pub async fn home(&self) -> Result<Box<dyn warp::Reply>, Infallible> {
-        let mut links: Vec<(model::Link, model::ClickStats)> = Vec::new();
-        match self.db.link.most_popular().await {
-            Ok(mut results) => {
-                links.append(&mut results);
-            }
-            Err(e) => {
+    pub async fn home(&self, short: &str, popularity: Popularity) -> Result<Box<dyn warp::Reply>, Infallible> {
+        self.render_home(short, "", &[], popularity).await
+    }
+
+    async fn render_home(
+        &self,
+        short: &str,
+        long: &str,
+        errors: &[String],
+        popularity: Popularity,
+    ) -> Result<Box<dyn warp::Reply>, Infallible> {
+        let limit = popularity.limit.clamp(1, MAX_POPULAR_LINKS);
+        let (days, links) = self.popular_links(popularity.days, limit).await;
+        let recent = |links: Result<Vec<model::Link>, Box<db::DbError>>| {
+            links.unwrap_or_else(|e| {
                 tracing::error!("{e}");
-            }
-        }
+                Vec::new()
+            })
+        };
+        let created = recent(self.db.link.recently_created(RECENT_LINKS).await);
+        let updated = recent(self.db.link.recently_updated(RECENT_LINKS).await);
 
         let most_popular_links: Vec<model::PopularLink> = links
             .iter()
@@ -165,9 +493,10 @@ impl Renderer {
             .collect();
         match self.handlebars.render(
             "home",
-            &serde_json::json!({"go": self.host, "parent": PARENT_PARTIAL, "links": most_popular_links, "XSRF": self.xsrf()}),
+            &serde_json::json!({"go": self.host, "parent": PARENT_PARTIAL, "links": most_popular_links, "days": days, "limit": limit, "created": created, "updated": updated, "tracking": self.track_clicks, "XSRF": self.xsrf(), "short": short, "long": long, "errors": errors}),
         ) {
-            Ok(response) => html(response),
+            Ok(response) if errors.is_empty() => html(response),
+            Ok(response) => html_with_status(response, warp::http::StatusCode::BAD_REQUEST),
             Err(e) => {
                 tracing::error!("{e}");
                 redirect("/")
@@ -175,12 +504,60 @@ impl Renderer {
         }
     }
 
-    pub async fn detail(&self, short: &str) -> Result<Box<dyn warp::Reply>, Infallible> {
+    // returns the most clicked links, and the window they were ranked over. Clicks per day are only kept
+    // from the upgrade that added them on, so a window without any falls back to all time
+    async fn popular_links(
+        &self,
+        days: Option<u32>,
+        limit: usize,
+    ) -> (Option<u32>, Vec<(model::Link, model::ClickStats)>) {
+        let popular = |days| async move {
+            self.db.link.most_popular(days, limit).await.unwrap_or_else(|e| {
+                tracing::error!("{e}");
+                Vec::new()
+            })
+        };
+        let links = popular(days).await;
+        if links.is_empty() && days.is_some() {
+            (None, popular(None).await)
+        } else {
+            (days, links)
+        }
+    }
+
+    /// Renders the detail page of a link, with where its clicks in the last
+    /// `days` days, or all its clicks, came from.
+    pub async fn detail(&self, short: &str, days: Option<u32>) -> Result<Box<dyn warp::Reply>, Infallible> {
         match self.db.link.load(short).await {
             Ok(link) => {
+                let breakdown = self.breakdown(&link.short, days).await;
+                // the sparkline shows the last 30 days, or the last 90 for longer windows
+                let trend_days = if days.is_some_and(|days| days <= 30) { 30 } else { 90 };
+                let rollups = if self.track_clicks {
+                    self.rollups(&link.short, trend_days).await.unwrap_or_else(|e| {
+                        tracing::error!("{e}");
+                        None
+                    })
+                } else {
+                    None
+                };
+                let sparkline = rollups.as_ref().map(|rollups| {
+                    let counts = rollups.daily.iter().map(|d| d.clicks).collect::<Vec<_>>();
+                    serde_json::json!({
+                        "points": sparkline(&counts),
+                        "max": counts.iter().max(),
+                        "total": counts.iter().sum::<u64>(),
+                        "width": SPARKLINE_WIDTH,
+                        "height": SPARKLINE_HEIGHT,
+                    })
+                });
+                let check = self.db.checks.load(&link.short).await.unwrap_or_else(|e| {
+                    tracing::error!("{e}");
+                    None
+                });
                 match self.handlebars.render(
                     "detail",
-                    &serde_json::json!({"go": self.host, "parent": PARENT_PARTIAL, "link": link, "XSRF": self.xsrf()}),
+                    &serde_json::json!({"go": self.host, "parent": PARENT_PARTIAL, "link": link, "breakdown": breakdown, "rollups": rollups, "sparkline": sparkline, "check": check, "XSRF": self.xsrf()}),
                 ) {
                     Ok(response) => html(response),
                     Err(e) => {
@@ -196,12 +573,345 @@ impl Renderer {
         }
     }
 
+    pub async fn wanted(&self) -> Result<Box<dyn warp::Reply>, Infallible> {
+        // misses recorded before tracking was turned off are not shown either
+        let wanted = if self.track_clicks {
+            self.db.misses.wanted(WANTED_LINKS).await
+        } else {
+            Ok(Vec::new())
+        };
+        match wanted {
+            Ok(wanted) => {
+                match self.handlebars.render(
+                    "wanted",
+                    &serde_json::json!({"wanted": wanted, "tracking": self.track_clicks, "go": self.host, "parent": PARENT_PARTIAL}),
+                ) {
+                    Ok(response) => html(response),
+                    Err(e) => {
+                        tracing::error!("{e}");
+                        redirect("/")
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::error!("{e}");
+                redirect("/")
+            }
+        }
+    }
+
+    // returns the hygiene report, reusing the last one built for `days` if no link changed since
+    // and it is recent
+    async fn hygiene_report(&self, days: u32) -> Result<model::HygieneReport, Box<db::DbError>> {
+        let days = days.min(MAX_HYGIENE_DAYS);
+        let generation = self.link_cache.generation();
+        if let Some(cached) = self.hygiene.lock().unwrap().as_ref()
+            && cached.report.days == days
+            && cached.generation == generation
+            && cached.built.elapsed() < HYGIENE_CACHE_TTL
+        {
+            return Ok(cached.report.clone());
+        }
+        let report = self.build_hygiene_report(days).await?;
+        *self.hygiene.lock().unwrap() = Some(CachedHygiene {
+            report: report.clone(),
+            generation,
+            built: Instant::now(),
+        });
+        Ok(report)
+    }
+
+    // lists links that were never clicked or not clicked in `days` days, that likely duplicate
+    // other links, or whose destination links look broken
+    async fn build_hygiene_report(&self, days: u32) -> Result<model::HygieneReport, Box<db::DbError>> {
+        let links = self.db.link.load_all_with_usage().await?;
+        let cutoff = chrono::Utc::now() - chrono::Duration::days(days.into());
+        let malformed = links
+            .iter()
+            .filter_map(|(link, _)| {
+                let mut problems = self
+                    .validate_target(&link.long, &link.passthrough)
+                    .err()
+                    .unwrap_or_default();
+                problems.extend(hygiene::target_problems(&link.long));
+                (!problems.is_empty()).then(|| model::MalformedLink {
+                    short: link.short.clone(),
+                    long: link.long.clone(),
+                    problems,
+                })
+            })
+            .collect();
+        let links: Vec<model::HygieneLink> = links
+            .into_iter()
+            .map(|(link, usage)| model::HygieneLink {
+                short: link.short,
+                long: link.long,
+                created: link.created,
+                usage,
+            })
+            .collect();
+        // links are only unused if clicks on them would have been recorded
+        let (never_clicked, stale) = if self.track_clicks {
+            let tracked = || links.iter().filter(|link| link.created < cutoff);
+            (
+                tracked().filter(|link| link.usage.clicks == 0).cloned().collect(),
+                // clicks from before daily rollups were kept have no day, so count as stale
+                tracked()
+                    .filter(|link| {
+                        link.usage.clicks > 0 && link.usage.last_clicked.is_none_or(|day| day < cutoff.date_naive())
+                    })
+                    .cloned()
+                    .collect(),
+            )
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        Ok(model::HygieneReport {
+            days,
+            never_clicked,
+            stale,
+            duplicates: hygiene::duplicates(&links),
+            collisions: hygiene::collisions(&links),
+            malformed,
+        })
+    }
+
+    /// Renders the hygiene report, where links not clicked in `days` days are stale.
+    pub async fn hygiene(&self, days: u32) -> Result<Box<dyn warp::Reply>, Infallible> {
+        self.render_hygiene(days, None).await
+    }
+
+    async fn render_hygiene(&self, days: u32, done: Option<String>) -> Result<Box<dyn warp::Reply>, Infallible> {
+        match self.hygiene_report(days).await {
+            Ok(report) => {
+                match self.handlebars.render(
+                    "hygiene",
+                    &serde_json::json!({"go": self.host, "parent": PARENT_PARTIAL, "report": report, "tracking": self.track_clicks, "done": done, "XSRF": self.xsrf()}),
+                ) {
+                    Ok(response) => html(response),
+                    Err(e) => {
+                        tracing::error!("{e}");
+                        redirect("/")
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::error!("{e}");
+                redirect("/")
+            }
+        }
+    }
+
+    /// Returns the hygiene report as JSON.
+    pub async fn json_hygiene(&self, days: u32) -> Result<Box<dyn warp::Reply>, Infallible> {
+        match self.hygiene_report(days).await {
+            Ok(report) => json(report, warp::http::StatusCode::OK),
+            Err(e) => {
+                tracing::error!("{e}");
+                response(
+                    "failed to build the hygiene report",
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                )
+            }
+        }
+    }
+
+    /// Applies a bulk action from the hygiene report to the chosen short names:
+    /// "delete" deletes their links, and "merge" merges them into the link
+    /// `into`, which keeps their clicks.
+    pub async fn hygiene_action(
+        &self,
+        action: &str,
+        shorts: &[String],
+        into: Option<&str>,
+        xsrf: &str,
+    ) -> Result<Box<dyn warp::Reply>, Infallible> {
+        if let Err(e) = self
+            .csrf_key
+            .parse_token(&data_encoding::BASE64.decode(xsrf.as_bytes()).unwrap_or_default())
+        {
+            tracing::error!("Invalid xsrf token: {e}");
+            return redirect("/.hygiene");
+        }
+
+        let mut done = 0;
+        let mut errors = Vec::new();
+        match (action, into) {
+            ("delete", _) => {
+                for short in shorts {
+                    match self.delete_link(short).await {
+                        Ok(()) => done += 1,
+                        Err(e) => errors.push(format!("go/{short}: {e}")),
+                    }
+                }
+            }
+            ("merge", Some(into)) => {
+                let into_id = model::normalized_id(into);
+                for short in shorts.iter().filter(|short| model::normalized_id(short) != into_id) {
+                    match self.db.link.merge(short, into).await {
+                        Ok(()) => {
+                            self.invalidate(short);
+                            done += 1;
+                        }
+                        Err(e) => errors.push(format!("go/{short}: {e}")),
+                    }
+                }
+            }
+            _ => return self.bad_request().await,
+        }
+        for error in &errors {
+            tracing::error!("hygiene {action}: {error}");
+        }
+        let summary = match (action, into) {
+            ("merge", Some(into)) => format!("Links merged into {}/{into}: {done}.", self.host),
+            _ => format!("Links deleted: {done}."),
+        };
+        let summary = if errors.is_empty() {
+            summary
+        } else {
+            format!("{summary} Failed: {}", errors.join("; "))
+        };
+        self.render_hygiene(HYGIENE_DAYS, Some(summary)).await
+    }
+
+    pub async fn admin(&self) -> Result<Box<dyn warp::Reply>, Infallible> {
+        self.render_admin(None, None).await
+    }
+
+    // renders the admin page, with the result of a purge or the token just created, which is only ever shown there
+    async fn render_admin(
+        &self,
+        purged: Option<model::PurgeResult>,
+        created: Option<model::NewToken>,
+    ) -> Result<Box<dyn warp::Reply>, Infallible> {
+        let tokens = match self.db.tokens.load_all().await {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                tracing::error!("{e}");
+                Vec::new()
+            }
+        };
+        let now = chrono::Utc::now();
+        let expired: HashMap<&str, bool> = tokens
+            .iter()
+            .filter(|token| token.is_expired(now))
+            .map(|token| (token.id.as_str(), true))
+            .collect();
+        match self.handlebars.render(
+            "admin",
+            &serde_json::json!({
+                "go": self.host,
+                "parent": PARENT_PARTIAL,
+                "tracking": self.track_clicks,
+                "purged": purged,
+                "tokens": tokens,
+                "expired": expired,
+                "created": created,
+                "require_api_tokens": self.require_api_tokens,
+                "XSRF": self.xsrf()
+            }),
+        ) {
+            Ok(response) => html(response),
+            Err(e) => {
+                tracing::error!("{e}");
+                redirect("/")
+            }
+        }
+    }
+
+    /// Forgets all recorded usage: the click stats of every link, clicks not yet
+    /// written to the database, and missed lookups.
+    pub async fn purge(&self, xsrf: &str) -> Result<Box<dyn warp::Reply>, Infallible> {
+        if let Err(e) = self
+            .csrf_key
+            .parse_token(&data_encoding::BASE64.decode(xsrf.as_bytes()).unwrap_or_default())
+        {
+            tracing::error!("Invalid xsrf token: {e}");
+            return redirect("/.admin");
+        }
+
+        self.clicks.discard().await;
+        let purged = match (
+            self.db.stats.purge().await,
+            self.db.clicks.purge().await,
+            self.db.misses.purge().await,
+        ) {
+            (Ok(links), Ok(clicks), Ok(misses)) => model::PurgeResult { links, clicks, misses },
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                tracing::error!("purge: {e}");
+                return redirect_with_status("/.admin", warp::http::StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+        tracing::info!(
+            "purged click stats of {} links, {} clicks and {} missed lookups",
+            purged.links,
+            purged.clicks,
+            purged.misses
+        );
+        self.render_admin(Some(purged), None).await
+    }
+
+    /// Creates an API token from the admin page, which shows the token itself just this once.
+    pub async fn create_token(&self, input: model::TokenInput, xsrf: &str) -> Result<Box<dyn warp::Reply>, Infallible> {
+        if let Err(e) = self
+            .csrf_key
+            .parse_token(&data_encoding::BASE64.decode(xsrf.as_bytes()).unwrap_or_default())
+        {
+            tracing::error!("Invalid xsrf token: {e}");
+            return redirect("/.admin");
+        }
+        if !crate::api::token_problems(&input).is_empty() {
+            return self.bad_request().await;
+        }
+
+        match self.issue_token(input).await {
+            Ok(created) => self.render_admin(None, Some(created)).await,
+            Err(e) => {
+                tracing::error!("create token: {e}");
+                redirect_with_status("/.admin", warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+
+    /// Revokes an API token from the admin page.
+    pub async fn revoke_token(&self, id: &str, xsrf: &str) -> Result<Box<dyn warp::Reply>, Infallible> {
+        if let Err(e) = self
+            .csrf_key
+            .parse_token(&data_encoding::BASE64.decode(xsrf.as_bytes()).unwrap_or_default())
+        {
+            tracing::error!("Invalid xsrf token: {e}");
+            return redirect("/.admin");
+        }
+
+        match self.db.tokens.delete(id).await {
+            Ok(true) => tracing::info!("revoked API token {id}"),
+            Ok(false) => tracing::warn!("no API token {id} to revoke"),
+            Err(e) => {
+                tracing::error!("revoke token: {e}");
+                return redirect_with_status("/.admin", warp::http::StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+        redirect("/.admin")
+    }
+
     pub async fn all(&self) -> Result<Box<dyn warp::Reply>, Infallible> {
         match self.db.link.load_all().await {
             Ok(links) => {
+                // links that have not been checked, or that the checker is not running for, are not flagged
+                let mut checks = self.db.checks.load_all().await.unwrap_or_else(|e| {
+                    tracing::error!("{e}");
+                    HashMap::new()
+                });
+                let broken = links
+                    .iter()
+                    .filter_map(|link| {
+                        let check = checks.remove(&model::normalized_id(&link.short))?;
+                        check.broken.then(|| (link.short.clone(), check))
+                    })
+                    .collect::<HashMap<_, _>>();
                 match self.handlebars.render(
                     "all",
-                    &serde_json::json!({"links": links, "go": self.host, "parent": PARENT_PARTIAL}),
+                    &serde_json::json!({"links": links, "broken": broken, "go": self.host, "parent": PARENT_PARTIAL}),
                 ) {
                     Ok(response) => html(response),
                     Err(e) => {
@@ -225,6 +935,15 @@ impl Renderer {
             tracing::error!("Invalid xsrf token: {e}");
             return redirect("/");
         }
+        let passthrough = request.passthrough.clone().unwrap_or_default();
+        let request = match self.prepare_target(&request.target, &passthrough) {
+            Ok(target) => CreateUpdateRequest { target, ..request },
+            Err(errors) => {
+                return self
+                    .render_home(&request.short, &request.target, &errors, Popularity::default())
+                    .await;
+            }
+        };
 
         let link = request.clone().into();
         let short = request.short.as_str();
@@ -233,6 +952,7 @@ impl Renderer {
             Err(_) => {
                 let reply = match self.db.link.save(&link).await {
                     Ok(_) => {
+                        self.invalidate(short);
                         match self.handlebars.render(
                             "success",
                             &serde_json::json!({"go": self.host, "parent": PARENT_PARTIAL, "link": link, "XSRF": self.xsrf()}),
@@ -243,7 +963,7 @@ impl Renderer {
                                 redirect("/")
                             }
                         }
-                    },
+                    }
                     Err(e) => {
                         tracing::error!("{e}");
                         redirect("/")
@@ -258,6 +978,20 @@ impl Renderer {
 
     // for answering POST requests
     pub async fn new_link(&self, request: CreateUpdateRequest) -> Result<Box<dyn warp::Reply>, Infallible> {
+        let passthrough = request.passthrough.clone().unwrap_or_default();
+        let request = match self.prepare_target(&request.target, &passthrough) {
+            Ok(target) => CreateUpdateRequest { target, ..request },
+            Err(errors) => {
+                return json(
+                    model::ErrorResponse {
+                        error: "invalid destination link".to_string(),
+                        details: errors,
+                    },
+                    warp::http::StatusCode::BAD_REQUEST,
+                );
+            }
+        };
+
         let link: model::Link = request.clone().into();
         let short = request.short.as_str();
         match self.db.link.load(short).await {
@@ -267,7 +1001,10 @@ impl Renderer {
             ),
             Err(_) => {
                 let reply = match self.db.link.save(&link).await {
-                    Ok(_) => json(link, warp::http::StatusCode::CREATED),
+                    Ok(_) => {
+                        self.invalidate(short);
+                        json(link, warp::http::StatusCode::CREATED)
+                    }
                     Err(e) => {
                         tracing::error!("new_link 1: {e}");
                         response(&e.to_string(), warp::http::StatusCode::INTERNAL_SERVER_ERROR)
@@ -281,6 +1018,56 @@ impl Renderer {
         }
     }
 
+    // shows the edit form of a link again, as it was filled in, with why it was not saved
+    fn render_rejected_update(
+        &self,
+        link: &model::Link,
+        errors: &[String],
+    ) -> Result<Box<dyn warp::Reply>, Infallible> {
+        match self.handlebars.render(
+            "detail",
+            &serde_json::json!({"go": self.host, "parent": PARENT_PARTIAL, "link": link, "XSRF": self.xsrf(), "errors": errors}),
+        ) {
+            Ok(response) => html_with_status(response, warp::http::StatusCode::BAD_REQUEST),
+            Err(e) => {
+                tracing::error!("{e}");
+                redirect(&format!("/.detail/{}", link.short))
+            }
+        }
+    }
+
+    /// Shows the create form again when a field of it could not be read, with why.
+    pub async fn reject_create(
+        &self,
+        short: &str,
+        long: &str,
+        error: String,
+    ) -> Result<Box<dyn warp::Reply>, Infallible> {
+        self.render_home(short, long, &[error], Popularity::default()).await
+    }
+
+    /// Shows the edit form of a link again when a field of it could not be read, with why.
+    pub async fn reject_update(
+        &self,
+        short: &str,
+        long: &str,
+        error: String,
+    ) -> Result<Box<dyn warp::Reply>, Infallible> {
+        match self.db.link.load(short).await {
+            Ok(link) => self.render_rejected_update(
+                &model::Link {
+                    long: long.to_string(),
+                    ..link
+                },
+                &[error],
+            ),
+            Err(e) => {
+                tracing::error!("{e}");
+                redirect_with_status("/", warp::http::StatusCode::NOT_FOUND)
+            }
+        }
+    }
+
     pub async fn update(&self, request: CreateUpdateRequest, xsrf: &str) -> Result<Box<dyn warp::Reply>, Infallible> {
         if let Err(e) = self
             .csrf_key
@@ -293,14 +1080,38 @@ impl Renderer {
         let short = request.short.as_str();
         match self.db.link.load(short).await {
             Ok(link) => {
+                let passthrough = request.passthrough.clone().unwrap_or(link.passthrough.clone());
+                let target = match self.prepare_target(&request.target, &passthrough) {
+                    Ok(target) => target,
+                    Err(errors) => {
+                        let rejected_link = model::Link {
+                            long: request.target.clone(),
+                            status: request.status.unwrap_or(link.status),
+                            max_age: request.max_age.unwrap_or(link.max_age),
+                            passthrough,
+                            dont_track: request.dont_track.unwrap_or(link.dont_track),
+                            ..link
+                        };
+                        return self.render_rejected_update(&rejected_link, &errors);
+                    }
+                };
                 let updated_link: model::Link = model::Link {
                     short: short.to_string(),
-                    long: request.target,
+                    long: target,
                     created: link.created,
                     updated: chrono::Utc::now(),
+                    status: request.status.unwrap_or(link.status),
+                    max_age: request.max_age.unwrap_or(link.max_age),
+                    passthrough,
+                    dont_track: request.dont_track.unwrap_or(link.dont_track),
                 };
                 match self.db.link.save(&updated_link).await {
                     Ok(()) => {
+                        self.invalidate(short);
+                        // the last check was of the old destination link
+                        if updated_link.long != link.long {
+                            let _ = self.db.checks.delete(short).await;
+                        }
                         match self.handlebars.render(
                             "success",
                             &serde_json::json!({"go": self.host, "parent": PARENT_PARTIAL, "link": link, "XSRF": self.xsrf()}),
@@ -311,7 +1122,7 @@ impl Renderer {
                                 redirect(&format!("/.detail/{}", short))
                             }
                         }
-                    },
+                    }
                     Err(e) => {
                         tracing::error!("{e}");
                         redirect(&format!("/.detail/{}", short))
@@ -338,30 +1149,25 @@ impl Renderer {
         }
 
         match self.db.link.load(short).await {
-            Ok(to_delete) => {
-                let reply = match self.db.link.delete(short).await {
-                    Ok(()) => {
-                        tracing::info!("Successfully deleted, rendering delete template");
-                        match self.handlebars.render(
-                            "delete",
-                            &serde_json::json!({"go": self.host, "parent": PARENT_PARTIAL, "link": to_delete, "XSRF": self.xsrf()}),
-                        ) {
-                            Ok(response) => html(response),
-                            Err(e) => {
-                                tracing::error!("{e}");
-                                redirect(&format!("/.detail/{}", short))
-                            }
+            Ok(to_delete) => match self.delete_link(short).await {
+                Ok(()) => {
+                    tracing::info!("Successfully deleted, rendering delete template");
+                    match self.handlebars.render(
+                        "delete",
+                        &serde_json::json!({"go": self.host, "parent": PARENT_PARTIAL, "link": to_delete, "XSRF": self.xsrf()}),
+                    ) {
+                        Ok(response) => html(response),
+                        Err(e) => {
+                            tracing::error!("{e}");
+                            redirect(&format!("/.detail/{}", short))
                         }
                     }
-                    Err(e) => {
-                        tracing::error!("{e}");
-                        redirect(&format!("/.detail/{}", short))
-                    }
-                };
-                // delete the click stats for short
-                let _ = self.db.stats.delete(short).await;
-                reply
-            }
+                }
+                Err(e) => {
+                    tracing::error!("{e}");
+                    redirect(&format!("/.detail/{}", short))
+                }
+            },
             Err(e) => {
                 tracing::error!("{e}");
                 redirect_with_status("/", warp::http::StatusCode::NOT_FOUND)
@@ -405,38 +1211,159 @@ impl Renderer {
         }
     }
 
+    // for answering POST requests with links exported by gohome or golink, one JSON object per line
+    pub async fn import(&self, body: &[u8]) -> Result<Box<dyn warp::Reply>, Infallible> {
+        let Ok(body) = std::str::from_utf8(body) else {
+            return json(
+                model::ErrorResponse {
+                    error: "import must be UTF-8 encoded".to_string(),
+                    details: Vec::new(),
+                },
+                warp::http::StatusCode::BAD_REQUEST,
+            );
+        };
+
+        let mut result = model::ImportResult::default();
+        for (number, line) in body.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let link: model::Link = match serde_json::from_str::<golink::ExportedLink>(line) {
+                Ok(link) => link.into(),
+                Err(e) => {
+                    result.errors.push(format!("line {}: {}", number + 1, e));
+                    continue;
+                }
+            };
+            let link = match self.prepare_target(&link.long, &link.passthrough) {
+                Ok(long) => model::Link { long, ..link },
+                Err(errors) => {
+                    result
+                        .errors
+                        .push(format!("line {}: go/{}: {}", number + 1, link.short, errors.join("; ")));
+                    continue;
+                }
+            };
+            // existing links keep their click stats
+            let existing = self.db.link.load(&link.short).await.is_ok();
+            match self.db.link.save(&link).await {
+                Ok(()) => {
+                    self.invalidate(&link.short);
+                    if !existing {
+                        let _ = self.db.stats.save(&link.short).await;
+                    }
+                    result.imported += 1;
+                }
+                Err(e) => {
+                    tracing::error!("import: {e}");
+                    result
+                        .errors
+                        .push(format!("line {}: go/{}: {}", number + 1, link.short, e));
+                }
+            }
+        }
+        json(result, warp::http::StatusCode::OK)
+    }
+
     pub async fn get(
         &self,
         short: &str,
         full_path: &str,
         query_params: HashMap<String, String>,
+        request: &RequestContext,
     ) -> Result<Box<dyn warp::Reply>, Infallible> {
-        let reply = if let Ok(link) = self.db.link.load(short).await {
-            let path = Renderer::path_remainder(full_path, short);
-            self.expand_link(&path, query_params, &link.long).map_or_else(
+        let link = self.find_link(short).await;
+        let path = Renderer::path_remainder(full_path, short);
+        let accepted = link
+            .as_ref()
+            .and_then(|link| link.passthrough.path(&path).map(|path| (link, path)));
+        let reply = if let Some((link, path)) = accepted {
+            let context = TemplateContext::new(short, path, query_params).with_request(request);
+            self.expand_saved(&context, link).map_or_else(
                 |e| {
                     tracing::error!("{e}");
+                    metrics::observe_redirect(RedirectOutcome::TemplateError);
                     redirect_with_status("/", warp::http::StatusCode::INTERNAL_SERVER_ERROR)
                 },
-                |location| redirect_with_status(location.as_ref(), warp::http::StatusCode::PERMANENT_REDIRECT),
+                |location| {
+                    metrics::observe_redirect(RedirectOutcome::Hit);
+                    redirect_for_link(location.as_ref(), link)
+                },
             )
         } else {
+            // the link does not exist, or does not accept a path
+            metrics::observe_redirect(RedirectOutcome::Miss);
             redirect_with_status("/", warp::http::StatusCode::NOT_FOUND)
         };
-        // incr click stats for short
-        let _ = self.db.stats.incr(short).await;
+        // click stats are counted in memory and written by flush_clicks
+        if !self.track_clicks || link.as_ref().is_some_and(|link| link.dont_track) {
+            return reply;
+        }
+        match (&link, accepted, self.not_a_click(request)) {
+            (None, _, None) => self.clicks.record_miss(short),
+            (Some(_), Some(_), None) => self.clicks.record(short, self.click_source(request)),
+            (Some(_), Some(_), Some(reason)) => {
+                tracing::debug!("not counting a click on {short}: {reason}");
+                self.clicks.record_skipped(short)
+            }
+            _ => {}
+        }
         reply
     }
 
-    pub async fn json_detail(&self, short: &str) -> Result<Box<dyn warp::Reply>, Infallible> {
+    // loads a link through the link cache, caching links that do not exist as well
+    async fn find_link(&self, short: &str) -> Option<model::Link> {
+        if let Some(link) = self.link_cache.get(short) {
+            return link;
+        }
+        let generation = self.link_cache.generation();
+        match self.db.link.find(short).await {
+            Ok(link) => {
+                self.link_cache.insert(short, link.clone(), generation);
+                link
+            }
+            Err(e) => {
+                tracing::error!("{e}");
+                None
+            }
+        }
+    }
+
+    pub async fn diagnostics(&self) -> Result<Box<dyn warp::Reply>, Infallible> {
+        json(
+            model::Diagnostics {
+                link_cache: self.link_cache.stats(),
+                templates: self.templates.stats(),
+            },
+            warp::http::StatusCode::OK,
+        )
+    }
+
+    /// Serves the metrics for Prometheus to scrape.
+    pub async fn metrics(&self) -> Result<Box<dyn warp::Reply>, Infallible> {
+        let links = self.db.link.count().await.inspect_err(|e| tracing::error!("{e}")).ok();
+        Ok(Box::new(warp::reply::with_header(
+            metrics::encode(links),
+            warp::http::header::CONTENT_TYPE,
+            metrics::CONTENT_TYPE,
+        )))
+    }
+
+    pub async fn json_detail(&self, short: &str, days: Option<u32>) -> Result<Box<dyn warp::Reply>, Infallible> {
         if let Ok(link) = self.db.link.load(short).await {
             if let Ok(click_stats) = self.db.stats.load(&link.short).await {
+                let breakdown = self.breakdown(&link.short, days).await;
+                // click stats are not shown when clicks are not being recorded
+                let click_stats = click_stats.filter(|_| self.track_clicks);
                 let details = model::LinkDetails {
                     short: link.short,
                     long: link.long,
                     created: link.created,
                     updated: link.updated,
+                    status: link.status,
+                    max_age: link.max_age,
+                    passthrough: link.passthrough,
+                    dont_track: link.dont_track,
+                    skipped: click_stats.as_ref().map(|s| s.skipped),
                     clicks: click_stats.map(|s| s.clicks.unwrap_or(0)),
+                    breakdown,
                 };
                 Ok(Box::new(warp::reply::json(&details)))
             } else {
@@ -453,6 +1380,76 @@ impl Renderer {
         }
     }
 
+    /// Resolves a link path such as "search/foo%20bar?hl=en" the same way a click
+    /// would, without redirecting or recording the click. An unsaved destination
+    /// link can be given to preview it in place of the stored one.
+    pub async fn preview(
+        &self,
+        link_path: &str,
+        long: Option<String>,
+        request: &RequestContext,
+    ) -> Result<Box<dyn warp::Reply>, Infallible> {
+        let (full_path, query) = match link_path.trim_start_matches('/').split_once('?') {
+            Some((path, query)) => (format!("/{path}"), query),
+            None => (format!("/{}", link_path.trim_start_matches('/')), ""),
+        };
+        let short = full_path[1..].split('/').next().unwrap_or_default().to_string();
+        // an unsaved destination link is previewed with the path and query handling of the saved link, if any
+        let saved = self.db.link.find(&short).await.unwrap_or_else(|e| {
+            tracing::error!("{e}");
+            None
+        });
+        let passthrough = saved.as_ref().map(|link| link.passthrough.clone()).unwrap_or_default();
+        let (long, compiled) = match (long, saved) {
+            (Some(long), _) => {
+                let compiled = self.templates.compile(&long).map(Arc::new);
+                (long, compiled)
+            }
+            (None, Some(link)) => {
+                let compiled = self.templates.get(&link.short, &link.long);
+                (link.long, compiled)
+            }
+            (None, None) => {
+                return json(
+                    model::ErrorResponse {
+                        error: format!("link {short} not found"),
+                        details: Vec::new(),
+                    },
+                    warp::http::StatusCode::NOT_FOUND,
+                );
+            }
+        };
+
+        let query_params: HashMap<String, String> =
+            url::form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+        let path = Renderer::path_remainder(&full_path, &short);
+        let accepted = passthrough.path(&path);
+        let context = TemplateContext::new(&short, accepted.unwrap_or(&path), query_params).with_request(request);
+        let mut preview = model::Preview {
+            short,
+            path: context.path.clone(),
+            long: long.clone(),
+            rendered: None,
+            location: None,
+            error: None,
+        };
+        if accepted.is_none() {
+            preview.error = Some(format!("go/{} does not accept a path", preview.short));
+            return json(preview, warp::http::StatusCode::OK);
+        }
+        match compiled.and_then(|compiled| Ok((self.render_link(&context, &compiled)?, compiled))) {
+            Ok((rendered, compiled)) => {
+                preview.rendered = Some(rendered.clone());
+                match Self::to_url(rendered, &context, &compiled, &passthrough) {
+                    Ok(location) => preview.location = Some(location.to_string()),
+                    Err(e) => preview.error = Some(e.to_string()),
+                }
+            }
+            Err(e) => preview.error = Some(e.to_string()),
+        }
+        json(preview, warp::http::StatusCode::OK)
+    }
+
     pub async fn bad_request(&self) -> Result<Box<dyn warp::Reply>, Infallible> {
         Ok(Box::new(warp::http::StatusCode::BAD_REQUEST))
     }
@@ -474,35 +1471,113 @@ impl Renderer {
         }
     }
 
+    #[cfg(test)]
     pub(crate) fn expand_link(
         &self,
         path: &str,
         query_params: HashMap<String, String>,
         long: &str,
-    ) -> Result<Url, Box<dyn std::error::Error>> {
-        // default behavior is to append remaining path to long URL
-        let template = Self::with_path(path, long);
-        let expanded = self
-            .handlebars
-            .render_template(&template, &serde_json::json!({"path": path}))?;
+    ) -> Result<Url, ExpandError> {
+        self.expand(&TemplateContext::new("", path, query_params), long)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn expand(&self, context: &TemplateContext, long: &str) -> Result<Url, ExpandError> {
+        self.expand_compiled(context, &self.templates.compile(long)?, &model::Passthrough::default())
+    }
+
+    // expands a saved link, reusing its compiled template
+    fn expand_saved(&self, context: &TemplateContext, link: &model::Link) -> Result<Url, ExpandError> {
+        let compiled = self.templates.get(&link.short, &link.long)?;
+        self.expand_compiled(context, &compiled, &link.passthrough)
+    }
+
+    fn expand_compiled(
+        &self,
+        context: &TemplateContext,
+        compiled: &CompiledLink,
+        passthrough: &model::Passthrough,
+    ) -> Result<Url, ExpandError> {
+        let rendered = self.render_link(context, compiled)?;
+        Self::to_url(rendered, context, compiled, passthrough)
+    }
+
+    // renders the destination link, before query parameters are appended
+    fn render_link(&self, context: &TemplateContext, compiled: &CompiledLink) -> Result<String, ExpandError> {
+        let mut output = BoundedOutput::new(MAX_LINK_LENGTH, MAX_RENDER_TIME);
+        let result = match compiled {
+            // default behavior is to append remaining path to long URL
+            CompiledLink::Plain(long) if !context.path.is_empty() => {
+                let separator = if long.ends_with('/') { "" } else { "/" };
+                [long.as_str(), separator, &context.path]
+                    .into_iter()
+                    .try_for_each(|segment| output.write(segment))
+                    .map_err(RenderError::from)
+            }
+            CompiledLink::Plain(long) => output.write(long).map_err(RenderError::from),
+            CompiledLink::Template { template, .. } => template.render(
+                &self.links,
+                &Context::wraps(context)?,
+                &mut RenderContext::new(None),
+                &mut output,
+            ),
+        };
+        output.finish(result)
+    }
+
+    fn to_url(
+        rendered: String,
+        context: &TemplateContext,
+        compiled: &CompiledLink,
+        passthrough: &model::Passthrough,
+    ) -> Result<Url, ExpandError> {
+        // query parameters read by the template are not passed through again
+        let query_params: Vec<(&String, &String)> = context
+            .query
+            .iter()
+            .filter(|(key, _)| !compiled.consumes(key) && passthrough.passes(key))
+            .collect();
         let u = if !query_params.is_empty() {
-            Url::parse_with_params(&expanded, query_params.iter()).map_err(Box::new)?
+            Url::parse_with_params(&rendered, query_params)
         } else {
-            Url::parse(&expanded).map_err(Box::new)?
+            Url::parse(&rendered)
         };
-        Ok(u)
+        u.map_err(|error| ExpandError::Url { rendered, error })
     }
 
-    fn with_path(path: &str, long: &str) -> String {
-        if !long.contains("{{") && !path.is_empty() {
-            if long.ends_with("/") {
-                format!("{}{}", long, "{{path}}")
-            } else {
-                format!("{}/{}", long, "{{path}}")
-            }
-        } else {
-            long.to_string()
+    /// Translates a destination link written with golink's Go template syntax, then
+    /// validates it, returning the link to store or a description of each problem found.
+    pub(crate) fn prepare_target(&self, long: &str, passthrough: &model::Passthrough) -> Result<String, Vec<String>> {
+        let target = golink::translate(long).map_err(|e| vec![e.to_string()])?;
+        self.validate_target(&target, passthrough)?;
+        Ok(target)
+    }
+
+    /// Checks that a destination link compiles and expands to a valid URL for each
+    /// sample path the link accepts, handling the path and query as the link does,
+    /// returning a description of each problem found.
+    pub(crate) fn validate_target(&self, long: &str, passthrough: &model::Passthrough) -> Result<(), Vec<String>> {
+        if long.trim().is_empty() {
+            return Err(vec!["destination link must not be empty".to_string()]);
         }
+        let compiled = self.templates.compile(long).map_err(|e| vec![e.to_string()])?;
+
+        let errors: Vec<String> = VALIDATION_PATHS
+            .iter()
+            .filter_map(|path| {
+                // links that ignore the path are expanded without it, and those that reject it are not followed
+                let accepted = passthrough.path(path)?;
+                let context = TemplateContext::new("", accepted, HashMap::new()).with_sample_request();
+                self.expand_compiled(&context, &compiled, passthrough).err().map(|e| {
+                    if path.is_empty() {
+                        format!("with no path: {e}")
+                    } else {
+                        format!("with path \"{path}\": {e}")
+                    }
+                })
+            })
+            .collect();
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
     }
 }
 
@@ -510,6 +1585,7 @@ impl Renderer {
 mod tests {
     use std::any::Any;
 
+    use chrono::Utc;
     use url::Url;
 
     use super::*;
@@ -838,11 +1914,72 @@ mod tests {
     #[test]
     fn test_undefined_field() {
         let renderer = Renderer::empty();
+        let res = renderer.expand_link("bar", HashMap::new(), "http://host.com/{{ bar }}");
+        assert!(matches!(res, Err(ExpandError::Render(_))));
+    }
+
+    #[test]
+    fn test_undefined_query_param() {
+        let renderer = Renderer::empty();
+        assert!(
+            renderer
+                .expand_link("", HashMap::new(), "http://host.com/{{query.q}}")
+                .is_err()
+        );
         let res = renderer
-            .expand_link("bar", HashMap::new(), "http://host.com/{{ bar }}")
-            .unwrap()
-            .to_string();
-        assert_eq!(res, "http://host.com/");
+            .expand_link("", HashMap::new(), "http://host.com/{{#if query.q}}{{query.q}}{{/if}}")
+            .unwrap();
+        assert_eq!(res.as_str(), "http://host.com/");
+        let res = renderer
+            .expand_link("", HashMap::new(), r#"http://host.com/{{default query.q "none"}}"#)
+            .unwrap();
+        assert_eq!(res.as_str(), "http://host.com/none");
+    }
+
+    #[test]
+    fn test_output_limit() {
+        let renderer = Renderer::empty();
+        let res = renderer.expand_link("bar", HashMap::new(), "http://host.com/{{padleft path 8190 pad=\"x\"}}");
+        assert!(matches!(res, Err(ExpandError::Sandbox(_))));
+        let res = renderer.expand_link("bar", HashMap::new(), "http://host.com/{{padleft path 1000 pad=\"x\"}}");
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_render_time_limit() {
+        let renderer = Renderer::empty();
+        // loops that write nothing still run out of time
+        let path = vec!["a"; 150].join("/");
+        let res = renderer.expand_link(
+            &path,
+            HashMap::new(),
+            "http://host.com/{{#each segments}}{{#each ../segments}}{{#each ../../segments}}{{/each}}{{/each}}{{/each}}",
+        );
+        assert!(matches!(res, Err(ExpandError::Sandbox(_))));
+        let res = renderer.expand_link(
+            "a/b",
+            HashMap::new(),
+            "http://host.com/{{#each segments}}{{#if @first}}{{this}}{{else}}-{{this}}{{/if}}{{/each}}",
+        );
+        assert_eq!(res.unwrap().as_str(), "http://host.com/a-b");
+    }
+
+    #[test]
+    fn test_validate_target_sandbox() {
+        let renderer = Renderer::empty();
+        let errors = renderer
+            .validate_target(
+                "{{#*inline \"u\"}}http://host.com/{{/inline}}{{> u}}",
+                &model::Passthrough::default(),
+            )
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("not allowed"));
+        assert!(
+            renderer
+                .validate_target("http://host.com/{{log path}}", &model::Passthrough::default())
+                .is_err()
+        );
     }
 
     #[test]
@@ -855,6 +1992,146 @@ mod tests {
         assert_eq!(res, "http://host.com/bar");
     }
 
+    #[test]
+    fn test_consumed_query_params() {
+        let mut query_params = HashMap::new();
+        query_params.insert("q".to_string(), "pangolins".to_string());
+        query_params.insert("utm_source".to_string(), "slack".to_string());
+
+        let renderer = Renderer::empty();
+        let res = renderer
+            .expand_link(
+                "",
+                query_params,
+                "https://www.google.com/search?q={{query_escape query.q}}",
+            )
+            .unwrap();
+        let pairs: Vec<(String, String)> = res.query_pairs().into_owned().collect();
+        assert_eq!(pairs.len(), 2);
+        assert!(pairs.contains(&("q".to_string(), "pangolins".to_string())));
+        assert!(pairs.contains(&("utm_source".to_string(), "slack".to_string())));
+    }
+
+    #[test]
+    fn test_request_context() {
+        let mut headers = warp::http::HeaderMap::new();
+        headers.insert("Accept-Language", "en-US".parse().unwrap());
+        let request = RequestContext {
+            host: Some("go".to_string()),
+            client_ip: Some(std::net::IpAddr::from([192, 168, 1, 20])),
+            headers,
+            user: Some("amelie".to_string()),
+            ..Default::default()
+        };
+        let context = TemplateContext::new("who", "/amelie/profile", HashMap::new()).with_request(&request);
+
+        let renderer = Renderer::empty();
+        let res = renderer
+            .expand(
+                &context,
+                "http://{{host}}/{{short}}/{{lookup segments 1}}/{{user}}/{{client_ip}}/{{headers.accept-language}}",
+            )
+            .unwrap()
+            .to_string();
+        assert_eq!(res, "http://go/who/profile/amelie/192.168.1.20/en-US");
+    }
+
+    #[test]
+    fn test_remainder_from_full_path() {
+        let renderer = Renderer::empty();
+        let path = Renderer::path_remainder("/who/amelie", "who");
+        let res = renderer
+            .expand(&TemplateContext::new("who", &path, HashMap::new()), "http://directory/")
+            .unwrap()
+            .to_string();
+        assert_eq!(res, "http://directory/amelie");
+    }
+
+    #[test]
+    fn test_validate_target() {
+        let renderer = Renderer::empty();
+        assert!(
+            renderer
+                .validate_target("https://www.google.com/", &model::Passthrough::default())
+                .is_ok()
+        );
+        assert!(
+            renderer
+                .validate_target(
+                    "https://www.google.com/{{#if path}}search?q={{query_escape path}}{{/if}}",
+                    &model::Passthrough::default()
+                )
+                .is_ok()
+        );
+        // links may read the request, though none is made to check them with
+        assert!(
+            renderer
+                .validate_target(
+                    "https://{{host}}/{{user}}/{{client_ip}}?hl={{headers.accept-language}}&r={{headers.referer}}",
+                    &model::Passthrough::default()
+                )
+                .is_ok()
+        );
+        assert!(
+            renderer
+                .validate_target("https://example.com/{{headers.cookie}}", &model::Passthrough::default())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_target_broken_template() {
+        let renderer = Renderer::empty();
+        let errors = renderer
+            .validate_target(
+                "https://www.google.com/{{#if path}}search?q={{path}}",
+                &model::Passthrough::default(),
+            )
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("invalid template:"));
+    }
+
+    #[test]
+    fn test_validate_target_not_url() {
+        let renderer = Renderer::empty();
+        let errors = renderer
+            .validate_target("www.google.com", &model::Passthrough::default())
+            .unwrap_err();
+        assert_eq!(errors.len(), VALIDATION_PATHS.len());
+        assert!(errors[0].starts_with(r#"with no path: "www.google.com" is not a valid URL"#));
+        assert!(errors[1].starts_with(r#"with path "foo": "www.google.com/foo" is not a valid URL"#));
+    }
+
+    #[test]
+    fn test_validate_target_not_url_with_path() {
+        let renderer = Renderer::empty();
+        let errors = renderer
+            .validate_target(
+                "{{#if path}}https://{{path}}{{else}}https://example.com{{/if}}",
+                &model::Passthrough::default(),
+            )
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with(r#"with path "foo bar": "https://foo bar" is not a valid URL"#));
+
+        // links that ignore or reject the path are never expanded with one
+        for path_mode in [model::PathMode::Ignore, model::PathMode::Reject] {
+            let passthrough = model::Passthrough {
+                path_mode,
+                ..Default::default()
+            };
+            assert!(
+                renderer
+                    .validate_target(
+                        "{{#if path}}https://{{path}}{{else}}https://example.com{{/if}}",
+                        &passthrough
+                    )
+                    .is_ok()
+            );
+        }
+    }
+
     #[test]
     fn test_path_remainder_1() {
         let original_path = "/nyt/sports/article";
@@ -886,4 +2163,787 @@ mod tests {
         let result = Renderer::path_remainder(original_path, slug_to_remove);
         assert_eq!(result, "");
     }
+
+    #[test]
+    fn test_prepare_golink_target() {
+        let renderer = Renderer::empty();
+        let target = renderer
+            .prepare_target(
+                "https://www.google.com/{{if .Path}}search?q={{QueryEscape .Path}}{{end}}",
+                &model::Passthrough::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            target,
+            "https://www.google.com/{{#if path}}search?q={{query_escape path}}{{/if}}"
+        );
+        let res = renderer.expand_link("pangolins", HashMap::new(), &target).unwrap();
+        assert_eq!(res.as_str(), "https://www.google.com/search?q=pangolins");
+        assert!(
+            renderer
+                .prepare_target("http://host/{{if .Path}}", &model::Passthrough::default())
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import() {
+        let renderer = Renderer::empty();
+        let body = [
+            r#"{"short":"nyt","long":"http://www.nytimes.com","created":"2024-01-01T00:00:00Z","updated":"2024-01-02T00:00:00Z"}"#,
+            r#"{"Short":"Search","Long":"https://www.google.com/{{if .Path}}search?q={{QueryEscape .Path}}{{end}}","Created":"2024-01-01T00:00:00Z","LastEdit":"2024-01-03T00:00:00Z","Owner":"amelie@example.com"}"#,
+            "",
+            r#"{"short":"broken"}"#,
+            r#"{"short":"broken","long":"{{#if path}}","created":"2024-01-01T00:00:00Z","updated":"2024-01-01T00:00:00Z"}"#,
+        ]
+        .join("\n");
+        let reply = warp::Reply::into_response(renderer.import(body.as_bytes()).await.unwrap());
+        assert_eq!(reply.status(), warp::http::StatusCode::OK);
+
+        let nyt = renderer.db.link.load("nyt").await.unwrap();
+        assert_eq!(nyt.updated.to_rfc3339(), "2024-01-02T00:00:00+00:00");
+        let search = renderer.db.link.load("search").await.unwrap();
+        assert_eq!(search.short, "Search");
+        assert_eq!(
+            search.long,
+            "https://www.google.com/{{#if path}}search?q={{query_escape path}}{{/if}}"
+        );
+        assert_eq!(search.updated.to_rfc3339(), "2024-01-03T00:00:00+00:00");
+        assert!(renderer.db.link.load("broken").await.is_err());
+        assert!(renderer.db.stats.load("search").await.unwrap().is_some());
+
+        // importing a link again keeps its single stats row
+        renderer.db.stats.incr("nyt").await.unwrap();
+        let reply = warp::Reply::into_response(renderer.import(body.as_bytes()).await.unwrap());
+        assert_eq!(reply.status(), warp::http::StatusCode::OK);
+        let stats = renderer.db.stats.load("nyt").await.unwrap().unwrap();
+        assert_eq!(stats.clicks, Some(1));
+        let reply = warp::Reply::into_response(renderer.json_detail("nyt", None).await.unwrap());
+        assert_eq!(reply.status(), warp::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_hot_redirects_do_not_compile() {
+        let renderer = Renderer::empty();
+        let request = RequestContext::default();
+        let search = model::Link {
+            short: "search".to_string(),
+            long: "https://www.google.com/{{#if path}}search?q={{query_escape path}}{{/if}}".to_string(),
+            created: Utc::now(),
+            updated: Utc::now(),
+            status: model::RedirectStatus::default(),
+            max_age: None,
+            passthrough: model::Passthrough::default(),
+            dont_track: false,
+        };
+        let nyt = model::Link {
+            short: "nyt".to_string(),
+            long: "http://www.nytimes.com".to_string(),
+            ..search.clone()
+        };
+        renderer.db.link.save(&search).await.unwrap();
+        renderer.db.link.save(&nyt).await.unwrap();
+
+        for path in [
+            "/search",
+            "/search/pangolins",
+            "/search/armadillos",
+            "/nyt",
+            "/nyt/section/arts",
+        ] {
+            let short = path[1..].split('/').next().unwrap();
+            let reply = renderer.get(short, path, HashMap::new(), &request).await.unwrap();
+            assert_eq!(
+                warp::Reply::into_response(reply).status(),
+                warp::http::StatusCode::FOUND
+            );
+        }
+        assert_eq!(renderer.templates.compiles(), 1); // plain links never invoke handlebars
+
+        // an updated link is compiled again
+        renderer.templates.invalidate("search");
+        renderer
+            .get("search", "/search", HashMap::new(), &request)
+            .await
+            .unwrap();
+        assert_eq!(renderer.templates.compiles(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_link_cache_coherence() {
+        let renderer = Renderer::empty();
+        let request = RequestContext::default();
+        let status = |reply: Box<dyn warp::Reply>| warp::Reply::into_response(reply).status();
+
+        // a miss is cached, then forgotten once the link is created
+        let reply = renderer.get("nyt", "/nyt", HashMap::new(), &request).await.unwrap();
+        assert_eq!(status(reply), warp::http::StatusCode::NOT_FOUND);
+        assert!(renderer.link_cache.get("nyt").is_some_and(|link| link.is_none()));
+        let create = CreateUpdateRequest {
+            short: "nyt".to_string(),
+            target: "http://www.nytimes.com".to_string(),
+            status: None,
+            max_age: None,
+            passthrough: None,
+            dont_track: None,
+        };
+        renderer.new_link(create).await.unwrap();
+        let reply = renderer.get("nyt", "/nyt", HashMap::new(), &request).await.unwrap();
+        assert_eq!(status(reply), warp::http::StatusCode::FOUND);
+
+        // hot redirects are answered from the cache
+        let hits = renderer.link_cache.stats().hits;
+        renderer.get("nyt", "/nyt", HashMap::new(), &request).await.unwrap();
+        assert_eq!(renderer.link_cache.stats().hits, hits + 1);
+
+        // a changed link is not served from the cache
+        renderer
+            .api_patch("nyt", br#"{"long": "http://www.nytimes.com/section/arts"}"#)
+            .await
+            .unwrap();
+        let reply = renderer.get("nyt", "/nyt", HashMap::new(), &request).await.unwrap();
+        let response = warp::Reply::into_response(reply);
+        assert_eq!(response.headers()["Location"], "http://www.nytimes.com/section/arts");
+
+        // nor is a deleted one
+        renderer.delete("nyt", &renderer.xsrf()).await.unwrap();
+        let reply = renderer.get("nyt", "/nyt", HashMap::new(), &request).await.unwrap();
+        assert_eq!(status(reply), warp::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_status() {
+        let renderer = Renderer::empty();
+        let request = RequestContext::default();
+        let redirect = |reply: Box<dyn warp::Reply>| {
+            let response = warp::Reply::into_response(reply);
+            let cache_control = response
+                .headers()
+                .get("Cache-Control")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string();
+            (response.status(), cache_control)
+        };
+
+        let create = CreateUpdateRequest {
+            short: "oncall".to_string(),
+            target: "http://pagerduty.example".to_string(),
+            status: None,
+            max_age: None,
+            passthrough: None,
+            dont_track: None,
+        };
+        renderer.new_link(create.clone()).await.unwrap();
+        let reply = renderer
+            .get("oncall", "/oncall", HashMap::new(), &request)
+            .await
+            .unwrap();
+        assert_eq!(redirect(reply), (warp::http::StatusCode::FOUND, "no-cache".to_string()));
+
+        let permanent = model::Link {
+            status: model::RedirectStatus::MovedPermanently,
+            max_age: Some(86400),
+            ..create.into()
+        };
+        renderer.db.link.save(&permanent).await.unwrap();
+        renderer.invalidate("oncall");
+        let reply = renderer
+            .get("oncall", "/oncall", HashMap::new(), &request)
+            .await
+            .unwrap();
+        assert_eq!(
+            redirect(reply),
+            (warp::http::StatusCode::MOVED_PERMANENTLY, "max-age=86400".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_passthrough() {
+        use model::{PathMode, QueryMode};
+
+        let renderer = Renderer::empty();
+        let request = RequestContext::default();
+        let location = |reply: Box<dyn warp::Reply>| {
+            let response = warp::Reply::into_response(reply);
+            let location = response.headers().get("Location");
+            (response.status(), location.map(|l| l.to_str().unwrap().to_string()))
+        };
+        let query = HashMap::from([
+            ("q".to_string(), "pangolins".to_string()),
+            ("utm_source".to_string(), "mail".to_string()),
+        ]);
+        let link = |short: &str, long: &str, path_mode, query_mode| CreateUpdateRequest {
+            short: short.to_string(),
+            target: long.to_string(),
+            status: None,
+            max_age: None,
+            passthrough: Some(model::Passthrough {
+                path_mode,
+                query_mode,
+                query_allow: vec!["q".to_string()],
+            }),
+            dont_track: None,
+        };
+        for request in [
+            link("vpn", "http://vpn.example/connect", PathMode::Ignore, QueryMode::Drop),
+            link("wiki", "http://wiki.example", PathMode::Reject, QueryMode::Allow),
+        ] {
+            renderer.new_link(request).await.unwrap();
+        }
+
+        let reply = renderer
+            .get("vpn", "/vpn/extra", query.clone(), &request)
+            .await
+            .unwrap();
+        assert_eq!(
+            location(reply),
+            (
+                warp::http::StatusCode::FOUND,
+                Some("http://vpn.example/connect".to_string())
+            )
+        );
+        let reply = renderer.get("wiki", "/wiki", query.clone(), &request).await.unwrap();
+        assert_eq!(
+            location(reply),
+            (
+                warp::http::StatusCode::FOUND,
+                Some("http://wiki.example/?q=pangolins".to_string())
+            )
+        );
+        let reply = renderer.get("wiki", "/wiki/extra", query, &request).await.unwrap();
+        assert_eq!(location(reply).0, warp::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_misses_are_wanted() {
+        let renderer = Renderer::empty();
+        let request = RequestContext::default();
+        for path in ["/oncall", "/on-call/today", "/wiki"] {
+            let short = path[1..].split('/').next().unwrap();
+            renderer.get(short, path, HashMap::new(), &request).await.unwrap();
+        }
+        renderer.flush_clicks().await;
+
+        let wanted = renderer.db.misses.wanted(10).await.unwrap();
+        assert_eq!(
+            wanted.iter().map(|w| (w.short.as_str(), w.misses)).collect::<Vec<_>>(),
+            vec![("on-call", 2), ("wiki", 1)]
+        );
+        assert!(renderer.db.stats.load_all().await.unwrap().is_empty()); // misses are not clicks
+    }
+
+    #[tokio::test]
+    async fn test_not_a_click() {
+        let renderer = Renderer::empty();
+        let create = CreateUpdateRequest {
+            short: "nyt".to_string(),
+            target: "http://www.nytimes.com".to_string(),
+            status: None,
+            max_age: None,
+            passthrough: None,
+            dont_track: None,
+        };
+        renderer.new_link(create).await.unwrap();
+
+        let with_header = |name: &str, value: &str| {
+            let mut request = RequestContext::default();
+            request.headers.insert(
+                warp::http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+            request
+        };
+        let head = RequestContext {
+            method: warp::http::Method::HEAD,
+            ..Default::default()
+        };
+        let browser = with_header("User-Agent", "Mozilla/5.0 (X11; Linux x86_64) Firefox/140.0");
+        for request in [
+            head,
+            with_header("Sec-Purpose", "prefetch;prerender"),
+            with_header("Purpose", "prefetch"),
+            with_header(
+                "User-Agent",
+                "Slackbot-LinkExpanding 1.0 (+https://api.slack.com/robots)",
+            ),
+            browser.clone(),
+        ] {
+            assert_eq!(
+                renderer.not_a_click(&request).is_some(),
+                request.headers != browser.headers
+            );
+            let reply = renderer.get("nyt", "/nyt", HashMap::new(), &request).await.unwrap();
+            assert_eq!(
+                warp::Reply::into_response(reply).status(),
+                warp::http::StatusCode::FOUND
+            );
+        }
+        renderer
+            .get("nope", "/nope", HashMap::new(), &with_header("Purpose", "prefetch"))
+            .await
+            .unwrap();
+        renderer.flush_clicks().await;
+
+        let stats = renderer.db.stats.load("nyt").await.unwrap().unwrap();
+        assert_eq!((stats.clicks, stats.skipped), (Some(1), 4));
+        assert!(renderer.db.misses.wanted(10).await.unwrap().is_empty()); // prefetched misses are not wanted
+    }
+
+    #[tokio::test]
+    async fn test_dont_track() {
+        let link = |short: &str, dont_track| CreateUpdateRequest {
+            short: short.to_string(),
+            target: "http://www.nytimes.com".to_string(),
+            status: None,
+            max_age: None,
+            passthrough: None,
+            dont_track: Some(dont_track),
+        };
+        let request = RequestContext::default();
+
+        let renderer = Renderer::empty();
+        renderer.new_link(link("nyt", false)).await.unwrap();
+        renderer.new_link(link("private", true)).await.unwrap();
+        for short in ["nyt", "private"] {
+            renderer
+                .get(short, &format!("/{short}"), HashMap::new(), &request)
+                .await
+                .unwrap();
+        }
+        renderer.flush_clicks().await;
+        assert_eq!(renderer.db.stats.load("nyt").await.unwrap().unwrap().clicks, Some(1));
+        assert_eq!(renderer.db.stats.load("private").await.unwrap().unwrap().clicks, None);
+
+        let untracked = Renderer::with_options(
+            "go",
+            db::Db::in_memory().unwrap(),
+            Handlebars::new(),
+            Options {
+                track_clicks: false,
+                ..Default::default()
+            },
+        );
+        untracked.new_link(link("nyt", false)).await.unwrap();
+        untracked.db.stats.incr("nyt").await.unwrap(); // recorded before tracking was turned off
+        untracked.get("nyt", "/nyt", HashMap::new(), &request).await.unwrap();
+        untracked.get("nope", "/nope", HashMap::new(), &request).await.unwrap();
+        untracked.flush_clicks().await;
+        assert_eq!(untracked.db.stats.load("nyt").await.unwrap().unwrap().clicks, Some(1));
+        assert!(untracked.db.misses.wanted(10).await.unwrap().is_empty());
+
+        let response = warp::Reply::into_response(untracked.json_detail("nyt", None).await.unwrap());
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        let details: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(details.get("clicks").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wanted() {
+        let mut handlebars = Handlebars::new();
+        handlebars
+            .register_templates_directory("templates", handlebars::DirectorySourceOptions::default())
+            .unwrap();
+        let renderer = Renderer::new("go", db::Db::in_memory().unwrap(), handlebars);
+        renderer.clicks.record_miss("r&d");
+        renderer.flush_clicks().await;
+
+        let response = warp::Reply::into_response(renderer.wanted().await.unwrap());
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(page.contains(r#"href="/?short=r%26d""#));
+
+        // with tracking turned off, the page says so rather than showing misses from before
+        let untracked = Renderer::with_options(
+            "go",
+            renderer.db.clone(),
+            renderer.handlebars.clone(),
+            Options {
+                track_clicks: false,
+                ..Default::default()
+            },
+        );
+        let response = warp::Reply::into_response(untracked.wanted().await.unwrap());
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(!page.contains("r%26d"));
+        assert!(page.contains("tracking is turned off"));
+    }
+
+    #[tokio::test]
+    async fn test_purge() {
+        let renderer = Renderer::empty();
+        let create = CreateUpdateRequest {
+            short: "nyt".to_string(),
+            target: "http://www.nytimes.com".to_string(),
+            status: None,
+            max_age: None,
+            passthrough: None,
+            dont_track: None,
+        };
+        renderer.new_link(create).await.unwrap();
+        let request = RequestContext::default();
+        renderer.get("nyt", "/nyt", HashMap::new(), &request).await.unwrap();
+        renderer.get("nope", "/nope", HashMap::new(), &request).await.unwrap();
+        renderer.flush_clicks().await;
+        renderer.get("nyt", "/nyt", HashMap::new(), &request).await.unwrap(); // still queued
+
+        renderer.purge("not a token").await.unwrap();
+        assert_eq!(renderer.db.stats.load("nyt").await.unwrap().unwrap().clicks, Some(1));
+
+        renderer.purge(&renderer.xsrf()).await.unwrap();
+        renderer.flush_clicks().await;
+        let stats = renderer.db.stats.load("nyt").await.unwrap().unwrap();
+        assert_eq!((stats.clicks, stats.skipped), (None, 0));
+        assert!(renderer.db.misses.wanted(10).await.unwrap().is_empty());
+        assert!(renderer.db.link.load("nyt").await.is_ok()); // links are kept
+    }
+
+    #[test]
+    fn test_forwarded_user() {
+        let options = Options {
+            trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+            ..Default::default()
+        };
+        let renderer = Renderer::with_options("go", db::Db::in_memory().unwrap(), Handlebars::new(), options);
+        let mut headers = warp::http::HeaderMap::new();
+        headers.insert("x-forwarded-user", "amelie".parse().unwrap());
+        let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(renderer.forwarded_user(proxy, &headers), Some("amelie".to_string()));
+        // anyone else could claim to be anyone
+        assert_eq!(renderer.forwarded_user(client, &headers), None);
+        assert_eq!(renderer.forwarded_user(proxy, &warp::http::HeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn test_popular_links() {
+        let renderer = Renderer::empty();
+        for short in ["nyt", "cnn"] {
+            let create = CreateUpdateRequest {
+                short: short.to_string(),
+                target: format!("http://www.{short}.com"),
+                status: None,
+                max_age: None,
+                passthrough: None,
+                dont_track: None,
+            };
+            renderer.new_link(create).await.unwrap();
+        }
+        // clicks counted before there were clicks per day are ranked over all time
+        renderer.db.stats.incr("nyt").await.unwrap();
+        let (days, links) = renderer.popular_links(Some(30), 10).await;
+        assert_eq!(days, None);
+        assert_eq!(links[0].0.short, "nyt");
+
+        let request = RequestContext::default();
+        renderer.get("cnn", "/cnn", HashMap::new(), &request).await.unwrap();
+        renderer.flush_clicks().await;
+        let (days, links) = renderer.popular_links(Some(30), 10).await;
+        assert_eq!(days, Some(30));
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].0.short, "cnn");
+    }
+
+    #[tokio::test]
+    async fn test_click_sources() {
+        let request = |ip: &str, referrer: &str, user_agent: &str| {
+            let mut request = RequestContext {
+                client_ip: Some(ip.parse().unwrap()),
+                ..Default::default()
+            };
+            request.headers.insert("referer", referrer.parse().unwrap());
+            request.headers.insert("user-agent", user_agent.parse().unwrap());
+            request
+        };
+        let browser = "Mozilla/5.0 (X11; Linux x86_64) Firefox/140.0";
+
+        let renderer = Renderer::empty();
+        let source = renderer.click_source(&request("192.0.2.77", "https://Chat.Example/room/1", browser));
+        assert_eq!(source.subnet.as_deref(), Some("192.0.2.0/24"));
+        assert_eq!(source.referrer.as_deref(), Some("chat.example"));
+        assert_eq!(source.agent, model::AgentFamily::Browser);
+        let source = renderer.click_source(&request("2001:db8:1:2::9", "not a url", "curl/8.5.0"));
+        assert_eq!(source.subnet.as_deref(), Some("2001:db8:1::/48"));
+        assert_eq!(source.referrer, None);
+        assert_eq!(source.agent, model::AgentFamily::Cli);
+        let source = renderer.click_source(&RequestContext::default());
+        assert_eq!((source.subnet, source.referrer), (None, None));
+
+        let hashed = Renderer::with_options(
+            "go",
+            db::Db::in_memory().unwrap(),
+            Handlebars::new(),
+            Options {
+                subnet_key: Some("secret".to_string()),
+                ..Default::default()
+            },
+        );
+        let subnet = |ip| hashed.click_source(&request(ip, "", browser)).subnet.unwrap();
+        assert_eq!(subnet("192.0.2.77").len(), 16);
+        assert!(!subnet("192.0.2.77").contains("192"));
+        assert_eq!(subnet("192.0.2.77"), subnet("192.0.2.1"));
+        assert_ne!(subnet("192.0.2.77"), subnet("192.0.3.77"));
+
+        let create = CreateUpdateRequest {
+            short: "nyt".to_string(),
+            target: "http://www.nytimes.com".to_string(),
+            status: None,
+            max_age: None,
+            passthrough: None,
+            dont_track: None,
+        };
+        renderer.new_link(create).await.unwrap();
+        for ip in ["192.0.2.1", "192.0.2.2", "198.51.100.1"] {
+            let request = request(ip, "https://chat.example/", browser);
+            renderer.get("nyt", "/nyt", HashMap::new(), &request).await.unwrap();
+        }
+        renderer.flush_clicks().await;
+        let response = warp::Reply::into_response(renderer.json_detail("nyt", Some(7)).await.unwrap());
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        let details: model::LinkDetails = serde_json::from_slice(&body).unwrap();
+        let breakdown = details.breakdown.unwrap();
+        assert_eq!(breakdown.days, Some(7));
+        assert_eq!(
+            (breakdown.subnets[0].name.as_str(), breakdown.subnets[0].clicks),
+            ("192.0.2.0/24", 2)
+        );
+        assert_eq!(
+            (breakdown.referrers[0].name.as_str(), breakdown.referrers[0].clicks),
+            ("chat.example", 3)
+        );
+        assert_eq!(
+            (breakdown.agents[0].name.as_str(), breakdown.agents[0].clicks),
+            ("browser", 3)
+        );
+    }
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[0, 2, 1]), "0.0,40.0 150.0,0.0 300.0,20.0");
+        assert_eq!(sparkline(&[0, 0]), "0.0,40.0 300.0,40.0");
+        assert_eq!(sparkline(&[3]), "0.0,0.0");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[tokio::test]
+    async fn test_rollups() {
+        let renderer = Renderer::empty();
+        let create = CreateUpdateRequest {
+            short: "nyt".to_string(),
+            target: "http://www.nytimes.com".to_string(),
+            status: None,
+            max_age: None,
+            passthrough: None,
+            dont_track: None,
+        };
+        renderer.new_link(create).await.unwrap();
+        assert!(renderer.rollups("nope", 7).await.unwrap().is_none());
+
+        let rollups = renderer.rollups("nyt", 7).await.unwrap().unwrap();
+        assert_eq!((rollups.days, rollups.clicks, rollups.last_clicked), (7, 0, None));
+        assert!(rollups.daily.iter().all(|d| d.clicks == 0));
+
+        let request = RequestContext::default();
+        for _ in 0..2 {
+            renderer.get("nyt", "/nyt", HashMap::new(), &request).await.unwrap();
+        }
+        renderer.flush_clicks().await;
+        let today = chrono::Utc::now().date_naive();
+        let rollups = renderer.rollups("NYT", 7).await.unwrap().unwrap();
+        assert_eq!(rollups.daily.len(), 7);
+        assert_eq!(rollups.daily[0].day, today - chrono::Duration::days(6));
+        assert_eq!(rollups.daily[6], model::DailyClicks { day: today, clicks: 2 });
+        assert_eq!(rollups.clicks, 2);
+        assert!(rollups.last_clicked.is_some());
+
+        let response = warp::Reply::into_response(renderer.json_rollups("nyt", 10_000).await.unwrap());
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        let rollups: model::Rollups = serde_json::from_slice(&body).unwrap();
+        assert_eq!(rollups.days, MAX_ROLLUP_DAYS);
+        let response = warp::Reply::into_response(renderer.json_rollups("nope", 30).await.unwrap());
+        assert_eq!(response.status(), warp::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_hygiene() {
+        let renderer = Renderer::empty();
+        let old = chrono::Utc::now() - chrono::Duration::days(400);
+        for (short, long) in [
+            ("docs", "https://www.example.com/docs/"),
+            ("documentation", "http://example.com/docs"),
+            ("wiki", "http://wiki.example"),
+            ("legacy", "gopher://old.example"),
+        ] {
+            let link = model::Link {
+                short: short.to_string(),
+                long: long.to_string(),
+                created: old,
+                updated: old,
+                status: model::RedirectStatus::default(),
+                max_age: None,
+                passthrough: model::Passthrough::default(),
+                dont_track: false,
+            };
+            renderer.db.link.save(&link).await.unwrap();
+            renderer.db.stats.save(short).await.unwrap();
+        }
+        renderer.db.stats.incr("wiki").await.unwrap(); // clicked before rollups, so when is unknown
+        let request = RequestContext::default();
+        renderer.get("docs", "/docs", HashMap::new(), &request).await.unwrap();
+        renderer.flush_clicks().await;
+
+        let report = renderer.hygiene_report(HYGIENE_DAYS).await.unwrap();
+        let shorts = |links: &[model::HygieneLink]| links.iter().map(|l| l.short.clone()).collect::<Vec<_>>();
+        assert_eq!(shorts(&report.never_clicked), vec!["documentation", "legacy"]);
+        assert_eq!(shorts(&report.stale), vec!["wiki"]);
+        assert_eq!(report.duplicates.len(), 1);
+        assert_eq!(shorts(&report.duplicates[0].links), vec!["docs", "documentation"]);
+        assert!(report.collisions.is_empty());
+        assert_eq!(report.malformed.len(), 1);
+        assert_eq!(report.malformed[0].short, "legacy");
+
+        let shorts_of = |shorts: &[&str]| shorts.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let response = renderer
+            .hygiene_action(
+                "merge",
+                &shorts_of(&["docs", "documentation"]),
+                Some("documentation"),
+                "bad",
+            )
+            .await
+            .unwrap();
+        assert!(warp::Reply::into_response(response).status().is_redirection());
+        assert!(renderer.find_link("docs").await.is_some());
+
+        let xsrf = renderer.xsrf();
+        renderer
+            .hygiene_action(
+                "merge",
+                &shorts_of(&["docs", "documentation"]),
+                Some("documentation"),
+                &xsrf,
+            )
+            .await
+            .unwrap();
+        assert!(renderer.find_link("docs").await.is_none());
+        let stats = renderer.db.stats.load("documentation").await.unwrap().unwrap();
+        assert_eq!(stats.clicks, Some(1));
+
+        renderer
+            .hygiene_action("delete", &shorts_of(&["legacy"]), None, &xsrf)
+            .await
+            .unwrap();
+        assert!(renderer.find_link("legacy").await.is_none());
+        let response = renderer.hygiene_action("merge", &[], None, &xsrf).await.unwrap();
+        assert_eq!(
+            warp::Reply::into_response(response).status(),
+            warp::http::StatusCode::BAD_REQUEST
+        );
+
+        let report = renderer.hygiene_report(HYGIENE_DAYS).await.unwrap();
+        assert!(report.duplicates.is_empty() && report.malformed.is_empty());
+
+        // the report is reused until a link is changed through the renderer
+        let link = |short: &str| CreateUpdateRequest {
+            short: short.to_string(),
+            target: "gopher://new.example".to_string(),
+            status: None,
+            max_age: None,
+            passthrough: None,
+            dont_track: None,
+        };
+        renderer.db.link.save(&link("sneaky").into()).await.unwrap();
+        assert!(
+            renderer
+                .hygiene_report(HYGIENE_DAYS)
+                .await
+                .unwrap()
+                .malformed
+                .is_empty()
+        );
+        renderer.new_link(link("gopher")).await.unwrap();
+        assert_eq!(renderer.hygiene_report(HYGIENE_DAYS).await.unwrap().malformed.len(), 2);
+
+        let report = renderer.hygiene_report(u32::MAX).await.unwrap();
+        assert_eq!(report.days, MAX_HYGIENE_DAYS);
+    }
+
+    #[tokio::test]
+    async fn test_link_checks() {
+        let link = |short: &str, target: &str| CreateUpdateRequest {
+            short: short.to_string(),
+            target: target.to_string(),
+            status: None,
+            max_age: None,
+            passthrough: None,
+            dont_track: None,
+        };
+        let renderer = Renderer::empty();
+        let gone = model::LinkCheck::new(Some(404), None, None, chrono::Utc::now());
+        for short in ["nyt", "wiki"] {
+            renderer.new_link(link(short, "http://www.nytimes.com")).await.unwrap();
+            renderer.db.checks.save(short, &gone).await.unwrap();
+        }
+        let xsrf = renderer.xsrf();
+
+        // saving the same destination link keeps its last check, and changing it forgets it
+        renderer
+            .update(link("nyt", "http://www.nytimes.com"), &xsrf)
+            .await
+            .unwrap();
+        assert!(renderer.db.checks.load("nyt").await.unwrap().is_some_and(|c| c.broken));
+        renderer
+            .update(link("nyt", "https://www.nytimes.com"), &xsrf)
+            .await
+            .unwrap();
+        assert!(renderer.db.checks.load("nyt").await.unwrap().is_none());
+
+        renderer.delete("wiki", &xsrf).await.unwrap();
+        assert!(renderer.db.checks.load("wiki").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_update_max_age() {
+        let request = |json: &str| serde_json::from_str::<CreateUpdateRequest>(json).unwrap();
+        let renderer = Renderer::empty();
+        let xsrf = renderer.xsrf();
+        renderer
+            .new_link(request(
+                r#"{"short":"nyt","target":"http://www.nytimes.com","max_age":60}"#,
+            ))
+            .await
+            .unwrap();
+
+        // a max age that is left out is kept, and null stops caching
+        renderer
+            .update(request(r#"{"short":"nyt","target":"http://nytimes.com"}"#), &xsrf)
+            .await
+            .unwrap();
+        assert_eq!(renderer.db.link.load("nyt").await.unwrap().max_age, Some(60));
+        renderer
+            .update(
+                request(r#"{"short":"nyt","target":"http://nytimes.com","max_age":null}"#),
+                &xsrf,
+            )
+            .await
+            .unwrap();
+        assert_eq!(renderer.db.link.load("nyt").await.unwrap().max_age, None);
+    }
 }
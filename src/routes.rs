@@ -1,8 +1,18 @@
 use std::{collections::HashMap, convert::Infallible};
 
-use warp::{Filter, filters::path::FullPath};
+use warp::{
+    Filter,
+    filters::path::FullPath,
+    http::{HeaderMap, Method},
+};
 
-use crate::{CreateUpdateRequest, render::Renderer};
+use crate::{
+    CreateUpdateRequest, RequestContext, api,
+    model::{ErrorResponse, Passthrough, TokenInput, TokenScope, parse_query_allow},
+    ratelimit::Budget,
+    render::{self, Renderer},
+    server::RemoteAddr,
+};
 
 // If the caller sends this header set to a non-empty value, we will allow
 // them to make the call even without an XSRF token. JavaScript in browser
@@ -15,11 +25,144 @@ fn with_renderer(handlers: Renderer) -> impl Filter<Extract = (Renderer,), Error
     warp::any().map(move || handlers.clone())
 }
 
+// whether a request may use an endpoint, or else the error it is answered with
+type Access = Result<(), Box<dyn warp::Reply>>;
+
+// decides whether a request may do what the scope allows, from its "Authorization: Bearer" API token
+// or, unless API tokens are required, its Sec-Golink header
+fn authorize(
+    renderer: Renderer,
+    scope: TokenScope,
+) -> impl Filter<Extract = (Access,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>(warp::http::header::AUTHORIZATION.as_str())
+        .and(warp::header::optional::<String>(SEC_HEADER_NAME))
+        .and(with_renderer(renderer))
+        .then(
+            move |authorization: Option<String>, sec_header_value: Option<String>, renderer: Renderer| async move {
+                let sec_golink = sec_header_value.is_some_and(|value| !value.is_empty());
+                renderer.authorize(authorization.as_deref(), sec_golink, scope).await
+            },
+        )
+}
+
+// decides whether a form posted from a browser may do what the scope allows, from its "Authorization: Bearer"
+// API token or the user a trusted proxy says is signed in
+fn authorize_form(
+    renderer: Renderer,
+    scope: TokenScope,
+) -> impl Filter<Extract = (Access,), Error = Infallible> + Clone {
+    warp::header::headers_cloned()
+        .and(warp::ext::optional::<RemoteAddr>())
+        .and(with_renderer(renderer))
+        .then(
+            move |headers: HeaderMap, remote_addr: Option<RemoteAddr>, renderer: Renderer| async move {
+                let authorization = headers
+                    .get(warp::http::header::AUTHORIZATION)
+                    .and_then(|value| value.to_str().ok());
+                let user = remote_addr.and_then(|RemoteAddr(addr)| renderer.forwarded_user(addr.ip(), &headers));
+                renderer.authorize_form(authorization, user.as_deref(), scope).await
+            },
+        )
+}
+
+fn with_request_context(renderer: Renderer) -> impl Filter<Extract = (RequestContext,), Error = Infallible> + Clone {
+    warp::method()
+        .and(warp::header::headers_cloned())
+        .and(warp::ext::optional::<RemoteAddr>())
+        .map(
+            move |method: Method, headers: HeaderMap, remote_addr: Option<RemoteAddr>| {
+                let header = |name| {
+                    headers
+                        .get(name)
+                        .and_then(|value: &warp::http::HeaderValue| value.to_str().ok())
+                        .map(str::to_string)
+                };
+                RequestContext {
+                    method,
+                    host: header(warp::http::header::HOST.as_str()),
+                    client_ip: remote_addr.map(|RemoteAddr(addr)| renderer.client_ip(addr.ip(), &headers)),
+                    // if gohome sits behind an authenticating proxy, the proxy can identify the current user
+                    user: remote_addr.and_then(|RemoteAddr(addr)| renderer.forwarded_user(addr.ip(), &headers)),
+                    headers: headers.clone(),
+                }
+            },
+        )
+}
+
+// reads a link form: the "short" and "long" fields, and the optional "status", "max_age",
+// "path_mode", "query_mode", "query_allow" and "dont_track" fields, where a field left out keeps what the
+// link has, and an empty "max_age" turns caching off
+fn link_request(form_data: &HashMap<String, String>) -> Result<CreateUpdateRequest, String> {
+    let field = |name| {
+        form_data
+            .get(name)
+            .map(|value: &String| value.trim())
+            .filter(|value| !value.is_empty())
+    };
+    let required = |name| form_data.get(name).cloned().ok_or_else(|| format!("missing {name}"));
+    let status = field("status").map(str::parse).transpose()?;
+    let max_age = match form_data.get("max_age") {
+        Some(_) => Some(
+            field("max_age")
+                .map(|value| value.parse().map_err(|_| format!("invalid max_age {value:?}")))
+                .transpose()?,
+        ),
+        None => None,
+    };
+    let passthrough = if ["path_mode", "query_mode", "query_allow"]
+        .iter()
+        .any(|name| form_data.contains_key(*name))
+    {
+        Some(Passthrough {
+            path_mode: field("path_mode").map(str::parse).transpose()?.unwrap_or_default(),
+            query_mode: field("query_mode").map(str::parse).transpose()?.unwrap_or_default(),
+            query_allow: field("query_allow").map(parse_query_allow).unwrap_or_default(),
+        })
+    } else {
+        None
+    };
+    Ok(CreateUpdateRequest {
+        short: required("short")?,
+        target: required("long")?,
+        status,
+        max_age,
+        passthrough,
+        // an unchecked box is not sent, so the form sends "off" ahead of the box for it to override
+        dont_track: form_data
+            .get("dont_track")
+            .map(|value| matches!(value.trim(), "on" | "true" | "1")),
+    })
+}
+
 fn home(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path::end()
         .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
         .and(with_renderer(renderer))
-        .and_then(|renderer: Renderer| async move { renderer.home().await })
+        .and_then(|query: HashMap<String, String>, renderer: Renderer| async move {
+            // popular links are ranked over the "days" query parameter, or "all" time, and "limit" are shown
+            let default = render::Popularity::default();
+            let popularity = render::Popularity {
+                days: match query.get("days").map(String::as_str) {
+                    Some("all") => None,
+                    // clicks in a window are summed from the rollups, which go back no further
+                    Some(days) => days
+                        .parse::<u32>()
+                        .ok()
+                        .map(|days| days.min(render::MAX_ROLLUP_DAYS))
+                        .or(default.days),
+                    None => default.days,
+                },
+                limit: query
+                    .get("limit")
+                    .and_then(|limit| limit.parse().ok())
+                    .unwrap_or(default.limit),
+            };
+            // the short name may be filled in, as it is when creating a wanted link
+            renderer
+                .home(query.get("short").map_or("", String::as_str), popularity)
+                .await
+        })
 }
 
 fn all(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -29,53 +172,246 @@ fn all(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = wa
         .and_then(|renderer: Renderer| async move { renderer.all().await })
 }
 
+fn wanted(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(".wanted")
+        .and(warp::get())
+        .and(with_renderer(renderer))
+        .and_then(|renderer: Renderer| async move { renderer.wanted().await })
+}
+
+// reads the "days" query parameter of the hygiene report, after which unclicked links are stale
+fn hygiene_days(query_params: &HashMap<String, String>) -> u32 {
+    query_params
+        .get("days")
+        .and_then(|days| days.parse::<u32>().ok())
+        .map_or(render::HYGIENE_DAYS, |days| days.min(render::MAX_HYGIENE_DAYS))
+}
+
+fn hygiene(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let report = warp::path!(".hygiene")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_renderer(renderer.clone()))
+        .and_then(|query_params: HashMap<String, String>, renderer: Renderer| async move {
+            renderer.hygiene(hygiene_days(&query_params)).await
+        });
+    let json = warp::path!(".hygiene.json")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_renderer(renderer.clone()))
+        .and_then(|query_params: HashMap<String, String>, renderer: Renderer| async move {
+            renderer.json_hygiene(hygiene_days(&query_params)).await
+        });
+    // the form repeats "short" for each link chosen
+    let action = warp::path!(".hygiene")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 64))
+        .and(authorize_form(renderer.clone(), TokenScope::Write))
+        .and(warp::body::form())
+        .and(with_renderer(renderer))
+        .and_then(
+            |access: Access, form_data: Vec<(String, String)>, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
+                }
+                let field = |name: &str| {
+                    form_data
+                        .iter()
+                        .find(|(key, value)| key == name && !value.is_empty())
+                        .map(|(_, value)| value.as_str())
+                };
+                let shorts: Vec<String> = form_data
+                    .iter()
+                    .filter(|(key, _)| key == "short")
+                    .map(|(_, value)| value.clone())
+                    .collect();
+                renderer
+                    .hygiene_action(
+                        field("action").unwrap_or_default(),
+                        &shorts,
+                        field("into"),
+                        field("xsrf").unwrap_or_default(),
+                    )
+                    .await
+            },
+        );
+    report.or(json).or(action)
+}
+
+fn admin(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!(".admin")
+        .and(warp::get())
+        .and(with_renderer(renderer))
+        .and_then(|renderer: Renderer| async move { renderer.admin().await })
+}
+
+fn purge(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!(".admin" / "purge")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(authorize_form(renderer.clone(), TokenScope::Admin))
+        .and(warp::body::form())
+        .and(with_renderer(renderer))
+        .and_then(
+            |access: Access, form_data: HashMap<String, String>, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
+                }
+                let xsrf = form_data.get("xsrf").cloned().unwrap_or_default();
+                renderer.purge(&xsrf).await
+            },
+        )
+}
+
+fn tokens(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let create = warp::path!(".admin" / "tokens")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(authorize_form(renderer.clone(), TokenScope::Admin))
+        .and(warp::body::form())
+        .and(with_renderer(renderer.clone()))
+        .and_then(
+            |access: Access, form_data: HashMap<String, String>, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
+                }
+                let field = |name| {
+                    form_data
+                        .get(name)
+                        .map(|value: &String| value.trim())
+                        .filter(|value| !value.is_empty())
+                };
+                let (Ok(scope), Ok(expires_in_days)) = (
+                    field("scope").map_or(Ok(TokenScope::default()), str::parse),
+                    field("expires_in_days").map(str::parse).transpose(),
+                ) else {
+                    return renderer.bad_request().await;
+                };
+                let input = TokenInput {
+                    name: field("name").unwrap_or_default().to_string(),
+                    scope,
+                    expires_in_days,
+                };
+                renderer.create_token(input, field("xsrf").unwrap_or_default()).await
+            },
+        );
+    let revoke = warp::path!(".admin" / "tokens" / String / "revoke")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(authorize_form(renderer.clone(), TokenScope::Admin))
+        .and(warp::body::form())
+        .and(with_renderer(renderer))
+        .and_then(
+            |id: String, access: Access, form_data: HashMap<String, String>, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
+                }
+                let xsrf = form_data.get("xsrf").cloned().unwrap_or_default();
+                renderer.revoke_token(&id, &xsrf).await
+            },
+        );
+    create.or(revoke)
+}
+
+// reads the "days" query parameter choosing how far back clicks are broken down, where "all" is all time
+fn breakdown_days(query_params: &HashMap<String, String>) -> Option<u32> {
+    match query_params.get("days").map(String::as_str) {
+        Some("all") => None,
+        Some(days) => Some(days.parse().unwrap_or(render::BREAKDOWN_DAYS)),
+        None => Some(render::BREAKDOWN_DAYS),
+    }
+}
+
 fn detail(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!(".detail" / String)
         .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
         .and(with_renderer(renderer))
-        .and_then(|short: String, renderer: Renderer| async move { renderer.detail(&short).await })
+        .and_then(
+            |short: String, query_params: HashMap<String, String>, renderer: Renderer| async move {
+                renderer.detail(&short, breakdown_days(&query_params)).await
+            },
+        )
+}
+
+fn rollups(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!(".rollups" / String)
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_renderer(renderer))
+        .and_then(
+            |short: String, query_params: HashMap<String, String>, renderer: Renderer| async move {
+                let days = query_params
+                    .get("days")
+                    .and_then(|days| days.parse().ok())
+                    .unwrap_or(render::BREAKDOWN_DAYS);
+                renderer.json_rollups(&short, days).await
+            },
+        )
 }
 
 fn create(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(".create")
         .and(warp::post())
         .and(warp::body::content_length_limit(1024 * 16))
+        .and(authorize_form(renderer.clone(), TokenScope::Write))
         .and(warp::body::form())
         .and(with_renderer(renderer))
-        .and_then(|form_data: HashMap<String, String>, renderer: Renderer| async move {
-            let xsrf = form_data.get("xsrf").unwrap().to_string();
-            let request = CreateUpdateRequest {
-                short: form_data.get("short").unwrap().to_string(),
-                target: form_data.get("long").unwrap().to_string(),
-            };
-            renderer.create(request, &xsrf).await
-        })
+        .and_then(
+            |access: Access, form_data: HashMap<String, String>, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
+                }
+                let xsrf = form_data.get("xsrf").unwrap().to_string();
+                let request = match link_request(&form_data) {
+                    Ok(request) => request,
+                    Err(error) => {
+                        let field = |name| form_data.get(name).map_or("", String::as_str);
+                        return renderer.reject_create(field("short"), field("long"), error).await;
+                    }
+                };
+                renderer.create(request, &xsrf).await
+            },
+        )
 }
 
 fn update(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path(".update")
         .and(warp::post())
         .and(warp::body::content_length_limit(1024 * 16))
+        .and(authorize_form(renderer.clone(), TokenScope::Write))
         .and(warp::body::form())
         .and(with_renderer(renderer))
-        .and_then(|form_data: HashMap<String, String>, renderer: Renderer| async move {
-            let xsrf = form_data.get("xsrf").unwrap().to_string();
-            let request = CreateUpdateRequest {
-                short: form_data.get("short").unwrap().to_string(),
-                target: form_data.get("long").unwrap().to_string(),
-            };
-            renderer.update(request, &xsrf).await
-        })
+        .and_then(
+            |access: Access, form_data: HashMap<String, String>, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
+                }
+                let xsrf = form_data.get("xsrf").unwrap().to_string();
+                let request = match link_request(&form_data) {
+                    Ok(request) => request,
+                    Err(error) => {
+                        let field = |name| form_data.get(name).map_or("", String::as_str);
+                        return renderer.reject_update(field("short"), field("long"), error).await;
+                    }
+                };
+                renderer.update(request, &xsrf).await
+            },
+        )
 }
 
 fn delete(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!(".delete" / String)
         .and(warp::post())
         .and(warp::body::content_length_limit(1024 * 16))
+        .and(authorize_form(renderer.clone(), TokenScope::Write))
         .and(warp::body::form())
         .and(with_renderer(renderer))
         .and_then(
-            |short: String, form_data: HashMap<String, String>, renderer: Renderer| async move {
+            |short: String, access: Access, form_data: HashMap<String, String>, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
+                }
                 let xsrf = form_data.get("xsrf").unwrap().to_string();
                 renderer.delete(&short, &xsrf).await
             },
@@ -96,56 +432,429 @@ fn export(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error =
         .and_then(|renderer: Renderer| async move { renderer.export().await })
 }
 
+fn openapi() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!(".well-known" / "openapi.json")
+        .and(warp::get())
+        .map(|| warp::reply::json(crate::openapi::document()))
+}
+
+fn diagnostics(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path(".diagnostics")
+        .and(warp::get())
+        .and(with_renderer(renderer))
+        .and_then(|renderer: Renderer| async move { renderer.diagnostics().await })
+}
+
+fn metrics(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!(".metrics")
+        .and(warp::get())
+        .and(with_renderer(renderer))
+        .and_then(|renderer: Renderer| async move { renderer.metrics().await })
+}
+
+fn preview(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!(".preview")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_request_context(renderer.clone()))
+        .and(with_renderer(renderer))
+        .and_then(
+            |mut query_params: HashMap<String, String>, request: RequestContext, renderer: Renderer| async move {
+                let path = query_params.remove("path").unwrap_or_default();
+                renderer.preview(&path, query_params.remove("long"), &request).await
+            },
+        )
+}
+
 fn get(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // HEAD requests are answered like GET requests, but are not counted as clicks
     warp::get()
+        .or(warp::head())
+        .unify()
         .and(warp::path::param::<String>())
         .and(warp::path::full())
         .and(warp::query::<HashMap<String, String>>())
+        .and(with_request_context(renderer.clone()))
         .and(with_renderer(renderer))
         .and_then(
-            |short: String, path: FullPath, query_params: HashMap<String, String>, renderer: Renderer| async move {
+            |short: String,
+             path: FullPath,
+             query_params: HashMap<String, String>,
+             request: RequestContext,
+             renderer: Renderer| async move {
                 let path_as_str = path.as_str();
                 if path_as_str.ends_with("+") {
                     let trimmed = short.strip_suffix("+").unwrap_or(path_as_str);
-                    renderer.json_detail(trimmed).await
+                    renderer.json_detail(trimmed, breakdown_days(&query_params)).await
                 } else {
-                    renderer.get(&short, path.as_str(), query_params).await
+                    renderer.get(&short, path.as_str(), query_params, &request).await
                 }
             },
         )
 }
 
-fn post(renderer: Renderer) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+fn post(renderer: Renderer) -> impl Filter<Extract = (Box<dyn warp::Reply>,), Error = warp::Rejection> + Clone {
     warp::path::end()
         .and(warp::post())
         .and(warp::body::content_length_limit(1024 * 16))
+        .and(authorize(renderer.clone(), TokenScope::Write))
         .and(warp::body::form())
-        .and(warp::header::<String>(SEC_HEADER_NAME))
         .and(with_renderer(renderer))
         .and_then(
-            |form_data: HashMap<String, String>, sec_header_value: String, renderer: Renderer| async move {
-                if sec_header_value.is_empty() {
-                    renderer.bad_request().await
-                } else {
-                    let request = CreateUpdateRequest {
-                        short: form_data.get("short").unwrap().to_string(),
-                        target: form_data.get("long").unwrap().to_string(),
-                    };
-                    renderer.new_link(request).await
+            |access: Access, form_data: HashMap<String, String>, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
+                }
+                match link_request(&form_data) {
+                    Ok(request) => renderer.new_link(request).await,
+                    _ => renderer.bad_request().await,
+                }
+            },
+        )
+}
+
+fn import(renderer: Renderer) -> impl Filter<Extract = (Box<dyn warp::Reply>,), Error = warp::Rejection> + Clone {
+    warp::path(".import")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 1024 * 8))
+        .and(authorize(renderer.clone(), TokenScope::Write))
+        .and(warp::body::bytes())
+        .and(with_renderer(renderer))
+        .and_then(
+            |access: Access, body: warp::hyper::body::Bytes, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
                 }
+                renderer.import(&body).await
             },
         )
 }
 
+fn api_body() -> impl Filter<Extract = (warp::hyper::body::Bytes,), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16).and(warp::body::bytes())
+}
+
+// answers API requests that match no endpoint, or whose body cannot be read, with an error object
+// like every other API error
+async fn api_rejection(rejection: warp::Rejection) -> Result<Box<dyn warp::Reply>, Infallible> {
+    // the rejections of every endpoint are combined, so those of the endpoint with the right method
+    // and path are looked for before those of the endpoints with the wrong method
+    let (status, error) = if rejection.is_not_found() {
+        (warp::http::StatusCode::NOT_FOUND, "no such API endpoint")
+    } else if rejection.find::<warp::reject::PayloadTooLarge>().is_some() {
+        (warp::http::StatusCode::PAYLOAD_TOO_LARGE, "request body is too large")
+    } else if rejection.find::<warp::reject::LengthRequired>().is_some() {
+        (
+            warp::http::StatusCode::LENGTH_REQUIRED,
+            "request body length is required",
+        )
+    } else if rejection.find::<warp::reject::MethodNotAllowed>().is_some() {
+        (warp::http::StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+    } else {
+        (warp::http::StatusCode::BAD_REQUEST, "bad request")
+    };
+    api::error(status, error, Vec::new())
+}
+
+// the JSON API under /api/v1, for scripts
+fn api(renderer: Renderer) -> impl Filter<Extract = (Box<dyn warp::Reply>,), Error = warp::Rejection> + Clone {
+    let list = warp::path!("links")
+        .and(warp::get())
+        .and(authorize(renderer.clone(), TokenScope::Read))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_renderer(renderer.clone()))
+        .and_then(
+            |access: Access, query: HashMap<String, String>, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
+                }
+                renderer.api_list(&query).await
+            },
+        );
+    let create = warp::path!("links")
+        .and(warp::post())
+        .and(authorize(renderer.clone(), TokenScope::Write))
+        .and(api_body())
+        .and(with_renderer(renderer.clone()))
+        .and_then(
+            |access: Access, body: warp::hyper::body::Bytes, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
+                }
+                renderer.api_create(&body).await
+            },
+        );
+    let read = warp::path!("links" / String)
+        .and(warp::get())
+        .and(authorize(renderer.clone(), TokenScope::Read))
+        .and(with_renderer(renderer.clone()))
+        .and_then(|short: String, access: Access, renderer: Renderer| async move {
+            if let Err(denied) = access {
+                return Ok(denied);
+            }
+            renderer.api_get(&short).await
+        });
+    let replace = warp::path!("links" / String)
+        .and(warp::put())
+        .and(authorize(renderer.clone(), TokenScope::Write))
+        .and(api_body())
+        .and(with_renderer(renderer.clone()))
+        .and_then(
+            |short: String, access: Access, body: warp::hyper::body::Bytes, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
+                }
+                renderer.api_replace(&short, &body).await
+            },
+        );
+    let patch = warp::path!("links" / String)
+        .and(warp::patch())
+        .and(authorize(renderer.clone(), TokenScope::Write))
+        .and(api_body())
+        .and(with_renderer(renderer.clone()))
+        .and_then(
+            |short: String, access: Access, body: warp::hyper::body::Bytes, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
+                }
+                renderer.api_patch(&short, &body).await
+            },
+        );
+    let remove = warp::path!("links" / String)
+        .and(warp::delete())
+        .and(authorize(renderer.clone(), TokenScope::Write))
+        .and(with_renderer(renderer.clone()))
+        .and_then(|short: String, access: Access, renderer: Renderer| async move {
+            if let Err(denied) = access {
+                return Ok(denied);
+            }
+            renderer.api_delete(&short).await
+        });
+    let stats = warp::path!("links" / String / "stats")
+        .and(warp::get())
+        .and(authorize(renderer.clone(), TokenScope::Read))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_renderer(renderer.clone()))
+        .and_then(
+            |short: String, access: Access, query: HashMap<String, String>, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
+                }
+                renderer.api_stats(&short, &query).await
+            },
+        );
+    let list_tokens = warp::path!("tokens")
+        .and(warp::get())
+        .and(authorize(renderer.clone(), TokenScope::Admin))
+        .and(with_renderer(renderer.clone()))
+        .and_then(|access: Access, renderer: Renderer| async move {
+            if let Err(denied) = access {
+                return Ok(denied);
+            }
+            renderer.api_tokens().await
+        });
+    let create_token = warp::path!("tokens")
+        .and(warp::post())
+        .and(authorize(renderer.clone(), TokenScope::Admin))
+        .and(api_body())
+        .and(with_renderer(renderer.clone()))
+        .and_then(
+            |access: Access, body: warp::hyper::body::Bytes, renderer: Renderer| async move {
+                if let Err(denied) = access {
+                    return Ok(denied);
+                }
+                renderer.api_create_token(&body).await
+            },
+        );
+    let revoke_token = warp::path!("tokens" / String)
+        .and(warp::delete())
+        .and(authorize(renderer.clone(), TokenScope::Admin))
+        .and(with_renderer(renderer))
+        .and_then(|id: String, access: Access, renderer: Renderer| async move {
+            if let Err(denied) = access {
+                return Ok(denied);
+            }
+            renderer.api_revoke_token(&id).await
+        });
+    // boxed so the nested futures of the endpoints live on the heap rather than overflowing the stack
+    let links = list
+        .or(create)
+        .unify()
+        .or(read)
+        .unify()
+        .or(replace)
+        .unify()
+        .or(patch)
+        .unify()
+        .or(remove)
+        .unify()
+        .or(stats)
+        .unify()
+        .boxed();
+    let tokens = list_tokens.or(create_token).unify().or(revoke_token).unify().boxed();
+    let endpoints = links.or(tokens).unify();
+    warp::path("api")
+        .and(warp::path("v1"))
+        .and(endpoints.recover(api_rejection).unify())
+}
+
+// RateLimited rejects a request from a client that has spent its budget, until it may retry
+#[derive(Debug)]
+struct RateLimited {
+    retry_after: std::time::Duration,
+}
+
+impl warp::reject::Reject for RateLimited {}
+
+// returns the budget a request counts against: anything but reading counts as a write, and reading
+// anything but the pages, the API and the assets is a link lookup
+fn budget(method: &Method, path: &str) -> Option<Budget> {
+    if !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Some(Budget::Write);
+    }
+    let first = path.trim_start_matches('/').split('/').next().unwrap_or_default();
+    if first.is_empty() || first.starts_with('.') || first == "api" || first == "assets" {
+        None
+    } else {
+        Some(Budget::Redirect)
+    }
+}
+
+// the routes whose paths have no parameters
+pub(crate) const FIXED_ROUTES: &[&str] = &[
+    "/",
+    "/.all",
+    "/.wanted",
+    "/.hygiene",
+    "/.hygiene.json",
+    "/.admin",
+    "/.admin/purge",
+    "/.admin/tokens",
+    "/.create",
+    "/.update",
+    "/.help",
+    "/.export",
+    "/.import",
+    "/.preview",
+    "/.diagnostics",
+    "/.metrics",
+    "/.well-known/openapi.json",
+    "/api/v1/links",
+    "/api/v1/tokens",
+];
+
+// the routes whose paths have parameters, named as route_name names them
+#[cfg(test)]
+pub(crate) const PARAMETERISED_ROUTES: &[&str] = &[
+    "/assets",
+    "/.admin/tokens/{id}/revoke",
+    "/.detail/{short}",
+    "/.rollups/{short}",
+    "/.delete/{short}",
+    "/api/v1/links/{short}",
+    "/api/v1/links/{short}/stats",
+    "/api/v1/tokens/{id}",
+    "/{short}+",
+    "/{short}",
+];
+
+/// Returns the route a request path is served by, as the path with its
+/// parameters named, so that metrics are kept per route rather than per link.
+/// Paths that no route serves are "other".
+pub fn route_name(path: &str) -> &'static str {
+    if let Some(route) = FIXED_ROUTES.iter().find(|route| **route == path) {
+        return route;
+    }
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["assets", ..] => "/assets",
+        [".admin", "tokens", _, "revoke"] => "/.admin/tokens/{id}/revoke",
+        [".detail", _] => "/.detail/{short}",
+        [".rollups", _] => "/.rollups/{short}",
+        [".delete", _] => "/.delete/{short}",
+        ["api", "v1", "links", _] => "/api/v1/links/{short}",
+        ["api", "v1", "links", _, "stats"] => "/api/v1/links/{short}/stats",
+        ["api", "v1", "tokens", _] => "/api/v1/tokens/{id}",
+        ["api", ..] => "other",
+        [short, ..] if short.starts_with('.') || short.is_empty() => "other",
+        _ if path.ends_with('+') => "/{short}+",
+        _ => "/{short}",
+    }
+}
+
+// counts a request against its client's budget, rejecting it once the budget is spent
+fn rate_limit(renderer: Renderer) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::header::headers_cloned())
+        .and(warp::ext::optional::<RemoteAddr>())
+        .and(with_renderer(renderer))
+        .and_then(
+            |method: Method,
+             path: FullPath,
+             headers: HeaderMap,
+             remote_addr: Option<RemoteAddr>,
+             renderer: Renderer| async move {
+                let (Some(budget), Some(RemoteAddr(addr))) = (budget(&method, path.as_str()), remote_addr) else {
+                    return Ok(());
+                };
+                let client = renderer.client_ip(addr.ip(), &headers);
+                renderer.rate_limits.check(budget, client).map_err(|retry_after| {
+                    tracing::debug!("rate limited {client}: {method} {}", path.as_str());
+                    warp::reject::custom(RateLimited { retry_after })
+                })
+            },
+        )
+        .untuple_one()
+}
+
+// answers a rate limited request with 429 Too Many Requests and when it may be retried
+async fn rate_limited(rejection: warp::Rejection) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let Some(RateLimited { retry_after }) = rejection.find::<RateLimited>() else {
+        return Err(rejection);
+    };
+    // Retry-After is in whole seconds, so the wait is rounded up
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let reply = warp::reply::with_status(
+        warp::reply::json(&ErrorResponse {
+            error: "too many requests".to_string(),
+            details: vec![format!("retry after {seconds} seconds")],
+        }),
+        warp::http::StatusCode::TOO_MANY_REQUESTS,
+    );
+    Ok(Box::new(warp::reply::with_header(
+        reply,
+        warp::http::header::RETRY_AFTER,
+        seconds.to_string(),
+    )))
+}
+
 pub fn get_routes(
     renderer: Renderer,
     assets: String,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    let routes = post(renderer.clone())
-        .or(detail(renderer.clone()))
+    // boxed so the nested futures of the page routes live on the heap rather than overflowing the stack
+    let pages = detail(renderer.clone())
         .or(all(renderer.clone()))
+        .or(wanted(renderer.clone()))
+        .or(rollups(renderer.clone()))
+        .or(hygiene(renderer.clone()))
+        .or(admin(renderer.clone()))
+        .or(purge(renderer.clone()))
+        .or(tokens(renderer.clone()))
         .or(help(renderer.clone()))
+        .boxed();
+    // the routes that check API tokens are boxed for the same reason
+    let routes = post(renderer.clone())
+        .boxed()
+        .or(api(renderer.clone()).boxed())
+        .or(pages)
         .or(export(renderer.clone()))
+        .or(import(renderer.clone()).boxed())
+        .or(preview(renderer.clone()))
+        .or(diagnostics(renderer.clone()))
+        .or(metrics(renderer.clone()))
+        .or(openapi())
         .or(get(renderer.clone()))
         .or(home(renderer.clone()))
         .or(create(renderer.clone()))
@@ -153,5 +862,38 @@ pub fn get_routes(
         .or(delete(renderer.clone()));
 
     let static_route = warp::path("assets").and(warp::fs::dir(assets));
-    static_route.or(routes)
+    static_route
+        .or(rate_limit(renderer).and(routes.boxed()))
+        .recover(rate_limited)
+        .with(warp::log::custom(|info| {
+            let route = route_name(info.path());
+            crate::metrics::observe_request(route, info.method().as_str(), info.status().as_u16(), info.elapsed());
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_name() {
+        assert_eq!(route_name("/"), "/");
+        assert_eq!(route_name("/.hygiene.json"), "/.hygiene.json");
+        assert_eq!(route_name("/.admin/tokens/abc123/revoke"), "/.admin/tokens/{id}/revoke");
+        assert_eq!(route_name("/.detail/docs"), "/.detail/{short}");
+        assert_eq!(route_name("/api/v1/links"), "/api/v1/links");
+        assert_eq!(route_name("/api/v1/links/docs/stats"), "/api/v1/links/{short}/stats");
+        assert_eq!(route_name("/api/v1/tokens/abc123"), "/api/v1/tokens/{id}");
+        assert_eq!(route_name("/assets/base.css"), "/assets");
+        // links are not told apart, whatever path they are given
+        assert_eq!(route_name("/docs"), "/{short}");
+        assert_eq!(route_name("/search/foo/bar"), "/{short}");
+        assert_eq!(route_name("/docs+"), "/{short}+");
+        assert_eq!(route_name("/.nothing"), "other");
+        assert_eq!(route_name("/api/v2/links"), "other");
+        for route in FIXED_ROUTES.iter().chain(PARAMETERISED_ROUTES) {
+            let path = route.replace("{short}", "docs").replace("{id}", "abc123");
+            assert_eq!(route_name(&path), *route);
+        }
+    }
 }
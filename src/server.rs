@@ -0,0 +1,54 @@
+use std::{convert::Infallible, future::Future, net::SocketAddr};
+
+use hyper::{Request, body::Incoming};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+};
+use tower_service::Service;
+
+/// RemoteAddr is the peer address of the connection a request arrived on. It is
+/// inserted into the request extensions by [`serve`] since warp no longer exposes it.
+#[derive(Clone, Copy, Debug)]
+pub struct RemoteAddr(pub SocketAddr);
+
+/// Serves the given warp service on the listener until the shutdown future
+/// completes, then waits for in-flight connections to finish.
+pub async fn serve<S>(service: S, listener: tokio::net::TcpListener, shutdown: impl Future<Output = ()>)
+where
+    S: Service<Request<Incoming>, Response = warp::reply::Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    let graceful = GracefulShutdown::new();
+    let mut shutdown = std::pin::pin!(shutdown);
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::error!("accept error: {e}");
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let service = service.clone();
+        let hyper_service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+            request.extensions_mut().insert(RemoteAddr(remote_addr));
+            service.clone().call(request)
+        });
+        let connection = auto::Builder::new(TokioExecutor::new())
+            .serve_connection_with_upgrades(TokioIo::new(stream), hyper_service)
+            .into_owned();
+        let connection = graceful.watch(connection);
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::debug!("connection error: {e}");
+            }
+        });
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+}
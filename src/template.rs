@@ -0,0 +1,506 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use handlebars::{
+    Context, Handlebars, Helper, HelperResult, JsonValue, Output, Path, PathSeg, RenderContext, RenderError,
+    TemplateError,
+    template::{HelperTemplate, Parameter, Template, TemplateElement},
+};
+use serde::Serialize;
+
+use crate::{RequestContext, model};
+
+// Request headers made available to destination link templates under `headers`.
+const TEMPLATE_HEADERS: [&str; 3] = ["accept-language", "referer", "user-agent"];
+
+const QUERY_FIELD: &str = "query";
+
+/// The helper added to the end of every block of a destination link template, so
+/// that the render deadline is checked on each pass through a block, even when the
+/// template writes nothing.
+pub(crate) const DEADLINE_HELPER: &str = "__deadline";
+
+/// TemplateContext is the data a destination link template is rendered with.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TemplateContext {
+    pub short: String,                     // the short name that was requested
+    pub path: String,                      // the remaining path after the short name
+    pub segments: Vec<String>,             // the remaining path split on "/"
+    pub query: HashMap<String, String>,    // the query parameters of the request
+    pub host: Option<String>,              // the host the request was sent to
+    pub client_ip: Option<String>,         // the address of the requesting client
+    pub headers: BTreeMap<String, String>, // selected request headers, keyed by lowercase name
+    pub user: Option<String>,              // the current user, when known
+}
+
+impl TemplateContext {
+    pub fn new(short: &str, path: &str, query: HashMap<String, String>) -> Self {
+        let path = path.trim_start_matches('/');
+        Self {
+            short: short.to_string(),
+            path: path.to_string(),
+            segments: path
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .map(str::to_string)
+                .collect(),
+            query,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_request(mut self, request: &RequestContext) -> Self {
+        self.host = request.host.clone();
+        self.client_ip = request.client_ip.map(|ip| ip.to_string());
+        self.headers = TEMPLATE_HEADERS
+            .iter()
+            .filter_map(|name| {
+                request
+                    .headers
+                    .get(*name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| (name.to_string(), value.to_string()))
+            })
+            .collect();
+        self.user = request.user.clone();
+        self
+    }
+
+    /// Fills in the request with typical values, so that a link can be checked for
+    /// whether it expands before any request has been made with it.
+    pub fn with_sample_request(mut self) -> Self {
+        self.host = Some("go".to_string());
+        self.client_ip = Some("192.0.2.1".to_string());
+        self.headers = TEMPLATE_HEADERS
+            .iter()
+            .map(|name| {
+                let value = match *name {
+                    "accept-language" => "en-US,en;q=0.9",
+                    "referer" => "https://example.com/",
+                    _ => "Mozilla/5.0",
+                };
+                (name.to_string(), value.to_string())
+            })
+            .collect();
+        self.user = Some("user".to_string());
+        self
+    }
+}
+
+/// ExpandError describes why a destination link could not be expanded to a URL.
+#[derive(Debug)]
+pub enum ExpandError {
+    Template(TemplateError),
+    Render(RenderError),
+    Url { rendered: String, error: url::ParseError },
+    Sandbox(String),
+}
+
+impl std::fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpandError::Template(e) => write!(f, "invalid template: {}", e),
+            ExpandError::Render(e) => write!(f, "template failed to render: {}", e),
+            ExpandError::Url { rendered, error } => write!(f, "\"{}\" is not a valid URL: {}", rendered, error),
+            ExpandError::Sandbox(reason) => write!(f, "template not allowed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ExpandError {}
+
+impl From<TemplateError> for ExpandError {
+    fn from(e: TemplateError) -> Self {
+        ExpandError::Template(e)
+    }
+}
+
+impl From<RenderError> for ExpandError {
+    fn from(e: RenderError) -> Self {
+        ExpandError::Render(e)
+    }
+}
+
+/// CompiledLink is a destination link that has been checked and compiled, ready to be expanded.
+pub(crate) enum CompiledLink {
+    // a link with no template expressions, which has the path appended without invoking handlebars
+    Plain(String),
+    Template { template: Template, usage: QueryUsage },
+}
+
+impl CompiledLink {
+    /// Returns true if the expanded link reads the query parameter itself, so it
+    /// should not be appended to the URL.
+    pub(crate) fn consumes(&self, key: &str) -> bool {
+        match self {
+            CompiledLink::Plain(_) => false,
+            CompiledLink::Template { usage, .. } => usage.consumes(key),
+        }
+    }
+}
+
+/// TemplateCache holds the compiled destination link of each link by its
+/// normalized id, so that redirects do not parse the link again.
+#[derive(Default)]
+pub(crate) struct TemplateCache {
+    entries: RwLock<HashMap<String, (String, Arc<CompiledLink>)>>,
+    compiles: AtomicUsize,
+}
+
+impl TemplateCache {
+    /// Returns the compiled destination link for the short name, compiling it if
+    /// it is not cached or the cached entry was compiled from a different link.
+    pub(crate) fn get(&self, short: &str, long: &str) -> Result<Arc<CompiledLink>, ExpandError> {
+        let id = model::normalized_id(short);
+        if let Some((cached_long, compiled)) = self.entries.read().unwrap().get(&id)
+            && cached_long == long
+        {
+            return Ok(compiled.clone());
+        }
+        let compiled = Arc::new(self.compile(long)?);
+        self.entries
+            .write()
+            .unwrap()
+            .insert(id, (long.to_string(), compiled.clone()));
+        Ok(compiled)
+    }
+
+    /// Compiles the destination link without caching it.
+    pub(crate) fn compile(&self, long: &str) -> Result<CompiledLink, ExpandError> {
+        if !long.contains("{{") {
+            return Ok(CompiledLink::Plain(long.to_string()));
+        }
+        self.compiles.fetch_add(1, Ordering::Relaxed);
+        let mut template = Template::compile(long)?;
+        check_sandbox(&template)?;
+        let usage = QueryUsage::of(&template);
+        let check = Template::compile(&format!("{{{{{DEADLINE_HELPER}}}}}"))?
+            .elements
+            .remove(0);
+        add_deadline_checks(&mut template.elements, &check);
+        Ok(CompiledLink::Template { template, usage })
+    }
+
+    pub(crate) fn invalidate(&self, short: &str) {
+        self.entries.write().unwrap().remove(&model::normalized_id(short));
+    }
+
+    /// The number of templates compiled by handlebars so far.
+    pub(crate) fn compiles(&self) -> usize {
+        self.compiles.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn stats(&self) -> model::TemplateStats {
+        model::TemplateStats {
+            cached: self.entries.read().unwrap().len(),
+            compiles: self.compiles(),
+        }
+    }
+}
+
+/// Checks that a destination link template only uses expressions and helpers,
+/// since partials and decorators could define or pull in other templates.
+pub(crate) fn check_sandbox(template: &Template) -> Result<(), ExpandError> {
+    check_elements(&template.elements)
+}
+
+fn check_elements(elements: &[TemplateElement]) -> Result<(), ExpandError> {
+    for element in elements {
+        match element {
+            TemplateElement::HelperBlock(helper) => {
+                if let Some(template) = &helper.template {
+                    check_elements(&template.elements)?;
+                }
+                if let Some(inverse) = &helper.inverse {
+                    check_elements(&inverse.elements)?;
+                }
+            }
+            TemplateElement::PartialExpression(_) | TemplateElement::PartialBlock(_) => {
+                return Err(ExpandError::Sandbox("partials are not allowed".to_string()));
+            }
+            TemplateElement::DecoratorExpression(_) | TemplateElement::DecoratorBlock(_) => {
+                return Err(ExpandError::Sandbox(
+                    "decorators and inline partials are not allowed".to_string(),
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// appends the check to every block, so that loops cannot run on without writing
+fn add_deadline_checks(elements: &mut [TemplateElement], check: &TemplateElement) {
+    for element in elements {
+        if let TemplateElement::HelperBlock(helper) = element {
+            for template in [&mut helper.template, &mut helper.inverse].into_iter().flatten() {
+                add_deadline_checks(&mut template.elements, check);
+                template.elements.push(check.clone());
+            }
+        }
+    }
+}
+
+/// Writes nothing, which has a BoundedOutput check whether the render has run past its deadline.
+pub(crate) fn check_deadline(
+    _: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    out.write("").map_err(RenderError::from)
+}
+
+/// BoundedOutput collects rendered output, failing the render once the output
+/// grows past a length limit or rendering runs past a deadline.
+pub(crate) struct BoundedOutput {
+    buffer: String,
+    limit: usize,
+    timeout: Duration,
+    deadline: Instant,
+    exceeded: Option<String>,
+}
+
+impl BoundedOutput {
+    pub(crate) fn new(limit: usize, timeout: Duration) -> Self {
+        Self {
+            buffer: String::new(),
+            limit,
+            timeout,
+            deadline: Instant::now() + timeout,
+            exceeded: None,
+        }
+    }
+
+    /// Returns the output of a finished render, or why the render was stopped.
+    pub(crate) fn finish(self, rendered: Result<(), RenderError>) -> Result<String, ExpandError> {
+        match (self.exceeded, rendered) {
+            (Some(reason), _) => Err(ExpandError::Sandbox(reason)),
+            (None, Err(e)) => Err(ExpandError::Render(e)),
+            (None, Ok(())) => Ok(self.buffer),
+        }
+    }
+
+    fn exceed(&mut self, reason: String) -> std::io::Error {
+        let e = std::io::Error::other(reason.clone());
+        self.exceeded = Some(reason);
+        e
+    }
+}
+
+impl Output for BoundedOutput {
+    fn write(&mut self, seg: &str) -> Result<(), std::io::Error> {
+        if self.buffer.len() + seg.len() > self.limit {
+            return Err(self.exceed(format!("output is longer than {} bytes", self.limit)));
+        }
+        if Instant::now() > self.deadline {
+            return Err(self.exceed(format!("rendering took longer than {:?}", self.timeout)));
+        }
+        self.buffer.push_str(seg);
+        Ok(())
+    }
+}
+
+/// QueryUsage records which query parameters a template reads, so that they are
+/// not appended to the expanded URL a second time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct QueryUsage {
+    all: bool,
+    keys: HashSet<String>,
+}
+
+impl QueryUsage {
+    pub(crate) fn consumes(&self, key: &str) -> bool {
+        self.all || self.keys.contains(key)
+    }
+
+    pub(crate) fn of(template: &Template) -> Self {
+        let mut usage = Self::default();
+        usage.visit_elements(&template.elements);
+        usage
+    }
+
+    fn visit_elements(&mut self, elements: &[TemplateElement]) {
+        for element in elements {
+            match element {
+                TemplateElement::Expression(helper)
+                | TemplateElement::HtmlExpression(helper)
+                | TemplateElement::HelperBlock(helper) => self.visit_helper(helper),
+                TemplateElement::DecoratorExpression(decorator)
+                | TemplateElement::DecoratorBlock(decorator)
+                | TemplateElement::PartialExpression(decorator)
+                | TemplateElement::PartialBlock(decorator) => {
+                    decorator
+                        .params
+                        .iter()
+                        .chain(decorator.hash.values())
+                        .for_each(|param| self.visit_parameter(param));
+                    if let Some(template) = &decorator.template {
+                        self.visit_elements(&template.elements);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn visit_helper(&mut self, helper: &HelperTemplate) {
+        // `lookup query "key"` only reads the one key
+        let params = match (&helper.name, helper.params.as_slice()) {
+            (
+                Parameter::Name(name),
+                [
+                    Parameter::Path(path),
+                    Parameter::Literal(JsonValue::String(key)),
+                    rest @ ..,
+                ],
+            ) if name == "lookup" && Self::query_reference(path) == Some(None) => {
+                self.keys.insert(key.clone());
+                rest
+            }
+            _ => {
+                self.visit_parameter(&helper.name);
+                helper.params.as_slice()
+            }
+        };
+        params
+            .iter()
+            .chain(helper.hash.values())
+            .for_each(|param| self.visit_parameter(param));
+        if let Some(template) = &helper.template {
+            self.visit_elements(&template.elements);
+        }
+        if let Some(inverse) = &helper.inverse {
+            self.visit_elements(&inverse.elements);
+        }
+    }
+
+    fn visit_parameter(&mut self, param: &Parameter) {
+        let reference = match param {
+            Parameter::Name(name) if name == QUERY_FIELD => Some(None),
+            Parameter::Path(path) => Self::query_reference(path),
+            Parameter::Subexpression(subexpression) => {
+                self.visit_elements(std::slice::from_ref(subexpression.element.as_ref()));
+                None
+            }
+            _ => None,
+        };
+        match reference {
+            Some(Some(key)) => {
+                self.keys.insert(key);
+            }
+            Some(None) => self.all = true,
+            None => {}
+        }
+    }
+
+    // Some(Some(key)) for `query.key`, Some(None) for `query` itself, None otherwise.
+    fn query_reference(path: &Path) -> Option<Option<String>> {
+        let Path::Relative((segments, _)) = path else {
+            return None;
+        };
+        let mut names = segments
+            .iter()
+            .skip_while(|segment| matches!(segment, PathSeg::Ruled(_)));
+        match names.next() {
+            Some(PathSeg::Named(name)) if name == QUERY_FIELD => match names.next() {
+                Some(PathSeg::Named(key)) => Some(Some(key.clone())),
+                _ => Some(None),
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(template: &str) -> QueryUsage {
+        QueryUsage::of(&Template::compile(template).unwrap())
+    }
+
+    #[test]
+    fn test_context_segments() {
+        let context = TemplateContext::new("who", "/amelie/profile/", HashMap::new());
+        assert_eq!(context.path, "amelie/profile/");
+        assert_eq!(context.segments, vec!["amelie".to_string(), "profile".to_string()]);
+    }
+
+    #[test]
+    fn test_template_cache() {
+        let cache = TemplateCache::default();
+        assert!(matches!(
+            *cache.get("nyt", "http://www.nytimes.com").unwrap(),
+            CompiledLink::Plain(_)
+        ));
+        assert_eq!(cache.compiles(), 0);
+
+        let long = "https://www.google.com/{{#if path}}search?q={{query_escape path}}{{/if}}";
+        cache.get("search", long).unwrap();
+        cache.get("Search", long).unwrap();
+        assert_eq!(cache.compiles(), 1);
+
+        // a changed link is compiled again even without being invalidated
+        cache.get("search", "https://www.bing.com/{{path}}").unwrap();
+        assert_eq!(cache.compiles(), 2);
+        cache.invalidate("search");
+        cache.get("search", "https://www.bing.com/{{path}}").unwrap();
+        assert_eq!(cache.compiles(), 3);
+
+        assert!(cache.get("broken", "{{#if path}}").is_err());
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let check = |template| check_sandbox(&Template::compile(template).unwrap());
+        assert!(check("https://host/{{#if path}}{{#each segments}}{{this}}{{/each}}{{/if}}").is_ok());
+        assert!(check("https://host/{{> base}}").is_err());
+        assert!(check("https://host/{{#if path}}{{> base}}{{/if}}").is_err());
+        assert!(check("{{#*inline \"url\"}}https://host/{{/inline}}").is_err());
+    }
+
+    #[test]
+    fn test_bounded_output() {
+        let mut output = BoundedOutput::new(8, Duration::from_secs(1));
+        assert!(output.write("https://").is_ok());
+        assert!(output.write("h").is_err());
+        assert!(matches!(output.finish(Ok(())), Err(ExpandError::Sandbox(_))));
+
+        let mut output = BoundedOutput::new(8, Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(output.write("h").is_err());
+
+        let mut output = BoundedOutput::new(8, Duration::from_secs(1));
+        output.write("https://").unwrap();
+        assert_eq!(output.finish(Ok(())).unwrap(), "https://");
+    }
+
+    #[test]
+    fn test_query_usage_none() {
+        let usage = usage("https://www.google.com/{{#if path}}search?q={{query_escape path}}{{/if}}");
+        assert!(!usage.consumes("q"));
+    }
+
+    #[test]
+    fn test_query_usage_keys() {
+        let usage = usage(r#"https://host/?a={{query.a}}{{#if (match "x" query.b)}}{{/if}}&c={{lookup query "c"}}"#);
+        assert!(usage.consumes("a"));
+        assert!(usage.consumes("b"));
+        assert!(usage.consumes("c"));
+        assert!(!usage.consumes("d"));
+    }
+
+    #[test]
+    fn test_query_usage_all() {
+        let usage = usage("https://host/?{{#each query}}{{@key}}={{this}}&{{/each}}");
+        assert!(usage.consumes("anything"));
+    }
+}